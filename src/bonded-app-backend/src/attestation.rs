@@ -0,0 +1,395 @@
+use crate::evidence_index::index_evidence;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use candid::Principal;
+use ed25519_dalek::VerifyingKey;
+use ic_cdk_macros::{query, update};
+
+// =========================
+// EVIDENCE ATTESTATION
+// =========================
+// Each partner registers an Ed25519 signing key per device with the
+// relationship. The uploader signs (relationship ID, evidence hash, metadata)
+// to attest an item; the other partner can then countersign the same fields
+// to confirm it ("I confirm this happened"). Signing and countersigning use
+// distinct domain separators so one can never be replayed as the other.
+//
+// Keys are never deleted, only revoked, so the key set that was valid when
+// a signature was made stays available to verify it later.
+
+const MAX_ACTIVE_SIGNING_KEYS_PER_PARTNER: usize = 10;
+
+const EVIDENCE_SIGNATURE_DOMAIN: &str = "bonded:evidence:sign:v1";
+const EVIDENCE_COUNTERSIGNATURE_DOMAIN: &str = "bonded:evidence:countersign:v1";
+
+fn push_field(message: &mut Vec<u8>, field: &[u8]) {
+    message.extend_from_slice(&(field.len() as u64).to_be_bytes());
+    message.extend_from_slice(field);
+}
+
+/// Canonical, length-prefixed encoding of the attested fields
+fn attestation_message(domain: &str, relationship_id: &str, hash: &str, metadata: &EvidenceMetadata) -> Vec<u8> {
+    let mut message = format!("{}\n", domain).into_bytes();
+    push_field(&mut message, relationship_id.as_bytes());
+    push_field(&mut message, hash.as_bytes());
+    message.extend_from_slice(&encode_evidence_metadata(metadata));
+    message
+}
+
+/// The exact bytes the uploader signs
+pub fn evidence_signature_message(relationship_id: &str, hash: &str, metadata: &EvidenceMetadata) -> Vec<u8> {
+    attestation_message(EVIDENCE_SIGNATURE_DOMAIN, relationship_id, hash, metadata)
+}
+
+/// The exact bytes the other partner signs to confirm an item
+pub fn evidence_countersignature_message(relationship_id: &str, hash: &str, metadata: &EvidenceMetadata) -> Vec<u8> {
+    attestation_message(EVIDENCE_COUNTERSIGNATURE_DOMAIN, relationship_id, hash, metadata)
+}
+
+pub fn attestation_state(evidence: &Evidence) -> AttestationState {
+    match (&evidence.signature, &evidence.countersignature) {
+        (Some(_), Some(_)) => AttestationState::Countersigned,
+        (Some(_), None) => AttestationState::Signed,
+        (None, _) => AttestationState::Unsigned,
+    }
+}
+
+fn key_active_at(activated_at: u64, revoked_at: Option<u64>, at: u64) -> bool {
+    activated_at <= at && revoked_at.is_none_or(|revoked_at| at < revoked_at)
+}
+
+/// Signing keys valid at `at`, across both partners
+pub fn signing_keys_at(relationship: &Relationship, at: u64) -> Vec<PartnerSigningKey> {
+    relationship
+        .signing_keys
+        .iter()
+        .filter(|key| key_active_at(key.registered_at, key.revoked_at, at))
+        .cloned()
+        .collect()
+}
+
+pub fn relationship_key_at(relationship: &Relationship, at: u64) -> Option<RelationshipKey> {
+    relationship
+        .relationship_keys
+        .iter()
+        .rev()
+        .find(|key| key_active_at(key.activated_at, key.revoked_at, at))
+        .cloned()
+}
+
+/// Check `signature` over `message` against relationship key `key_version`
+pub fn verify_relationship_signature(
+    relationship: &Relationship,
+    key_version: u32,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), BondedError> {
+    let Some(key) = relationship.relationship_keys.iter().find(|key| key.version == key_version) else {
+        return Err(BondedError::invalid_input(
+            "signature",
+            format!("Relationship has no public key for key version {}", key_version),
+        ));
+    };
+
+    match verify_signature(&key.public_key, message, signature) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(BondedError::unauthorized("Invalid evidence signature")),
+        Err(e) => Err(BondedError::invalid_input("signature", format!("Key version {}: {}", key_version, e))),
+    }
+}
+
+/// Check `signature` over `message` against the keys `partner` had active at `at`
+pub fn verify_partner_signature(
+    relationship: &Relationship,
+    partner: Principal,
+    message: &[u8],
+    signature: &[u8],
+    at: u64,
+) -> Result<(), BondedError> {
+    let signing_keys: Vec<PartnerSigningKey> = signing_keys_at(relationship, at)
+        .into_iter()
+        .filter(|key| key.partner == partner)
+        .collect();
+    if signing_keys.is_empty() {
+        return Err(BondedError::not_found("Signing key"));
+    }
+
+    for signing_key in &signing_keys {
+        match verify_signature(&signing_key.public_key, message, signature) {
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(e) => return Err(BondedError::invalid_input("signature", e)),
+        }
+    }
+
+    Err(BondedError::unauthorized("Invalid evidence signature"))
+}
+
+/// Load evidence and its relationship for a partner of that relationship
+fn accessible_evidence(evidence_id: &str, caller: Principal) -> Result<(Evidence, Relationship), BondedError> {
+    let evidence = match with_evidence_store_read(|store| store.get(&evidence_id.to_string())) {
+        Some(ev) => ev,
+        None => return Err(BondedError::not_found("Evidence")),
+    };
+
+    let relationship = match with_relationship_store_read(|store| store.get(&evidence.relationship_id)) {
+        Some(rel) => rel,
+        None => return Err(BondedError::not_found("Relationship")),
+    };
+
+    verify_relationship_access(&relationship, caller)?;
+
+    Ok((evidence, relationship))
+}
+
+fn store_attested_evidence(evidence: Evidence) -> EvidenceSummary {
+    index_evidence(&evidence);
+    with_evidence_store(|store| {
+        store.insert(evidence.id.clone(), evidence.clone());
+    });
+    crate::downloads::summarize_evidence(evidence)
+}
+
+// =========================
+// KEY REGISTRY
+// =========================
+
+/// Register a device's signing key; partners may have several active devices
+#[update]
+pub fn register_signing_key(
+    relationship_id: String,
+    public_key: Vec<u8>,
+    device_name: Option<String>,
+) -> BondedResult<PartnerSigningKey> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "register_signing_key") {
+        return BondedResult::err(msg);
+    }
+
+    let mut relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let key_bytes: [u8; 32] = match public_key.as_slice().try_into() {
+        Ok(bytes) => bytes,
+        Err(_) => return BondedResult::err(BondedError::invalid_input("public_key", "Signing key must be exactly 32 bytes")),
+    };
+    if VerifyingKey::from_bytes(&key_bytes).is_err() {
+        return BondedResult::err(BondedError::invalid_input("public_key", "Signing key is not a valid Ed25519 key"));
+    }
+
+    // Keys are never reused, even after revocation, so history stays unambiguous
+    if relationship.signing_keys.iter().any(|key| key.public_key == public_key) {
+        return BondedResult::err(BondedError::conflict("Signing key already registered for this relationship"));
+    }
+
+    let now = current_time();
+    let active_keys = signing_keys_at(&relationship, now).iter().filter(|key| key.partner == caller).count();
+    if active_keys >= MAX_ACTIVE_SIGNING_KEYS_PER_PARTNER {
+        return BondedResult::err(BondedError::quota_exceeded(
+            "Active signing keys",
+            MAX_ACTIVE_SIGNING_KEYS_PER_PARTNER as u64,
+        ));
+    }
+
+    let signing_key = PartnerSigningKey {
+        partner: caller,
+        public_key,
+        registered_at: now,
+        revoked_at: None,
+        device_name,
+    };
+
+    relationship.signing_keys.push(signing_key.clone());
+    relationship.last_activity = current_time();
+    with_relationship_store(|store| {
+        store.insert(relationship_id.clone(), relationship);
+    });
+
+    log_relationship_audit_event(caller, &relationship_id, "register_signing_key", None);
+
+    BondedResult::ok(signing_key)
+}
+
+/// Revoke one of the caller's device keys; signatures it made before now
+/// remain verifiable
+#[update]
+pub fn revoke_signing_key(relationship_id: String, public_key: Vec<u8>) -> BondedResult<PartnerSigningKey> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "revoke_signing_key") {
+        return BondedResult::err(msg);
+    }
+
+    let mut relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let now = current_time();
+    let signing_key = match relationship
+        .signing_keys
+        .iter_mut()
+        .find(|key| key.partner == caller && key.public_key == public_key)
+    {
+        Some(key) => key,
+        None => return BondedResult::err(BondedError::not_found("Signing key")),
+    };
+
+    if signing_key.revoked_at.is_some() {
+        return BondedResult::err(BondedError::conflict("Signing key is already revoked"));
+    }
+
+    signing_key.revoked_at = Some(now);
+    let revoked = signing_key.clone();
+
+    relationship.last_activity = now;
+    with_relationship_store(|store| {
+        store.insert(relationship_id.clone(), relationship);
+    });
+
+    log_relationship_audit_event(caller, &relationship_id, "revoke_signing_key", None);
+
+    BondedResult::ok(revoked)
+}
+
+/// The relationship key and device signing keys valid at `at` (default: now).
+/// Verify a signature with the key set at its `signed_at`.
+#[query]
+pub fn get_relationship_keys(relationship_id: String, at: Option<u64>) -> BondedResult<RelationshipKeySet> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let at = at.unwrap_or_else(current_time);
+    BondedResult::ok(RelationshipKeySet {
+        relationship_key: relationship_key_at(&relationship, at),
+        signing_keys: signing_keys_at(&relationship, at),
+        relationship_id,
+        at,
+    })
+}
+
+// =========================
+// ATTESTATION ENDPOINTS
+// =========================
+
+#[query]
+pub fn get_evidence_signing_messages(evidence_id: String) -> BondedResult<EvidenceSigningMessages> {
+    let caller = caller_principal();
+
+    let (evidence, _) = match accessible_evidence(&evidence_id, caller) {
+        Ok(found) => found,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    BondedResult::ok(EvidenceSigningMessages {
+        signature_message: evidence_signature_message(&evidence.relationship_id, &evidence.hash, &evidence.metadata),
+        countersignature_message: evidence_countersignature_message(
+            &evidence.relationship_id,
+            &evidence.hash,
+            &evidence.metadata,
+        ),
+        evidence_id,
+    })
+}
+
+/// Attach the uploader's signature to an item uploaded without one
+#[update]
+pub fn sign_evidence(evidence_id: String, signature: Vec<u8>) -> BondedResult<EvidenceSummary> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "sign_evidence") {
+        return BondedResult::err(msg);
+    }
+
+    let (mut evidence, relationship) = match accessible_evidence(&evidence_id, caller) {
+        Ok(found) => found,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    if evidence.uploader != caller {
+        return BondedResult::err(BondedError::unauthorized("Only the uploader can sign evidence"));
+    }
+
+    if evidence.signature.is_some() {
+        return BondedResult::err(BondedError::conflict("Evidence is already signed"));
+    }
+
+    let now = current_time();
+    let message = evidence_signature_message(&evidence.relationship_id, &evidence.hash, &evidence.metadata);
+    if let Err(msg) = verify_partner_signature(&relationship, caller, &message, &signature, now) {
+        return BondedResult::err(msg);
+    }
+
+    evidence.signature = Some(signature);
+    evidence.signed_at = Some(now);
+    let summary = store_attested_evidence(evidence);
+
+    log_relationship_audit_event(caller, &relationship.id, "sign_evidence", Some(evidence_id));
+
+    BondedResult::ok(summary)
+}
+
+/// The other partner confirms a signed item
+#[update]
+pub fn countersign_evidence(evidence_id: String, signature: Vec<u8>) -> BondedResult<EvidenceSummary> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "countersign_evidence") {
+        return BondedResult::err(msg);
+    }
+
+    let (mut evidence, relationship) = match accessible_evidence(&evidence_id, caller) {
+        Ok(found) => found,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    if evidence.uploader == caller {
+        return BondedResult::err(BondedError::unauthorized("Only the other partner can countersign evidence"));
+    }
+
+    if evidence.signature.is_none() {
+        return BondedResult::err(BondedError::conflict("Evidence must be signed by the uploader before it can be countersigned"));
+    }
+
+    if evidence.countersignature.is_some() {
+        return BondedResult::err(BondedError::conflict("Evidence is already countersigned"));
+    }
+
+    let now = current_time();
+    let message = evidence_countersignature_message(&evidence.relationship_id, &evidence.hash, &evidence.metadata);
+    if let Err(msg) = verify_partner_signature(&relationship, caller, &message, &signature, now) {
+        return BondedResult::err(msg);
+    }
+
+    evidence.countersignature = Some(EvidenceCountersignature {
+        partner: caller,
+        signature,
+        signed_at: now,
+    });
+    let summary = store_attested_evidence(evidence);
+
+    log_relationship_audit_event(caller, &relationship.id, "countersign_evidence", Some(evidence_id));
+
+    BondedResult::ok(summary)
+}
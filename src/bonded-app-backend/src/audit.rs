@@ -0,0 +1,319 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::Principal;
+use ic_cdk_macros::query;
+use sha2::{Digest, Sha256};
+
+// =======================
+// HASH-CHAINED AUDIT LOG
+// =======================
+// Entries are appended to stable memory keyed by a dense sequence number.
+// Each entry commits to the hash of its predecessor, so editing, removing or
+// truncating entries breaks the chain from that point on.
+
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 100;
+const MAX_VERIFY_BATCH: u32 = 1_000;
+
+/// Append an entry to the audit chain and index it by user and relationship
+pub fn append_audit_entry(
+    user: Principal,
+    relationship_id: Option<&str>,
+    action: &str,
+    metadata: Option<String>,
+) -> AuditLogEntry {
+    let (sequence, previous_hash) = with_audit_log_store_read(|store| match store.last_key_value() {
+        Some((last_sequence, last_entry)) => (last_sequence + 1, last_entry.entry_hash),
+        None => (0, GENESIS_HASH.to_string()),
+    });
+
+    let mut entry = AuditLogEntry {
+        id: generate_id("audit", sequence),
+        sequence,
+        user,
+        relationship_id: relationship_id.map(str::to_string),
+        action: action.to_string(),
+        timestamp: current_time(),
+        metadata,
+        previous_hash,
+        entry_hash: String::new(),
+    };
+    entry.entry_hash = compute_entry_hash(&entry);
+
+    with_audit_log_store(|store| {
+        store.insert(sequence, entry.clone());
+    });
+
+    with_audit_index_store(|index| {
+        index.insert(index_key(&user_scope(user), sequence), sequence);
+        if let Some(relationship_id) = relationship_id {
+            index.insert(index_key(&relationship_scope(relationship_id), sequence), sequence);
+        }
+    });
+
+    entry
+}
+
+/// SHA-256 over a length-prefixed encoding of every field except `entry_hash`
+fn compute_entry_hash(entry: &AuditLogEntry) -> String {
+    fn field(hasher: &mut Sha256, bytes: &[u8]) {
+        hasher.update((bytes.len() as u64).to_be_bytes());
+        hasher.update(bytes);
+    }
+
+    fn optional_field(hasher: &mut Sha256, value: Option<&str>) {
+        match value {
+            Some(value) => {
+                hasher.update([1u8]);
+                field(hasher, value.as_bytes());
+            }
+            None => hasher.update([0u8]),
+        }
+    }
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"bonded-audit-v1");
+    hasher.update(entry.sequence.to_be_bytes());
+    field(&mut hasher, entry.previous_hash.as_bytes());
+    field(&mut hasher, entry.id.as_bytes());
+    field(&mut hasher, entry.user.as_slice());
+    optional_field(&mut hasher, entry.relationship_id.as_deref());
+    field(&mut hasher, entry.action.as_bytes());
+    hasher.update(entry.timestamp.to_be_bytes());
+    optional_field(&mut hasher, entry.metadata.as_deref());
+    hex::encode(hasher.finalize())
+}
+
+fn user_scope(user: Principal) -> String {
+    format!("user:{}", user.to_text())
+}
+
+fn relationship_scope(relationship_id: &str) -> String {
+    format!("relationship:{}", relationship_id)
+}
+
+fn index_key(scope: &str, sequence: u64) -> String {
+    // Zero-padded so lexicographic order matches sequence order
+    format!("{}:{:020}", scope, sequence)
+}
+
+/// Newest-first page of entries in `scope`, strictly older than `before`
+fn page_for_scope(scope: &str, before: Option<u64>, limit: Option<u32>) -> AuditLogPage {
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as usize;
+    let start = format!("{}:", scope);
+    let end = match before {
+        Some(before) => index_key(scope, before),
+        None => format!("{};", scope), // ';' sorts directly after ':'
+    };
+
+    let mut sequences: Vec<u64> = with_audit_index_store_read(|index| {
+        index
+            .range(start..end)
+            .rev()
+            .take(limit + 1)
+            .map(|(_, sequence)| sequence)
+            .collect()
+    });
+
+    let has_more = sequences.len() > limit;
+    sequences.truncate(limit);
+
+    let entries: Vec<AuditLogEntry> = with_audit_log_store_read(|store| {
+        sequences.iter().filter_map(|sequence| store.get(sequence)).collect()
+    });
+
+    AuditLogPage {
+        next_before: if has_more { entries.last().map(|entry| entry.sequence) } else { None },
+        entries,
+    }
+}
+
+// =======================
+// AUDIT LOG QUERIES
+// =======================
+
+#[query]
+pub fn get_my_audit_log(before: Option<u64>, limit: Option<u32>) -> BondedResult<AuditLogPage> {
+    let caller = caller_principal();
+    BondedResult::ok(page_for_scope(&user_scope(caller), before, limit))
+}
+
+#[query]
+pub fn get_relationship_audit_log(
+    relationship_id: String,
+    before: Option<u64>,
+    limit: Option<u32>,
+) -> BondedResult<AuditLogPage> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    // Both partners see the full relationship-scoped history, even once the
+    // relationship has been destroyed
+    if let Err(msg) = verify_relationship_read_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    BondedResult::ok(page_for_scope(&relationship_scope(&relationship_id), before, limit))
+}
+
+#[query]
+pub fn get_audit_log_head() -> AuditLogHead {
+    with_audit_log_store_read(|store| match store.last_key_value() {
+        Some((sequence, entry)) => AuditLogHead {
+            length: sequence + 1,
+            head_hash: entry.entry_hash,
+        },
+        None => AuditLogHead {
+            length: 0,
+            head_hash: GENESIS_HASH.to_string(),
+        },
+    })
+}
+
+/// Re-derive hashes for a window of the chain and check every link.
+/// Clients that pinned an earlier head can use this to detect rewrites.
+#[query]
+pub fn verify_audit_chain(from_sequence: u64, limit: Option<u32>) -> AuditChainVerification {
+    let limit = limit.unwrap_or(MAX_VERIFY_BATCH).clamp(1, MAX_VERIFY_BATCH) as usize;
+
+    with_audit_log_store_read(|store| {
+        let previous = if from_sequence == 0 {
+            Some(GENESIS_HASH.to_string())
+        } else {
+            store.get(&(from_sequence - 1)).map(|entry| entry.entry_hash)
+        };
+
+        // A missing predecessor means the chain was truncated below this window
+        let Some(mut expected_previous) = previous else {
+            return AuditChainVerification {
+                checked_from: from_sequence,
+                checked_count: 0,
+                valid: false,
+                first_invalid_sequence: Some(from_sequence),
+            };
+        };
+
+        let mut checked_count = 0u64;
+        let mut expected_sequence = from_sequence;
+        let mut first_invalid_sequence = None;
+
+        for (sequence, entry) in store.range(from_sequence..).take(limit) {
+            let intact = sequence == expected_sequence
+                && entry.sequence == sequence
+                && entry.previous_hash == expected_previous
+                && compute_entry_hash(&entry) == entry.entry_hash;
+
+            if !intact {
+                first_invalid_sequence = Some(expected_sequence);
+                break;
+            }
+
+            expected_previous = entry.entry_hash;
+            expected_sequence = sequence + 1;
+            checked_count += 1;
+        }
+
+        AuditChainVerification {
+            checked_from: from_sequence,
+            checked_count,
+            valid: first_invalid_sequence.is_none(),
+            first_invalid_sequence,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Write a chain of `length` entries directly, since `append_audit_entry`
+    /// reads the canister clock
+    fn seed_chain(length: u64) {
+        let mut previous_hash = GENESIS_HASH.to_string();
+        for sequence in 0..length {
+            let mut entry = AuditLogEntry {
+                id: format!("audit_{}", sequence),
+                sequence,
+                user: Principal::from_slice(&[7; 29]),
+                relationship_id: Some("relationship_1".to_string()),
+                action: "upload_evidence".to_string(),
+                timestamp: 1_700_000_000_000_000_000 + sequence,
+                metadata: None,
+                previous_hash,
+                entry_hash: String::new(),
+            };
+            entry.entry_hash = compute_entry_hash(&entry);
+            previous_hash = entry.entry_hash.clone();
+            with_audit_log_store(|store| {
+                store.insert(sequence, entry);
+            });
+        }
+    }
+
+    fn edit_entry(sequence: u64, edit: impl FnOnce(&mut AuditLogEntry)) {
+        with_audit_log_store(|store| {
+            let mut entry = store.get(&sequence).unwrap();
+            edit(&mut entry);
+            store.insert(sequence, entry);
+        });
+    }
+
+    #[test]
+    fn intact_chain_verifies() {
+        seed_chain(4);
+
+        let result = verify_audit_chain(0, None);
+        assert!(result.valid);
+        assert_eq!(result.checked_count, 4);
+        assert_eq!(result.first_invalid_sequence, None);
+    }
+
+    #[test]
+    fn edited_entry_is_detected() {
+        seed_chain(4);
+        edit_entry(2, |entry| entry.metadata = Some("rewritten".to_string()));
+
+        let result = verify_audit_chain(0, None);
+        assert!(!result.valid);
+        assert_eq!(result.checked_count, 2);
+        assert_eq!(result.first_invalid_sequence, Some(2));
+    }
+
+    #[test]
+    fn rehashed_edit_breaks_the_next_link() {
+        seed_chain(4);
+        edit_entry(2, |entry| {
+            entry.action = "delete_evidence".to_string();
+            entry.entry_hash = compute_entry_hash(entry);
+        });
+
+        let result = verify_audit_chain(0, None);
+        assert!(!result.valid);
+        assert_eq!(result.first_invalid_sequence, Some(3));
+    }
+
+    #[test]
+    fn removed_entry_is_detected() {
+        seed_chain(4);
+        with_audit_log_store(|store| {
+            store.remove(&1);
+        });
+
+        let result = verify_audit_chain(0, None);
+        assert!(!result.valid);
+        assert_eq!(result.checked_count, 1);
+        assert_eq!(result.first_invalid_sequence, Some(1));
+
+        // A window starting right after the gap has no predecessor to link to
+        let result = verify_audit_chain(2, None);
+        assert!(!result.valid);
+        assert_eq!(result.checked_count, 0);
+        assert_eq!(result.first_invalid_sequence, Some(2));
+    }
+}
@@ -1,6 +1,8 @@
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use crate::quotas::{record_size, release_user_usage, reserve_user_storage, UsageStore};
 use candid::Principal;
 use ic_cdk_macros::{query, update};
 
@@ -8,9 +10,13 @@ use ic_cdk_macros::{query, update};
 // TIMELINE DATA STORAGE
 // =======================
 
-#[update]
+#[update(name = "save_timeline_data_v2")]
 pub fn save_timeline_data(timeline_items: Vec<String>) -> BondedResult<String> {
     let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "save_timeline_data") {
+        return BondedResult::err(msg);
+    }
     let timeline_id = format!("timeline_{}", caller.to_text());
     
     let timeline_data = TimelineData {
@@ -20,6 +26,11 @@ pub fn save_timeline_data(timeline_items: Vec<String>) -> BondedResult<String> {
         updated_at: current_time(),
     };
     
+    let previous = with_timeline_store_read(|store| store.get(&timeline_id)).map(|previous| record_size(&previous));
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::TimelineData, &timeline_id, previous, record_size(&timeline_data)) {
+        return BondedResult::err(msg);
+    }
+    
     with_timeline_store(|store| {
         store.insert(timeline_id.clone(), timeline_data);
     });
@@ -30,7 +41,7 @@ pub fn save_timeline_data(timeline_items: Vec<String>) -> BondedResult<String> {
     BondedResult::ok("Timeline data saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_timeline_data_v2")]
 pub fn get_timeline_data() -> BondedResult<Vec<String>> {
     let caller = caller_principal();
     let timeline_id = format!("timeline_{}", caller.to_text());
@@ -45,10 +56,14 @@ pub fn get_timeline_data() -> BondedResult<Vec<String>> {
 // FACE EMBEDDING STORAGE
 // =======================
 
-#[update]
+#[update(name = "save_face_embedding_v2")]
 pub fn save_face_embedding(embedding_data: Vec<f32>, partner_id: Option<Principal>) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_face_embedding") {
+        return BondedResult::err(msg);
+    }
+    
     let face_embedding = UserFaceEmbedding {
         user: caller,
         embedding_data,
@@ -57,6 +72,11 @@ pub fn save_face_embedding(embedding_data: Vec<f32>, partner_id: Option<Principa
         updated_at: current_time(),
     };
     
+    let previous = with_face_embedding_store_read(|store| store.get(&caller)).map(|previous| record_size(&previous));
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::FaceEmbeddings, &caller, previous, record_size(&face_embedding)) {
+        return BondedResult::err(msg);
+    }
+    
     with_face_embedding_store(|store| {
         store.insert(caller, face_embedding);
     });
@@ -67,17 +87,17 @@ pub fn save_face_embedding(embedding_data: Vec<f32>, partner_id: Option<Principa
     BondedResult::ok("Face embedding saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_face_embedding_v2")]
 pub fn get_face_embedding() -> BondedResult<UserFaceEmbedding> {
     let caller = caller_principal();
     
     match with_face_embedding_store_read(|store| store.get(&caller)) {
         Some(embedding) => BondedResult::ok(embedding),
-        None => BondedResult::err("Face embedding not found"),
+        None => BondedResult::err(BondedError::not_found("Face embedding")),
     }
 }
 
-#[query]
+#[query(name = "get_all_face_embeddings_v2")]
 pub fn get_all_face_embeddings() -> BondedResult<Vec<UserFaceEmbedding>> {
     let caller = caller_principal();
     
@@ -98,13 +118,17 @@ pub fn get_all_face_embeddings() -> BondedResult<Vec<UserFaceEmbedding>> {
     BondedResult::ok(embeddings)
 }
 
-#[update]
+#[update(name = "delete_face_embeddings_v2")]
 pub fn delete_face_embeddings() -> BondedResult<String> {
     let caller = caller_principal();
     
-    with_face_embedding_store(|store| {
-        store.remove(&caller);
-    });
+    if let Err(msg) = verify_update_allowed(caller, "delete_face_embeddings") {
+        return BondedResult::err(msg);
+    }
+    
+    if let Some(embedding) = with_face_embedding_store(|store| store.remove(&caller)) {
+        release_user_usage(caller, UsageStore::FaceEmbeddings, &caller, record_size(&embedding));
+    }
     
     // Log audit event
     log_audit_event(caller, "delete_face_embeddings", None);
@@ -116,16 +140,25 @@ pub fn delete_face_embeddings() -> BondedResult<String> {
 // AUTO SCANNER SETTINGS
 // =======================
 
-#[update]
+#[update(name = "save_auto_scanner_settings_v2")]
 pub fn save_auto_scanner_settings(settings_data: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_auto_scanner_settings") {
+        return BondedResult::err(msg);
+    }
+    
     let scanner_settings = AutoScannerSettings {
         user: caller,
         settings_data,
         updated_at: current_time(),
     };
     
+    let previous = with_auto_scanner_store_read(|store| store.get(&caller)).map(|previous| record_size(&previous));
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::AutoScanner, &caller, previous, record_size(&scanner_settings)) {
+        return BondedResult::err(msg);
+    }
+    
     with_auto_scanner_store(|store| {
         store.insert(caller, scanner_settings);
     });
@@ -136,7 +169,7 @@ pub fn save_auto_scanner_settings(settings_data: String) -> BondedResult<String>
     BondedResult::ok("Auto scanner settings saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_auto_scanner_settings_v2")]
 pub fn get_auto_scanner_settings() -> BondedResult<String> {
     let caller = caller_principal();
     
@@ -150,10 +183,14 @@ pub fn get_auto_scanner_settings() -> BondedResult<String> {
 // CAPTURE SETTINGS
 // =======================
 
-#[update]
+#[update(name = "save_capture_settings_v2")]
 pub fn save_capture_settings(settings_data: String, file_type_overrides: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_capture_settings") {
+        return BondedResult::err(msg);
+    }
+    
     let capture_settings = CaptureSettings {
         user: caller,
         settings_data,
@@ -161,6 +198,11 @@ pub fn save_capture_settings(settings_data: String, file_type_overrides: String)
         updated_at: current_time(),
     };
     
+    let previous = with_capture_settings_store_read(|store| store.get(&caller)).map(|previous| record_size(&previous));
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::CaptureSettings, &caller, previous, record_size(&capture_settings)) {
+        return BondedResult::err(msg);
+    }
+    
     with_capture_settings_store(|store| {
         store.insert(caller, capture_settings);
     });
@@ -171,7 +213,7 @@ pub fn save_capture_settings(settings_data: String, file_type_overrides: String)
     BondedResult::ok("Capture settings saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_capture_settings_v2")]
 pub fn get_capture_settings() -> BondedResult<(String, String)> {
     let caller = caller_principal();
     
@@ -185,9 +227,13 @@ pub fn get_capture_settings() -> BondedResult<(String, String)> {
 // EMAIL LOG STORAGE
 // =======================
 
-#[update]
+#[update(name = "save_email_log_v2")]
 pub fn save_email_log(log_data: String) -> BondedResult<String> {
     let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "save_email_log") {
+        return BondedResult::err(msg);
+    }
     let log_id = format!("email_log_{}_{}", caller.to_text(), current_time());
     
     let email_log = EmailLog {
@@ -195,8 +241,13 @@ pub fn save_email_log(log_data: String) -> BondedResult<String> {
         user: caller,
         log_data,
         created_at: current_time(),
+        delivery: None,
     };
     
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::EmailLogs, &log_id, None, record_size(&email_log)) {
+        return BondedResult::err(msg);
+    }
+    
     with_email_log_store(|store| {
         store.insert(log_id.clone(), email_log);
     });
@@ -207,7 +258,7 @@ pub fn save_email_log(log_data: String) -> BondedResult<String> {
     BondedResult::ok(log_id)
 }
 
-#[query]
+#[query(name = "get_email_logs_v2")]
 pub fn get_email_logs() -> BondedResult<Vec<EmailLog>> {
     let caller = caller_principal();
     
@@ -231,10 +282,14 @@ pub fn get_email_logs() -> BondedResult<Vec<EmailLog>> {
 // GEOLOCATION CACHE
 // =======================
 
-#[update]
+#[update(name = "save_geo_cache_v2")]
 pub fn save_geo_cache(cache_key: String, cache_data: String, expires_at: u64) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_geo_cache") {
+        return BondedResult::err(msg);
+    }
+    
     let geo_cache = GeolocationCache {
         cache_key: cache_key.clone(),
         user: Some(caller),
@@ -243,6 +298,19 @@ pub fn save_geo_cache(cache_key: String, cache_data: String, expires_at: u64) ->
         created_at: current_time(),
     };
     
+    // The cache is shared: an entry written by someone else is taken over
+    let previous = with_geo_cache_store_read(|store| store.get(&cache_key));
+    let replaced = match &previous {
+        Some(previous) if previous.user == Some(caller) => Some(record_size(previous)),
+        _ => None,
+    };
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::GeoCache, &cache_key, replaced, record_size(&geo_cache)) {
+        return BondedResult::err(msg);
+    }
+    if let Some(previous) = previous.filter(|previous| previous.user != Some(caller)) {
+        release_geo_cache_entry(&previous);
+    }
+    
     with_geo_cache_store(|store| {
         store.insert(cache_key.clone(), geo_cache);
     });
@@ -250,7 +318,7 @@ pub fn save_geo_cache(cache_key: String, cache_data: String, expires_at: u64) ->
     BondedResult::ok("Geo cache saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_geo_cache_v2")]
 pub fn get_geo_cache(cache_key: String) -> BondedResult<String> {
     match with_geo_cache_store_read(|store| store.get(&cache_key)) {
         Some(cache) => {
@@ -258,15 +326,25 @@ pub fn get_geo_cache(cache_key: String) -> BondedResult<String> {
             if cache.expires_at > current_time() {
                 BondedResult::ok(cache.cache_data)
             } else {
-                BondedResult::err("Cache expired")
+                BondedResult::err(BondedError::expired("Cache"))
             }
         },
-        None => BondedResult::err("Cache not found"),
+        None => BondedResult::err(BondedError::not_found("Cache")),
+    }
+}
+
+fn release_geo_cache_entry(cache: &GeolocationCache) {
+    if let Some(user) = cache.user {
+        release_user_usage(user, UsageStore::GeoCache, &cache.cache_key, record_size(cache));
     }
 }
 
-#[update]
+#[update(name = "cleanup_expired_geo_cache_v2")]
 pub fn cleanup_expired_geo_cache() -> BondedResult<String> {
+    if let Err(msg) = verify_update_allowed(caller_principal(), "cleanup_expired_geo_cache") {
+        return BondedResult::err(msg);
+    }
+    
     let current_time = current_time();
     let mut removed_count = 0;
     
@@ -285,7 +363,9 @@ pub fn cleanup_expired_geo_cache() -> BondedResult<String> {
     
     with_geo_cache_store(|store| {
         for key in expired_keys {
-            store.remove(&key);
+            if let Some(cache) = store.remove(&key) {
+                release_geo_cache_entry(&cache);
+            }
             removed_count += 1;
         }
     });
@@ -297,16 +377,25 @@ pub fn cleanup_expired_geo_cache() -> BondedResult<String> {
 // SCHEDULER SETTINGS
 // =======================
 
-#[update]
+#[update(name = "save_scheduler_settings_v2")]
 pub fn save_scheduler_settings(settings_data: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_scheduler_settings") {
+        return BondedResult::err(msg);
+    }
+    
     let scheduler_settings = SchedulerSettings {
         user: caller,
         settings_data,
         updated_at: current_time(),
     };
     
+    let previous = with_scheduler_store_read(|store| store.get(&caller)).map(|previous| record_size(&previous));
+    if let Err(msg) = reserve_user_storage(caller, UsageStore::Scheduler, &caller, previous, record_size(&scheduler_settings)) {
+        return BondedResult::err(msg);
+    }
+    
     with_scheduler_store(|store| {
         store.insert(caller, scheduler_settings);
     });
@@ -317,7 +406,7 @@ pub fn save_scheduler_settings(settings_data: String) -> BondedResult<String> {
     BondedResult::ok("Scheduler settings saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_scheduler_settings_v2")]
 pub fn get_scheduler_settings() -> BondedResult<String> {
     let caller = caller_principal();
     
@@ -331,7 +420,18 @@ pub fn get_scheduler_settings() -> BondedResult<String> {
 // PROCESSED CONTENT STORAGE
 // =======================
 
-#[update]
+/// Check the caller's quota and charge it for storing `content`; the ID must
+/// be free or already the caller's
+fn reserve_content(caller: Principal, content: &ProcessedContent) -> Result<(), BondedError> {
+    let replaced = match with_content_store_read(|store| store.get(&content.id)) {
+        Some(previous) if previous.user != caller => return Err(BondedError::conflict("Content ID is already in use")),
+        Some(previous) => Some(record_size(&previous)),
+        None => None,
+    };
+    reserve_user_storage(caller, UsageStore::Content, &content.id, replaced, record_size(content))
+}
+
+#[update(name = "save_processed_content_v2")]
 pub fn save_processed_content(
     content_id: String,
     relationship_id: Option<String>,
@@ -340,6 +440,10 @@ pub fn save_processed_content(
 ) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "save_processed_content") {
+        return BondedResult::err(msg);
+    }
+    
     let processed_content = ProcessedContent {
         id: content_id.clone(),
         user: caller,
@@ -350,6 +454,10 @@ pub fn save_processed_content(
         updated_at: current_time(),
     };
     
+    if let Err(msg) = reserve_content(caller, &processed_content) {
+        return BondedResult::err(msg);
+    }
+    
     with_content_store(|store| {
         store.insert(content_id.clone(), processed_content);
     });
@@ -360,7 +468,7 @@ pub fn save_processed_content(
     BondedResult::ok("Processed content saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_processed_content_v2")]
 pub fn get_processed_content(content_id: String) -> BondedResult<ProcessedContent> {
     let caller = caller_principal();
     
@@ -369,14 +477,14 @@ pub fn get_processed_content(content_id: String) -> BondedResult<ProcessedConten
             if content.user == caller {
                 BondedResult::ok(content)
             } else {
-                BondedResult::err("Access denied")
+                BondedResult::err(BondedError::unauthorized("Access denied"))
             }
         },
-        None => BondedResult::err("Content not found"),
+        None => BondedResult::err(BondedError::not_found("Content")),
     }
 }
 
-#[query]
+#[query(name = "get_processed_content_by_type_v2")]
 pub fn get_processed_content_by_type(content_type: String) -> BondedResult<Vec<ProcessedContent>> {
     let caller = caller_principal();
     
@@ -396,10 +504,14 @@ pub fn get_processed_content_by_type(content_type: String) -> BondedResult<Vec<P
     BondedResult::ok(content)
 }
 
-#[update]
+#[update(name = "delete_processed_content_v2")]
 pub fn delete_processed_content(content_id: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "delete_processed_content") {
+        return BondedResult::err(msg);
+    }
+    
     // Verify ownership before deletion
     let can_delete = with_content_store_read(|store| {
         store.get(&content_id)
@@ -408,12 +520,12 @@ pub fn delete_processed_content(content_id: String) -> BondedResult<String> {
     });
     
     if !can_delete {
-        return BondedResult::err("Access denied or content not found");
+        return BondedResult::err(BondedError::not_found("Content"));
     }
     
-    with_content_store(|store| {
-        store.remove(&content_id);
-    });
+    if let Some(content) = with_content_store(|store| store.remove(&content_id)) {
+        release_user_usage(caller, UsageStore::Content, &content_id, record_size(&content));
+    }
     
     // Log audit event
     log_audit_event(caller, "delete_processed_content", Some(content_id));
@@ -425,9 +537,13 @@ pub fn delete_processed_content(content_id: String) -> BondedResult<String> {
 // USER SETTINGS & DATA
 // =======================
 
-#[update]
+#[update(name = "save_user_data_v2")]
 pub fn save_user_data(data_type: String, data_content: String) -> BondedResult<String> {
     let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "save_user_data") {
+        return BondedResult::err(msg);
+    }
     let content_id = format!("{}_{}", data_type, caller.to_text());
     
     let processed_content = ProcessedContent {
@@ -440,6 +556,10 @@ pub fn save_user_data(data_type: String, data_content: String) -> BondedResult<S
         updated_at: current_time(),
     };
     
+    if let Err(msg) = reserve_content(caller, &processed_content) {
+        return BondedResult::err(msg);
+    }
+    
     with_content_store(|store| {
         store.insert(content_id.clone(), processed_content);
     });
@@ -447,7 +567,7 @@ pub fn save_user_data(data_type: String, data_content: String) -> BondedResult<S
     BondedResult::ok("User data saved successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_user_data_v2")]
 pub fn get_user_data(data_type: String) -> BondedResult<String> {
     let caller = caller_principal();
     let content_id = format!("{}_{}", data_type, caller.to_text());
@@ -462,9 +582,13 @@ pub fn get_user_data(data_type: String) -> BondedResult<String> {
 // CLIENT DATA STORAGE (Frontend compatibility)
 // =======================
 
-#[update]
+#[update(name = "store_client_data_v2")]
 pub fn store_client_data(data_key: String, data_value: String) -> BondedResult<String> {
     let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "store_client_data") {
+        return BondedResult::err(msg);
+    }
     let content_id = format!("client_{}_{}", data_key, caller.to_text());
     
     let processed_content = ProcessedContent {
@@ -477,6 +601,10 @@ pub fn store_client_data(data_key: String, data_value: String) -> BondedResult<S
         updated_at: current_time(),
     };
     
+    if let Err(msg) = reserve_content(caller, &processed_content) {
+        return BondedResult::err(msg);
+    }
+    
     with_content_store(|store| {
         store.insert(content_id.clone(), processed_content);
     });
@@ -487,7 +615,7 @@ pub fn store_client_data(data_key: String, data_value: String) -> BondedResult<S
     BondedResult::ok("Client data stored successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_client_data_v2")]
 pub fn get_client_data(data_key: String) -> BondedResult<String> {
     let caller = caller_principal();
     let content_id = format!("client_{}_{}", data_key, caller.to_text());
@@ -502,15 +630,19 @@ pub fn get_client_data(data_key: String) -> BondedResult<String> {
 // BULK OPERATIONS
 // =======================
 
-#[update]
+#[update(name = "clear_all_user_data_v2")]
 pub fn clear_all_user_data() -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "clear_all_user_data") {
+        return BondedResult::err(msg);
+    }
+    
     // Clear timeline data
     let timeline_id = format!("timeline_{}", caller.to_text());
-    with_timeline_store(|store| {
-        store.remove(&timeline_id);
-    });
+    if let Some(timeline_data) = with_timeline_store(|store| store.remove(&timeline_id)) {
+        release_user_usage(caller, UsageStore::TimelineData, &timeline_id, record_size(&timeline_data));
+    }
     
     // Remove all processed content
     let content_keys: Vec<String> = with_content_store_read(|store| {
@@ -528,7 +660,9 @@ pub fn clear_all_user_data() -> BondedResult<String> {
     
     with_content_store(|store| {
         for key in content_keys {
-            store.remove(&key);
+            if let Some(content) = store.remove(&key) {
+                release_user_usage(caller, UsageStore::Content, &key, record_size(&content));
+            }
         }
     });
     
@@ -0,0 +1,210 @@
+use crate::evidence_index::relationship_evidence;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use ic_cdk_macros::query;
+use ic_certified_map::{labeled, labeled_hash, AsHashTree, Hash, RbTree};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::ops::Bound;
+
+// ==================================
+// CERTIFIED RELATIONSHIP COMMITMENTS
+// ==================================
+// Every relationship has a Merkle root (H_input) over its evidence hashes.
+// Roots are persisted in stable memory and mirrored into an in-heap certified
+// tree whose root hash is published via `set_certified_data`, so a query can
+// return the root together with a witness and the subnet's certificate.
+// Relationships created before commitments existed are committed by a
+// background migration.
+
+const COMMITMENT_TREE_LABEL: &[u8] = b"relationship_commitments";
+
+thread_local! {
+    static CERTIFIED_COMMITMENTS: RefCell<RbTree<String, Hash>> = const { RefCell::new(RbTree::new()) };
+}
+
+/// Recompute the relationship's Merkle root from its current evidence and
+/// republish the canister's certified data. Call after every evidence change.
+pub fn update_relationship_commitment(relationship_id: &str) {
+    let mut leaves: Vec<(String, String)> = relationship_evidence(relationship_id, None, None)
+        .into_iter()
+        .map(|summary| (summary.id, summary.hash))
+        .collect();
+    leaves.sort();
+
+    let root = evidence_merkle_root(&leaves);
+    let commitment = RelationshipCommitment {
+        relationship_id: relationship_id.to_string(),
+        root_hash: root.to_vec(),
+        evidence_count: leaves.len() as u64,
+        updated_at: current_time(),
+    };
+
+    with_commitment_store(|store| {
+        store.insert(relationship_id.to_string(), commitment);
+    });
+
+    CERTIFIED_COMMITMENTS.with(|tree| {
+        tree.borrow_mut().insert(relationship_id.to_string(), root);
+    });
+    publish_certified_data();
+}
+
+/// Drop a relationship's commitment entirely (used when its data is destroyed)
+pub fn remove_relationship_commitment(relationship_id: &str) {
+    with_commitment_store(|store| {
+        store.remove(&relationship_id.to_string());
+    });
+
+    CERTIFIED_COMMITMENTS.with(|tree| {
+        tree.borrow_mut().delete(relationship_id.as_bytes());
+    });
+    publish_certified_data();
+}
+
+/// Compute and certify a commitment for the next batch of relationships
+/// after `cursor` that predate commitments. Relationships that already have
+/// one are kept up to date by the evidence endpoints and are skipped, as are
+/// relationships whose data was destroyed.
+pub(crate) fn commit_existing_relationships(cursor: Option<String>, batch_size: usize) -> (Option<String>, u64) {
+    let start = match cursor {
+        Some(relationship_id) => Bound::Excluded(relationship_id),
+        None => Bound::Unbounded,
+    };
+
+    let batch: Vec<(String, RelationshipStatus)> = with_relationship_store_read(|store| {
+        store
+            .range((start, Bound::Unbounded))
+            .take(batch_size)
+            .map(|(relationship_id, relationship)| (relationship_id, relationship.status))
+            .collect()
+    });
+    for (relationship_id, status) in &batch {
+        let committed = with_commitment_store_read(|store| store.contains_key(relationship_id));
+        if !committed && !matches!(status, RelationshipStatus::Destroyed) {
+            update_relationship_commitment(relationship_id);
+        }
+    }
+
+    let processed = batch.len() as u64;
+    let cursor = if batch.len() < batch_size { None } else { batch.last().map(|(relationship_id, _)| relationship_id.clone()) };
+    (cursor, processed)
+}
+
+/// Rebuild the heap tree from stable memory; run from `init`/`post_upgrade`
+pub fn restore_certified_commitments() {
+    let commitments: Vec<(String, Hash)> = with_commitment_store_read(|store| {
+        store
+            .iter()
+            .map(|(relationship_id, commitment)| (relationship_id, merkle_root_of(&commitment)))
+            .collect()
+    });
+
+    CERTIFIED_COMMITMENTS.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        *tree = RbTree::new();
+        for (relationship_id, root) in commitments {
+            tree.insert(relationship_id, root);
+        }
+    });
+    publish_certified_data();
+}
+
+fn publish_certified_data() {
+    let root_hash = CERTIFIED_COMMITMENTS.with(|tree| tree.borrow().root_hash());
+    ic_cdk::api::set_certified_data(&labeled_hash(COMMITMENT_TREE_LABEL, &root_hash));
+}
+
+fn merkle_root_of(commitment: &RelationshipCommitment) -> Hash {
+    commitment.root_hash.as_slice().try_into().unwrap_or([0u8; 32])
+}
+
+/// Binary Merkle tree over `(evidence_id, evidence_hash)` pairs sorted by ID.
+/// Leaves and inner nodes are domain-separated; an odd node is promoted to
+/// the next level unchanged rather than duplicated.
+pub fn evidence_merkle_root(leaves: &[(String, String)]) -> Hash {
+    if leaves.is_empty() {
+        return Sha256::digest(b"bonded-commitment-v1:empty").into();
+    }
+
+    let mut level: Vec<Hash> = leaves
+        .iter()
+        .map(|(evidence_id, evidence_hash)| {
+            let mut hasher = Sha256::new();
+            hasher.update([0x00]);
+            hasher.update((evidence_id.len() as u64).to_be_bytes());
+            hasher.update(evidence_id.as_bytes());
+            hasher.update((evidence_hash.len() as u64).to_be_bytes());
+            hasher.update(evidence_hash.as_bytes());
+            hasher.finalize().into()
+        })
+        .collect();
+
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => {
+                    let mut hasher = Sha256::new();
+                    hasher.update([0x01]);
+                    hasher.update(left);
+                    hasher.update(right);
+                    hasher.finalize().into()
+                }
+                [single] => *single,
+                _ => unreachable!("chunks(2) yields one or two nodes"),
+            })
+            .collect();
+    }
+
+    level[0]
+}
+
+// ==================
+// COMMITMENT QUERIES
+// ==================
+
+#[query]
+pub fn get_relationship_commitment(relationship_id: String) -> BondedResult<RelationshipCommitmentResponse> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let commitment = match with_commitment_store_read(|store| store.get(&relationship_id)) {
+        Some(commitment) => commitment,
+        None => return BondedResult::err(BondedError::not_found("Commitment")),
+    };
+
+    let witness = CERTIFIED_COMMITMENTS.with(|tree| {
+        let tree = tree.borrow();
+        let witness = labeled(COMMITMENT_TREE_LABEL, tree.witness(relationship_id.as_bytes()));
+
+        let mut serializer = serde_cbor::ser::Serializer::new(Vec::new());
+        serializer.self_describe()?;
+        witness.serialize(&mut serializer)?;
+        Ok::<Vec<u8>, serde_cbor::Error>(serializer.into_inner())
+    });
+
+    let witness = match witness {
+        Ok(witness) => witness,
+        Err(e) => return BondedResult::err(BondedError::internal(format!("Failed to encode witness: {}", e))),
+    };
+
+    BondedResult::ok(RelationshipCommitmentResponse {
+        relationship_id,
+        root_hash: hex::encode(&commitment.root_hash),
+        evidence_count: commitment.evidence_count,
+        updated_at: commitment.updated_at,
+        witness,
+        certificate: ic_cdk::api::data_certificate(),
+    })
+}
@@ -0,0 +1,172 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::{client_storage, evidence, relationships, users};
+use candid::Principal;
+use ic_cdk_macros::{query, update};
+
+// ==================
+// LEGACY INTERFACE SHIM
+// ==================
+// Until the next release, the original endpoint names keep answering with
+// `Err: text` so clients built against the old `.did` can still decode
+// errors. Each one forwards to the typed implementation, which is exported
+// under `<name>_v2` and returns a `BondedError`; results whose layout has
+// changed are converted back to the old one. Once clients have moved to
+// the `_v2` methods this module goes away and the typed endpoints take the
+// original names back.
+
+macro_rules! legacy_endpoints {
+    ($($kind:ident $name:tt fn $legacy:ident($($arg:ident: $ty:ty),*) -> $ok:ty => $typed:path;)*) => {
+        $(
+            #[$kind(name = $name)]
+            fn $legacy($($arg: $ty),*) -> LegacyBondedResult<$ok> {
+                $typed($($arg),*).into()
+            }
+        )*
+    };
+}
+
+legacy_endpoints! {
+    // evidence
+    update "upload_evidence" fn legacy_upload_evidence(relationship_id: String, encrypted_data: Vec<u8>, metadata: EvidenceMetadata) -> String => evidence::upload_evidence;
+    update "delete_evidence" fn legacy_delete_evidence(evidence_id: String, relationship_id: String) -> String => evidence::delete_evidence;
+    query "get_evidence_by_id" fn legacy_get_evidence_by_id(evidence_id: String) -> Evidence => evidence::get_evidence_by_id;
+
+    // relationships
+    query "get_partner_invite" fn legacy_get_partner_invite(invite_id: String) -> PartnerInvite => relationships::get_partner_invite;
+    update "terminate_relationship" fn legacy_terminate_relationship(relationship_id: String) -> String => relationships::terminate_relationship;
+    query "get_key_share" fn legacy_get_key_share(relationship_id: String) -> Vec<u8> => relationships::get_key_share;
+
+    // users
+    update "register_user" fn legacy_register_user(email: Option<String>) -> String => users::register_user;
+    update "update_user_settings" fn legacy_update_user_settings(request: UpdateSettingsRequest) -> String => users::update_user_settings;
+    query "get_user_settings" fn legacy_get_user_settings() -> UserSettings => users::get_user_settings;
+    update "update_face_embedding" fn legacy_update_face_embedding(embedding: Vec<f32>) -> String => users::update_face_embedding;
+    update "verify_kyc" fn legacy_verify_kyc(user: Principal) -> String => users::verify_kyc;
+    query "get_user_profile" fn legacy_get_user_profile() -> UserProfile => users::get_user_profile;
+    update "delete_user_account" fn legacy_delete_user_account() -> String => users::delete_user_account;
+
+    // client storage
+    update "save_timeline_data" fn legacy_save_timeline_data(timeline_items: Vec<String>) -> String => client_storage::save_timeline_data;
+    query "get_timeline_data" fn legacy_get_timeline_data() -> Vec<String> => client_storage::get_timeline_data;
+    update "save_face_embedding" fn legacy_save_face_embedding(embedding_data: Vec<f32>, partner_id: Option<Principal>) -> String => client_storage::save_face_embedding;
+    query "get_face_embedding" fn legacy_get_face_embedding() -> UserFaceEmbedding => client_storage::get_face_embedding;
+    query "get_all_face_embeddings" fn legacy_get_all_face_embeddings() -> Vec<UserFaceEmbedding> => client_storage::get_all_face_embeddings;
+    update "delete_face_embeddings" fn legacy_delete_face_embeddings() -> String => client_storage::delete_face_embeddings;
+    update "save_auto_scanner_settings" fn legacy_save_auto_scanner_settings(settings_data: String) -> String => client_storage::save_auto_scanner_settings;
+    query "get_auto_scanner_settings" fn legacy_get_auto_scanner_settings() -> String => client_storage::get_auto_scanner_settings;
+    update "save_capture_settings" fn legacy_save_capture_settings(settings_data: String, file_type_overrides: String) -> String => client_storage::save_capture_settings;
+    query "get_capture_settings" fn legacy_get_capture_settings() -> (String, String) => client_storage::get_capture_settings;
+    update "save_email_log" fn legacy_save_email_log(log_data: String) -> String => client_storage::save_email_log;
+    query "get_email_logs" fn legacy_get_email_logs() -> Vec<EmailLog> => client_storage::get_email_logs;
+    update "save_geo_cache" fn legacy_save_geo_cache(cache_key: String, cache_data: String, expires_at: u64) -> String => client_storage::save_geo_cache;
+    query "get_geo_cache" fn legacy_get_geo_cache(cache_key: String) -> String => client_storage::get_geo_cache;
+    update "cleanup_expired_geo_cache" fn legacy_cleanup_expired_geo_cache() -> String => client_storage::cleanup_expired_geo_cache;
+    update "save_scheduler_settings" fn legacy_save_scheduler_settings(settings_data: String) -> String => client_storage::save_scheduler_settings;
+    query "get_scheduler_settings" fn legacy_get_scheduler_settings() -> String => client_storage::get_scheduler_settings;
+    update "save_processed_content" fn legacy_save_processed_content(content_id: String, relationship_id: Option<String>, content_data: String, content_type: String) -> String => client_storage::save_processed_content;
+    query "get_processed_content" fn legacy_get_processed_content(content_id: String) -> ProcessedContent => client_storage::get_processed_content;
+    query "get_processed_content_by_type" fn legacy_get_processed_content_by_type(content_type: String) -> Vec<ProcessedContent> => client_storage::get_processed_content_by_type;
+    update "delete_processed_content" fn legacy_delete_processed_content(content_id: String) -> String => client_storage::delete_processed_content;
+    update "save_user_data" fn legacy_save_user_data(data_type: String, data_content: String) -> String => client_storage::save_user_data;
+    query "get_user_data" fn legacy_get_user_data(data_type: String) -> String => client_storage::get_user_data;
+    update "store_client_data" fn legacy_store_client_data(data_key: String, data_value: String) -> String => client_storage::store_client_data;
+    query "get_client_data" fn legacy_get_client_data(data_key: String) -> String => client_storage::get_client_data;
+    update "clear_all_user_data" fn legacy_clear_all_user_data() -> String => client_storage::clear_all_user_data;
+}
+
+// Relationship setup and invite emails now need fields old clients cannot
+// supply; the old requests still decode and are answered with an error
+// naming the replacement
+
+fn legacy_unsupported<T>(field: &str, replacement: &str) -> LegacyBondedResult<T> {
+    BondedResult::err(BondedError::invalid_input(field, format!("Required by this release; call {} instead", replacement))).into()
+}
+
+#[update(name = "create_partner_invite")]
+fn legacy_create_partner_invite(_request: LegacyCreatePartnerInviteRequest) -> LegacyBondedResult<CreatePartnerInviteResponse> {
+    legacy_unsupported("kill_switch_public_key", "create_partner_invite_v2")
+}
+
+#[update(name = "accept_partner_invite")]
+fn legacy_accept_partner_invite(_invite_id: String) -> LegacyBondedResult<AcceptInviteResponse> {
+    legacy_unsupported("kill_switch_public_key", "accept_partner_invite_v2")
+}
+
+#[update(name = "create_relationship")]
+fn legacy_create_relationship(_request: LegacyCreateRelationshipRequest) -> LegacyBondedResult<CreateRelationshipResponse> {
+    legacy_unsupported("kill_switch_public_key", "create_relationship_v2")
+}
+
+#[update(name = "accept_relationship")]
+fn legacy_accept_relationship(_relationship_id: String) -> LegacyBondedResult<Vec<u8>> {
+    legacy_unsupported("kill_switch_public_key", "accept_relationship_v2")
+}
+
+#[update(name = "send_invite_email")]
+fn legacy_send_invite_email(_request: LegacySendInviteEmailRequest) -> LegacyBondedResult<SendEmailResponse> {
+    legacy_unsupported("template", "send_invite_email_v2")
+}
+
+// Relationships in their old layout, and the dashboard with full evidence records
+
+#[query(name = "get_relationship")]
+fn legacy_get_relationship(relationship_id: String) -> LegacyBondedResult<LegacyRelationship> {
+    match relationships::get_relationship(relationship_id) {
+        BondedResult::Ok(relationship) => LegacyBondedResult::Ok(relationship.into()),
+        BondedResult::Err(error) => LegacyBondedResult::Err(error.to_string()),
+    }
+}
+
+#[query(name = "get_user_relationships")]
+fn legacy_get_user_relationships() -> LegacyBondedResult<Vec<LegacyRelationship>> {
+    match relationships::get_user_relationships() {
+        BondedResult::Ok(relationships) => LegacyBondedResult::Ok(relationships.into_iter().map(LegacyRelationship::from).collect()),
+        BondedResult::Err(error) => LegacyBondedResult::Err(error.to_string()),
+    }
+}
+
+#[query(name = "get_user_dashboard_data")]
+fn legacy_get_user_dashboard_data() -> LegacyBondedResult<LegacyUserDashboardData> {
+    match crate::get_user_dashboard_data() {
+        BondedResult::Ok(dashboard) => LegacyBondedResult::Ok(LegacyUserDashboardData {
+            profile: dashboard.profile,
+            settings: dashboard.settings,
+            relationships: dashboard.relationships.into_iter().map(LegacyRelationship::from).collect(),
+            recent_evidence: dashboard
+                .recent_evidence
+                .iter()
+                .filter_map(|summary| with_evidence_store_read(|store| store.get(&summary.id)))
+                .collect(),
+            last_updated: dashboard.last_updated,
+        }),
+        BondedResult::Err(error) => LegacyBondedResult::Err(error.to_string()),
+    }
+}
+
+// Timelines now list `EvidenceSummary`; old clients get the full records back
+
+fn legacy_timeline(result: BondedResult<TimelineResponse>) -> LegacyBondedResult<LegacyTimelineResponse> {
+    match result {
+        BondedResult::Ok(page) => LegacyBondedResult::Ok(LegacyTimelineResponse {
+            evidence: page
+                .evidence
+                .iter()
+                .filter_map(|summary| with_evidence_store_read(|store| store.get(&summary.id)))
+                .collect(),
+            total_count: page.total_count.unwrap_or_default(),
+            has_more: page.has_more,
+        }),
+        BondedResult::Err(error) => LegacyBondedResult::Err(error.to_string()),
+    }
+}
+
+#[query(name = "get_timeline")]
+fn legacy_get_timeline(relationship_id: String, page: u32, page_size: u32) -> LegacyBondedResult<LegacyTimelineResponse> {
+    legacy_timeline(evidence::get_timeline(relationship_id, page, page_size))
+}
+
+#[query(name = "get_timeline_with_filters")]
+fn legacy_get_timeline_with_filters(query: TimelineQuery) -> LegacyBondedResult<LegacyTimelineResponse> {
+    legacy_timeline(evidence::get_timeline_with_filters(query))
+}
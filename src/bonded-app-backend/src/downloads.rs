@@ -0,0 +1,328 @@
+use crate::attestation::attestation_state;
+use crate::entropy;
+use crate::storage::*;
+use crate::types::*;
+use crate::uploads::{chunk_key, MAX_CHUNK_SIZE};
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use candid::Principal;
+use hmac::{Hmac, Mac};
+use ic_cdk_macros::{query, update};
+use sha2::Sha256;
+use std::cell::RefCell;
+
+// ==================
+// EVIDENCE DOWNLOAD
+// ==================
+// Ciphertext is read chunk by chunk, either through `get_evidence_chunk` or
+// through the HTTP gateway at `/evidence/<evidence_id>?token=<token>`. The
+// gateway calls `http_request` anonymously, so HTTP access is authorized by a
+// short-lived token: an HMAC over (evidence ID, partner, expiry) issued by
+// `create_evidence_download_token` to a relationship partner.
+//
+// Responses are not certified, so browsers must use the canister's raw
+// domain (`<canister-id>.raw.icp0.io`).
+
+/// Evidence uploaded in a single message is served in slices of this size
+const INLINE_CHUNK_SIZE: usize = MAX_CHUNK_SIZE as usize;
+const DOWNLOAD_TOKEN_TTL_NS: u64 = 5 * 60 * 1_000_000_000;
+/// A download started with a valid token may keep streaming this long past
+/// the token's expiry, so large files do not break mid-transfer
+const STREAMING_GRACE_NS: u64 = 60 * 60 * 1_000_000_000;
+
+thread_local! {
+    // Heap only: an upgrade rotates the key and revokes outstanding tokens
+    static DOWNLOAD_TOKEN_KEY: RefCell<Option<[u8; 32]>> = const { RefCell::new(None) };
+}
+
+/// Ciphertext size and number of chunks for an evidence item
+pub(crate) fn evidence_layout(evidence: &Evidence) -> (u64, u32) {
+    match &evidence.content {
+        Some(content) => (content.total_size, content.chunk_count),
+        None => {
+            let size = evidence.encrypted_data.len();
+            (size as u64, size.div_ceil(INLINE_CHUNK_SIZE).max(1) as u32)
+        }
+    }
+}
+
+fn read_evidence_chunk(evidence: &Evidence, index: u32) -> Option<Vec<u8>> {
+    let (_, chunk_count) = evidence_layout(evidence);
+    if index >= chunk_count {
+        return None;
+    }
+
+    match &evidence.content {
+        Some(content) => with_evidence_chunk_store_read(|store| store.get(&chunk_key(&content.content_id, index))),
+        None => {
+            let start = index as usize * INLINE_CHUNK_SIZE;
+            let end = (start + INLINE_CHUNK_SIZE).min(evidence.encrypted_data.len());
+            Some(evidence.encrypted_data[start..end].to_vec())
+        }
+    }
+}
+
+pub fn summarize_evidence(evidence: Evidence) -> EvidenceSummary {
+    let (size, chunk_count) = evidence_layout(&evidence);
+    let attestation = attestation_state(&evidence);
+    EvidenceSummary {
+        id: evidence.id,
+        relationship_id: evidence.relationship_id,
+        metadata: evidence.metadata,
+        upload_timestamp: evidence.upload_timestamp,
+        hash: evidence.hash,
+        uploader: evidence.uploader,
+        signature: evidence.signature,
+        signed_at: evidence.signed_at,
+        size,
+        chunk_count,
+        attestation,
+        countersignature: evidence.countersignature,
+    }
+}
+
+/// Load evidence the given principal may read
+fn readable_evidence(evidence_id: &str, reader: Principal) -> Result<Evidence, BondedError> {
+    let evidence = match with_evidence_store_read(|store| store.get(&evidence_id.to_string())) {
+        Some(ev) => ev,
+        None => return Err(BondedError::not_found("Evidence")),
+    };
+
+    let relationship = match with_relationship_store_read(|store| store.get(&evidence.relationship_id)) {
+        Some(rel) => rel,
+        None => return Err(BondedError::not_found("Relationship")),
+    };
+
+    verify_relationship_access(&relationship, reader)?;
+
+    Ok(evidence)
+}
+
+// Download tokens
+
+fn download_token_key() -> Option<[u8; 32]> {
+    DOWNLOAD_TOKEN_KEY.with(|key| *key.borrow())
+}
+
+fn ensure_download_token_key() -> Result<[u8; 32], String> {
+    if let Some(key) = download_token_key() {
+        return Ok(key);
+    }
+
+    let mut key = [0u8; 32];
+    entropy::fill_random(&mut key)?;
+    DOWNLOAD_TOKEN_KEY.with(|slot| *slot.borrow_mut() = Some(key));
+    Ok(key)
+}
+
+fn download_token_mac(key: &[u8; 32], evidence_id: &str, partner: Principal, expires_at: u64) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(b"bonded-download-v1");
+    mac.update(&(evidence_id.len() as u64).to_be_bytes());
+    mac.update(evidence_id.as_bytes());
+    mac.update(&(partner.as_slice().len() as u64).to_be_bytes());
+    mac.update(partner.as_slice());
+    mac.update(&expires_at.to_be_bytes());
+    mac
+}
+
+/// Token format: `<partner principal>.<expiry ns, hex>.<HMAC, hex>`
+fn issue_download_token(evidence_id: &str, partner: Principal, expires_at: u64) -> Result<String, String> {
+    let key = ensure_download_token_key()?;
+    let tag = download_token_mac(&key, evidence_id, partner, expires_at).finalize().into_bytes();
+    Ok(format!("{}.{:x}.{}", partner.to_text(), expires_at, hex::encode(tag)))
+}
+
+/// Check a token for `evidence_id`, accepting it until `expires_at + grace`,
+/// and return the partner it was issued to
+fn verify_download_token(token: &str, evidence_id: &str, grace: u64) -> Result<Principal, String> {
+    const INVALID: &str = "Invalid or expired download token";
+
+    let mut parts = token.split('.');
+    let (Some(partner), Some(expires_at), Some(tag), None) = (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(INVALID.to_string());
+    };
+
+    let partner = Principal::from_text(partner).map_err(|_| INVALID.to_string())?;
+    let expires_at = u64::from_str_radix(expires_at, 16).map_err(|_| INVALID.to_string())?;
+    let tag = hex::decode(tag).map_err(|_| INVALID.to_string())?;
+    let key = download_token_key().ok_or_else(|| INVALID.to_string())?;
+
+    // Constant-time comparison
+    download_token_mac(&key, evidence_id, partner, expires_at)
+        .verify_slice(&tag)
+        .map_err(|_| INVALID.to_string())?;
+
+    if current_time() > expires_at.saturating_add(grace) {
+        return Err(INVALID.to_string());
+    }
+
+    Ok(partner)
+}
+
+// ==================
+// DOWNLOAD ENDPOINTS
+// ==================
+
+#[query]
+pub fn get_evidence_summary(evidence_id: String) -> BondedResult<EvidenceSummary> {
+    let caller = caller_principal();
+
+    match readable_evidence(&evidence_id, caller) {
+        Ok(evidence) => BondedResult::ok(summarize_evidence(evidence)),
+        Err(msg) => BondedResult::err(msg),
+    }
+}
+
+#[query]
+pub fn get_evidence_chunk(evidence_id: String, index: u32) -> BondedResult<EvidenceChunk> {
+    let caller = caller_principal();
+
+    let evidence = match readable_evidence(&evidence_id, caller) {
+        Ok(evidence) => evidence,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    let (total_size, chunk_count) = evidence_layout(&evidence);
+    match read_evidence_chunk(&evidence, index) {
+        Some(bytes) => BondedResult::ok(EvidenceChunk {
+            evidence_id,
+            index,
+            chunk_count,
+            total_size,
+            bytes,
+        }),
+        None => BondedResult::err(BondedError::invalid_input("index", "Chunk index out of range")),
+    }
+}
+
+#[update]
+pub fn create_evidence_download_token(evidence_id: String) -> BondedResult<EvidenceDownloadToken> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "create_evidence_download_token") {
+        return BondedResult::err(msg);
+    }
+
+    if let Err(msg) = readable_evidence(&evidence_id, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let expires_at = current_time() + DOWNLOAD_TOKEN_TTL_NS;
+    let token = match issue_download_token(&evidence_id, caller, expires_at) {
+        Ok(token) => token,
+        Err(msg) => return BondedResult::err(BondedError::unavailable(msg)),
+    };
+
+    BondedResult::ok(EvidenceDownloadToken {
+        url_path: format!("/evidence/{}?token={}", evidence_id, token),
+        evidence_id,
+        token,
+        expires_at,
+    })
+}
+
+// ==================
+// HTTP GATEWAY
+// ==================
+
+fn http_error(status_code: u16, message: &str) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+        body: message.as_bytes().to_vec(),
+        streaming_strategy: None,
+    }
+}
+
+/// Split `/evidence/<id>?token=<token>` into (id, token)
+fn parse_evidence_url(url: &str) -> Option<(String, String)> {
+    let (path, query) = url.split_once('?').unwrap_or((url, ""));
+    let evidence_id = path.strip_prefix("/evidence/")?;
+    if evidence_id.is_empty() || evidence_id.contains('/') {
+        return None;
+    }
+
+    let token = query
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+        .filter(|token| !token.is_empty())?;
+
+    Some((evidence_id.to_string(), token.to_string()))
+}
+
+fn streaming_token(evidence_id: &str, index: u32, chunk_count: u32, download_token: &str) -> Option<StreamingCallbackToken> {
+    (index < chunk_count).then(|| StreamingCallbackToken {
+        evidence_id: evidence_id.to_string(),
+        index,
+        download_token: download_token.to_string(),
+    })
+}
+
+#[query]
+pub fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" {
+        return http_error(405, "Method not allowed");
+    }
+
+    let Some((evidence_id, token)) = parse_evidence_url(&request.url) else {
+        return http_error(404, "Not found");
+    };
+
+    let partner = match verify_download_token(&token, &evidence_id, 0) {
+        Ok(partner) => partner,
+        Err(msg) => return http_error(403, &msg),
+    };
+
+    // Partnership is re-checked at read time, not just when the token was issued
+    let evidence = match readable_evidence(&evidence_id, partner) {
+        Ok(evidence) => evidence,
+        Err(_) => return http_error(404, "Not found"),
+    };
+
+    let (total_size, chunk_count) = evidence_layout(&evidence);
+    let Some(body) = read_evidence_chunk(&evidence, 0) else {
+        return http_error(404, "Not found");
+    };
+
+    let streaming_strategy = streaming_token(&evidence_id, 1, chunk_count, &token).map(|token| StreamingStrategy::Callback {
+        callback: StreamingCallback::new(ic_cdk::api::id(), "http_request_streaming_callback".to_string()),
+        token,
+    });
+
+    HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), "application/octet-stream".to_string()),
+            ("Content-Length".to_string(), total_size.to_string()),
+            ("Cache-Control".to_string(), "no-store".to_string()),
+        ],
+        body,
+        streaming_strategy,
+    }
+}
+
+#[query]
+pub fn http_request_streaming_callback(token: StreamingCallbackToken) -> StreamingCallbackHttpResponse {
+    let partner = match verify_download_token(&token.download_token, &token.evidence_id, STREAMING_GRACE_NS) {
+        Ok(partner) => partner,
+        Err(msg) => ic_cdk::trap(&msg),
+    };
+
+    let evidence = match readable_evidence(&token.evidence_id, partner) {
+        Ok(evidence) => evidence,
+        Err(error) => ic_cdk::trap(&error.to_string()),
+    };
+
+    let (_, chunk_count) = evidence_layout(&evidence);
+    let body = match read_evidence_chunk(&evidence, token.index) {
+        Some(body) => body,
+        None => ic_cdk::trap("Chunk index out of range"),
+    };
+
+    StreamingCallbackHttpResponse {
+        body,
+        token: streaming_token(&token.evidence_id, token.index + 1, chunk_count, &token.download_token),
+    }
+}
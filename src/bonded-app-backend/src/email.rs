@@ -0,0 +1,331 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call_with_payment128;
+use ic_cdk::api::management_canister::http_request::{
+    HttpHeader, HttpMethod, HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk_macros::{query, update};
+use serde::Deserialize;
+use serde_json::json;
+
+// ==================
+// OUTBOUND EMAIL
+// ==================
+// Canister-originated mail, for messages the frontend must not see or forge
+// (e.g. invite verification codes). Bodies may carry secrets: never log them.
+//
+// Delivery goes through a non-replicated HTTPS outcall: a single replica
+// makes the request, so one call sends one email. Neither SendGrid nor
+// Postmark deduplicates requests, so a replicated outcall would send one copy
+// per replica. Only the status code survives `transform_email_response`;
+// message IDs and bodies differ per request and carry nothing we act on.
+//
+// The `Mock` provider posts a minimal JSON document to any URL, e.g. a local
+// HTTP server or a PocketIC HTTP-outcall mock, for tests.
+
+const DEFAULT_OUTCALL_CYCLES: u64 = 20_000_000_000;
+const MAX_RESPONSE_BYTES: u64 = 4 * 1024;
+
+pub struct OutgoingEmail {
+    pub category: &'static str, // e.g. "invite_code", recorded in the delivery log
+    pub to: String,
+    pub subject: String,
+    pub text_body: String,
+    pub html_body: Option<String>,
+}
+
+// ==================
+// PROVIDERS
+// ==================
+
+/// Shapes the provider's HTTP API; status handling is shared
+trait EmailProvider {
+    fn name(&self) -> &'static str;
+    fn endpoint(&self, api_url: &str) -> String;
+    fn auth_headers(&self, api_key: &str) -> Vec<HttpHeader>;
+    fn body(&self, config: &EmailConfig, email: &OutgoingEmail) -> serde_json::Value;
+}
+
+struct SendGrid;
+struct Postmark;
+struct MockProvider;
+
+fn header(name: &str, value: &str) -> HttpHeader {
+    HttpHeader { name: name.to_string(), value: value.to_string() }
+}
+
+impl EmailProvider for SendGrid {
+    fn name(&self) -> &'static str {
+        "sendgrid"
+    }
+
+    fn endpoint(&self, api_url: &str) -> String {
+        format!("{}/v3/mail/send", api_url.trim_end_matches('/'))
+    }
+
+    fn auth_headers(&self, api_key: &str) -> Vec<HttpHeader> {
+        vec![header("Authorization", &format!("Bearer {}", api_key))]
+    }
+
+    fn body(&self, config: &EmailConfig, email: &OutgoingEmail) -> serde_json::Value {
+        // SendGrid rejects empty content values
+        let mut content = Vec::new();
+        if !email.text_body.is_empty() {
+            content.push(json!({ "type": "text/plain", "value": email.text_body }));
+        }
+        if let Some(html) = &email.html_body {
+            content.push(json!({ "type": "text/html", "value": html }));
+        }
+        json!({
+            "personalizations": [{ "to": [{ "email": email.to }] }],
+            "from": { "email": config.from_address, "name": config.from_name },
+            "subject": email.subject,
+            "content": content,
+        })
+    }
+}
+
+impl EmailProvider for Postmark {
+    fn name(&self) -> &'static str {
+        "postmark"
+    }
+
+    fn endpoint(&self, api_url: &str) -> String {
+        format!("{}/email", api_url.trim_end_matches('/'))
+    }
+
+    fn auth_headers(&self, api_key: &str) -> Vec<HttpHeader> {
+        vec![header("X-Postmark-Server-Token", api_key)]
+    }
+
+    fn body(&self, config: &EmailConfig, email: &OutgoingEmail) -> serde_json::Value {
+        let from = match &config.from_name {
+            Some(name) => format!("{} <{}>", name, config.from_address),
+            None => config.from_address.clone(),
+        };
+        json!({
+            "From": from,
+            "To": email.to,
+            "Subject": email.subject,
+            "TextBody": email.text_body,
+            "HtmlBody": email.html_body,
+            "MessageStream": "outbound",
+        })
+    }
+}
+
+impl EmailProvider for MockProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn endpoint(&self, api_url: &str) -> String {
+        format!("{}/send", api_url.trim_end_matches('/'))
+    }
+
+    fn auth_headers(&self, api_key: &str) -> Vec<HttpHeader> {
+        vec![header("Authorization", &format!("Bearer {}", api_key))]
+    }
+
+    fn body(&self, config: &EmailConfig, email: &OutgoingEmail) -> serde_json::Value {
+        json!({
+            "from": config.from_address,
+            "to": email.to,
+            "subject": email.subject,
+            "text": email.text_body,
+            "html": email.html_body,
+        })
+    }
+}
+
+fn email_provider(kind: &EmailProviderKind) -> &'static dyn EmailProvider {
+    match kind {
+        EmailProviderKind::SendGrid => &SendGrid,
+        EmailProviderKind::Postmark => &Postmark,
+        EmailProviderKind::Mock => &MockProvider,
+    }
+}
+
+// ==================
+// DELIVERY
+// ==================
+
+// Management canister `http_request` with the `is_replicated` flag, which
+// the ic-cdk 0.17 argument type does not have yet
+
+#[derive(CandidType, Deserialize)]
+struct NonReplicatedHttpRequestArgs {
+    url: String,
+    max_response_bytes: Option<u64>,
+    method: HttpMethod,
+    headers: Vec<HttpHeader>,
+    body: Option<Vec<u8>>,
+    transform: Option<TransformContext>,
+    is_replicated: Option<bool>,
+}
+
+/// Only the status code is kept
+#[query(hidden = true)]
+fn transform_email_response(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: vec![],
+    }
+}
+
+fn save_email_log(email_log: &EmailLog) {
+    with_email_log_store(|store| {
+        store.insert(email_log.id.clone(), email_log.clone());
+    });
+}
+
+/// Deliver `email` through the configured provider and return its delivery
+/// record. Failed deliveries are recorded in EMAIL_LOG_STORE as well.
+pub async fn send_email(email: OutgoingEmail) -> Result<EmailLog, BondedError> {
+    let config = match get_canister_config().email {
+        Some(config) => config,
+        None => return Err(BondedError::unavailable("No email provider is configured")),
+    };
+    let provider = email_provider(&config.provider);
+
+    let log_id = with_canister_state(|state| {
+        state.next_email_id += 1;
+        generate_id("email", state.next_email_id)
+    });
+
+    let mut delivery = EmailDelivery {
+        category: email.category.to_string(),
+        provider: provider.name().to_string(),
+        recipient_hint: mask_email(&email.to),
+        subject: email.subject.clone(),
+        status: EmailDeliveryStatus::Pending,
+        http_status: None,
+        error: None,
+        completed_at: None,
+    };
+    let mut email_log = EmailLog {
+        id: log_id.clone(),
+        user: caller_principal(),
+        log_data: String::new(),
+        created_at: current_time(),
+        delivery: Some(delivery.clone()),
+    };
+    save_email_log(&email_log);
+
+    let mut headers = provider.auth_headers(&config.api_key);
+    headers.push(header("Content-Type", "application/json"));
+    headers.push(header("Accept", "application/json"));
+    headers.push(header("Idempotency-Key", &log_id));
+
+    let request = NonReplicatedHttpRequestArgs {
+        url: provider.endpoint(&config.api_url),
+        method: HttpMethod::POST,
+        headers,
+        body: Some(provider.body(&config, &email).to_string().into_bytes()),
+        max_response_bytes: Some(MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_email_response".to_string(), vec![])),
+        is_replicated: Some(false),
+    };
+
+    let response: Result<(OutcallResponse,), _> = call_with_payment128(
+        Principal::management_canister(),
+        "http_request",
+        (request,),
+        config.outcall_cycles as u128,
+    )
+    .await;
+    let outcome = match response {
+        Ok((response,)) => {
+            let status = u32::try_from(response.status.0.clone()).unwrap_or(0);
+            if (200..300).contains(&status) {
+                Ok(status)
+            } else {
+                Err((Some(status), format!("HTTP {}", status)))
+            }
+        }
+        Err((code, msg)) => Err((None, format!("Outcall failed ({:?}): {}", code, msg))),
+    };
+
+    delivery.completed_at = Some(current_time());
+    let result = match outcome {
+        Ok(status) => {
+            delivery.status = EmailDeliveryStatus::Sent;
+            delivery.http_status = Some(status);
+            ic_cdk::println!("📧 {} email {} sent via {}", email.category, log_id, provider.name());
+            Ok(())
+        }
+        Err((status, error)) => {
+            delivery.status = EmailDeliveryStatus::Failed;
+            delivery.http_status = status;
+            delivery.error = Some(error.clone());
+            ic_cdk::println!("❌ {} email {} failed via {}: {}", email.category, log_id, provider.name(), error);
+            Err(error)
+        }
+    };
+    email_log.delivery = Some(delivery);
+    save_email_log(&email_log);
+
+    match result {
+        Ok(()) => Ok(email_log),
+        Err(error) => Err(BondedError::unavailable(format!("Email delivery failed: {}", error))),
+    }
+}
+
+// ==================
+// EMAIL CONFIGURATION
+// ==================
+
+/// The API key is write-only
+#[query]
+pub fn get_email_config() -> BondedResult<Option<EmailConfig>> {
+    if let Err(msg) = verify_controller(caller_principal()) {
+        return BondedResult::err(msg);
+    }
+
+    BondedResult::ok(get_canister_config().email.map(|config| EmailConfig {
+        api_key: String::new(),
+        ..config
+    }))
+}
+
+#[update]
+pub fn set_email_config(mut config: EmailConfig) -> BondedResult<String> {
+    let caller = caller_principal();
+
+    if let Err(msg) = verify_controller(caller) {
+        return BondedResult::err(msg);
+    }
+
+    if !config.api_url.starts_with("https://") && !matches!(config.provider, EmailProviderKind::Mock) {
+        return BondedResult::err(BondedError::invalid_input("api_url", "Provider API URL must use HTTPS"));
+    }
+    if !is_valid_email(&config.from_address) {
+        return BondedResult::err(BondedError::invalid_input("from_address", "Invalid email address format"));
+    }
+    if config.outcall_cycles == 0 {
+        config.outcall_cycles = DEFAULT_OUTCALL_CYCLES;
+    }
+
+    let provider = email_provider(&config.provider).name();
+    update_canister_config(|canister_config| {
+        canister_config.email = Some(config);
+    });
+
+    log_audit_event(caller, "set_email_config", Some(format!("provider:{}", provider)));
+
+    BondedResult::ok(format!("Email provider set to {}", provider))
+}
+
+/// Delivery status of an email the caller triggered
+#[query]
+pub fn get_email_delivery(log_id: String) -> BondedResult<EmailLog> {
+    let caller = caller_principal();
+
+    match with_email_log_store_read(|store| store.get(&log_id)) {
+        Some(email_log) if email_log.user == caller || ic_cdk::api::is_controller(&caller) => BondedResult::ok(email_log),
+        _ => BondedResult::err(BondedError::not_found("Email log")),
+    }
+}
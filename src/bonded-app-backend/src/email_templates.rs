@@ -0,0 +1,355 @@
+use crate::email::OutgoingEmail;
+use crate::types::*;
+use ic_cdk_macros::query;
+
+// ==================
+// EMAIL TEMPLATES
+// ==================
+// Every email the canister sends is rendered here from a fixed template;
+// callers choose a template and a locale but never supply content. Templates
+// declare the placeholders they use, written `{name}` in the text, and the
+// values are filled in from canister data (`PartnerInvite`, `Relationship`).
+// Bodies are plain text, so no caller-influenced value is ever interpreted
+// as HTML.
+
+const DEFAULT_APP_URL: &str = "https://bonded.app";
+
+use EmailPlaceholder as P;
+
+struct EmailTemplate {
+    id: EmailTemplateId,
+    locale: EmailLocale,
+    subject: &'static str,
+    body: &'static str,
+    placeholders: &'static [EmailPlaceholder],
+}
+
+const INVITE_PLACEHOLDERS: &[EmailPlaceholder] = &[P::InviterName, P::InviteLink, P::InviteExpiresOn];
+const CODE_PLACEHOLDERS: &[EmailPlaceholder] = &[P::InviterName, P::VerificationCode, P::CodeValidMinutes];
+const ACCEPTED_PLACEHOLDERS: &[EmailPlaceholder] = &[P::AcceptedOn, P::AppLink];
+const KILL_SWITCH_PLACEHOLDERS: &[EmailPlaceholder] = &[P::DestroyedOn];
+const MILESTONE_PLACEHOLDERS: &[EmailPlaceholder] = &[P::EvidenceCount, P::AppLink];
+
+const TEMPLATES: &[EmailTemplate] = &[
+    // Invite
+    EmailTemplate {
+        id: EmailTemplateId::Invite,
+        locale: EmailLocale::En,
+        subject: "{inviter_name} invited you to Bonded",
+        body: "{inviter_name} would like to start documenting your relationship together on Bonded.\n\nAccept the invitation here:\n{invite_link}\n\nThe invitation expires on {invite_expires_on}. If you did not expect it, you can ignore this email.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::Invite,
+        locale: EmailLocale::Es,
+        subject: "{inviter_name} te ha invitado a Bonded",
+        body: "{inviter_name} quiere empezar a documentar vuestra relación en Bonded.\n\nAcepta la invitación aquí:\n{invite_link}\n\nLa invitación caduca el {invite_expires_on}. Si no la esperabas, puedes ignorar este correo.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::Invite,
+        locale: EmailLocale::De,
+        subject: "{inviter_name} hat dich zu Bonded eingeladen",
+        body: "{inviter_name} möchte eure Beziehung gemeinsam auf Bonded dokumentieren.\n\nHier kannst du die Einladung annehmen:\n{invite_link}\n\nDie Einladung läuft am {invite_expires_on} ab. Falls du sie nicht erwartet hast, kannst du diese E-Mail ignorieren.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    // Invite reminder
+    EmailTemplate {
+        id: EmailTemplateId::InviteReminder,
+        locale: EmailLocale::En,
+        subject: "Reminder: {inviter_name} is waiting for you on Bonded",
+        body: "{inviter_name}'s invitation to Bonded is still open.\n\nAccept it here:\n{invite_link}\n\nIt expires on {invite_expires_on}.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::InviteReminder,
+        locale: EmailLocale::Es,
+        subject: "Recordatorio: {inviter_name} te espera en Bonded",
+        body: "La invitación de {inviter_name} a Bonded sigue abierta.\n\nAcéptala aquí:\n{invite_link}\n\nCaduca el {invite_expires_on}.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::InviteReminder,
+        locale: EmailLocale::De,
+        subject: "Erinnerung: {inviter_name} wartet auf dich bei Bonded",
+        body: "Die Einladung von {inviter_name} zu Bonded ist noch offen.\n\nHier kannst du sie annehmen:\n{invite_link}\n\nSie läuft am {invite_expires_on} ab.",
+        placeholders: INVITE_PLACEHOLDERS,
+    },
+    // Invite verification code
+    EmailTemplate {
+        id: EmailTemplateId::InviteCode,
+        locale: EmailLocale::En,
+        subject: "Your code to join {inviter_name} on Bonded",
+        body: "Your Bonded verification code is {verification_code}.\n\nIt expires in {code_valid_minutes} minutes. If you did not expect this, ignore this email.",
+        placeholders: CODE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::InviteCode,
+        locale: EmailLocale::Es,
+        subject: "Tu código para unirte a {inviter_name} en Bonded",
+        body: "Tu código de verificación de Bonded es {verification_code}.\n\nCaduca en {code_valid_minutes} minutos. Si no lo esperabas, ignora este correo.",
+        placeholders: CODE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::InviteCode,
+        locale: EmailLocale::De,
+        subject: "Dein Code, um {inviter_name} auf Bonded beizutreten",
+        body: "Dein Bonded-Bestätigungscode lautet {verification_code}.\n\nEr läuft in {code_valid_minutes} Minuten ab. Falls du ihn nicht angefordert hast, ignoriere diese E-Mail.",
+        placeholders: CODE_PLACEHOLDERS,
+    },
+    // Relationship accepted
+    EmailTemplate {
+        id: EmailTemplateId::RelationshipAccepted,
+        locale: EmailLocale::En,
+        subject: "Your relationship on Bonded is active",
+        body: "Your partner accepted on {accepted_on}. You can now build your shared timeline:\n{app_link}",
+        placeholders: ACCEPTED_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::RelationshipAccepted,
+        locale: EmailLocale::Es,
+        subject: "Tu relación en Bonded está activa",
+        body: "Tu pareja aceptó el {accepted_on}. Ya podéis crear vuestra línea de tiempo compartida:\n{app_link}",
+        placeholders: ACCEPTED_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::RelationshipAccepted,
+        locale: EmailLocale::De,
+        subject: "Eure Beziehung auf Bonded ist aktiv",
+        body: "Dein Partner hat am {accepted_on} angenommen. Ihr könnt jetzt eure gemeinsame Zeitleiste aufbauen:\n{app_link}",
+        placeholders: ACCEPTED_PLACEHOLDERS,
+    },
+    // Kill switch executed
+    EmailTemplate {
+        id: EmailTemplateId::KillSwitchExecuted,
+        locale: EmailLocale::En,
+        subject: "Your Bonded relationship data was deleted",
+        body: "On {destroyed_on}, the kill switch was used and all evidence stored for your relationship was permanently destroyed. This cannot be undone.",
+        placeholders: KILL_SWITCH_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::KillSwitchExecuted,
+        locale: EmailLocale::Es,
+        subject: "Se han eliminado los datos de tu relación en Bonded",
+        body: "El {destroyed_on} se activó el interruptor de emergencia y todas las pruebas guardadas de vuestra relación se destruyeron de forma permanente. No se puede deshacer.",
+        placeholders: KILL_SWITCH_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::KillSwitchExecuted,
+        locale: EmailLocale::De,
+        subject: "Die Daten eurer Beziehung auf Bonded wurden gelöscht",
+        body: "Am {destroyed_on} wurde der Notschalter betätigt und alle für eure Beziehung gespeicherten Nachweise wurden dauerhaft vernichtet. Dies kann nicht rückgängig gemacht werden.",
+        placeholders: KILL_SWITCH_PLACEHOLDERS,
+    },
+    // Evidence milestone
+    EmailTemplate {
+        id: EmailTemplateId::EvidenceMilestone,
+        locale: EmailLocale::En,
+        subject: "{evidence_count} moments on Bonded",
+        body: "Your shared timeline now holds {evidence_count} pieces of evidence. Take a look:\n{app_link}",
+        placeholders: MILESTONE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::EvidenceMilestone,
+        locale: EmailLocale::Es,
+        subject: "{evidence_count} momentos en Bonded",
+        body: "Vuestra línea de tiempo compartida ya contiene {evidence_count} pruebas. Échale un vistazo:\n{app_link}",
+        placeholders: MILESTONE_PLACEHOLDERS,
+    },
+    EmailTemplate {
+        id: EmailTemplateId::EvidenceMilestone,
+        locale: EmailLocale::De,
+        subject: "{evidence_count} Momente auf Bonded",
+        body: "Eure gemeinsame Zeitleiste enthält jetzt {evidence_count} Nachweise. Schau sie dir an:\n{app_link}",
+        placeholders: MILESTONE_PLACEHOLDERS,
+    },
+];
+
+impl EmailPlaceholder {
+    fn key(&self) -> &'static str {
+        match self {
+            P::InviterName => "inviter_name",
+            P::InviteLink => "invite_link",
+            P::InviteExpiresOn => "invite_expires_on",
+            P::VerificationCode => "verification_code",
+            P::CodeValidMinutes => "code_valid_minutes",
+            P::AppLink => "app_link",
+            P::AcceptedOn => "accepted_on",
+            P::DestroyedOn => "destroyed_on",
+            P::EvidenceCount => "evidence_count",
+        }
+    }
+}
+
+/// Placeholder values; a template renders only if every placeholder it
+/// declares has a value
+#[derive(Default)]
+pub struct TemplateData {
+    pub inviter_name: Option<String>,
+    pub invite_link: Option<String>,
+    pub invite_expires_on: Option<String>,
+    pub verification_code: Option<String>,
+    pub code_valid_minutes: Option<u64>,
+    pub app_link: Option<String>,
+    pub accepted_on: Option<String>,
+    pub destroyed_on: Option<String>,
+    pub evidence_count: Option<u64>,
+}
+
+impl TemplateData {
+    pub fn from_invite(invite: &PartnerInvite, invite_token: &str) -> Self {
+        TemplateData {
+            inviter_name: Some(invite.inviter_name.clone()),
+            invite_link: Some(format!("{}/accept-invite?invite={}", app_url(), invite_token)),
+            invite_expires_on: Some(format_date(invite.expires_at)),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_relationship(relationship: &Relationship) -> Self {
+        TemplateData {
+            app_link: Some(app_url()),
+            accepted_on: relationship.accepted_at.map(format_date),
+            destroyed_on: relationship.destroyed_at.map(format_date),
+            evidence_count: Some(relationship.evidence_count),
+            ..Default::default()
+        }
+    }
+
+    fn value(&self, placeholder: EmailPlaceholder) -> Option<String> {
+        match placeholder {
+            P::InviterName => self.inviter_name.clone(),
+            P::InviteLink => self.invite_link.clone(),
+            P::InviteExpiresOn => self.invite_expires_on.clone(),
+            P::VerificationCode => self.verification_code.clone(),
+            P::CodeValidMinutes => self.code_valid_minutes.map(|minutes| minutes.to_string()),
+            P::AppLink => self.app_link.clone(),
+            P::AcceptedOn => self.accepted_on.clone(),
+            P::DestroyedOn => self.destroyed_on.clone(),
+            P::EvidenceCount => self.evidence_count.map(|count| count.to_string()),
+        }
+    }
+}
+
+fn app_url() -> String {
+    crate::storage::get_canister_config()
+        .email
+        .and_then(|config| config.app_url)
+        .unwrap_or_else(|| DEFAULT_APP_URL.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Nanosecond timestamp as an ISO date (UTC)
+fn format_date(timestamp_ns: u64) -> String {
+    match time::OffsetDateTime::from_unix_timestamp_nanos(timestamp_ns as i128) {
+        Ok(datetime) => format!("{:04}-{:02}-{:02}", datetime.year(), u8::from(datetime.month()), datetime.day()),
+        Err(_) => timestamp_ns.to_string(),
+    }
+}
+
+fn find_template(id: EmailTemplateId, locale: EmailLocale) -> Option<&'static EmailTemplate> {
+    TEMPLATES
+        .iter()
+        .find(|template| template.id == id && template.locale == locale)
+        // Every template exists in English
+        .or_else(|| TEMPLATES.iter().find(|template| template.id == id && template.locale == EmailLocale::En))
+}
+
+fn fill(text: &str, template: &EmailTemplate, data: &TemplateData) -> Result<String, BondedError> {
+    let mut rendered = text.to_string();
+    for placeholder in template.placeholders {
+        let value = data.value(*placeholder).ok_or_else(|| {
+            BondedError::invalid_input("template", format!("No value for placeholder {}", placeholder.key()))
+        })?;
+        rendered = rendered.replace(&format!("{{{}}}", placeholder.key()), &value);
+    }
+    Ok(rendered)
+}
+
+/// Render a template for `to`
+pub fn render_email(
+    id: EmailTemplateId,
+    locale: EmailLocale,
+    to: String,
+    data: &TemplateData,
+) -> Result<OutgoingEmail, BondedError> {
+    let template = find_template(id, locale).ok_or_else(|| BondedError::not_found("Email template"))?;
+
+    Ok(OutgoingEmail {
+        category: template_category(id),
+        to,
+        subject: fill(template.subject, template, data)?,
+        text_body: fill(template.body, template, data)?,
+        html_body: None,
+    })
+}
+
+fn template_category(id: EmailTemplateId) -> &'static str {
+    match id {
+        EmailTemplateId::Invite => "invite",
+        EmailTemplateId::InviteReminder => "invite_reminder",
+        EmailTemplateId::InviteCode => "invite_code",
+        EmailTemplateId::RelationshipAccepted => "relationship_accepted",
+        EmailTemplateId::KillSwitchExecuted => "kill_switch_executed",
+        EmailTemplateId::EvidenceMilestone => "evidence_milestone",
+    }
+}
+
+#[query]
+pub fn list_email_templates() -> Vec<EmailTemplateInfo> {
+    TEMPLATES
+        .iter()
+        .map(|template| EmailTemplateInfo {
+            id: template.id,
+            locale: template.locale,
+            subject: template.subject.to_string(),
+            placeholders: template.placeholders.to_vec(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete_data() -> TemplateData {
+        TemplateData {
+            inviter_name: Some("Alex".to_string()),
+            invite_link: Some("https://bonded.app/accept-invite?invite=token".to_string()),
+            invite_expires_on: Some("2026-01-31".to_string()),
+            verification_code: Some("123456".to_string()),
+            code_valid_minutes: Some(10),
+            app_link: Some("https://bonded.app".to_string()),
+            accepted_on: Some("2026-01-01".to_string()),
+            destroyed_on: Some("2026-02-01".to_string()),
+            evidence_count: Some(100),
+        }
+    }
+
+    #[test]
+    fn fill_fails_on_missing_placeholder() {
+        let template = find_template(EmailTemplateId::InviteCode, EmailLocale::En).unwrap();
+        let data = TemplateData {
+            verification_code: None,
+            ..complete_data()
+        };
+
+        assert_eq!(
+            fill(template.body, template, &data),
+            Err(BondedError::invalid_input("template", "No value for placeholder verification_code"))
+        );
+    }
+
+    #[test]
+    fn every_template_fills_completely() {
+        let data = complete_data();
+        for template in TEMPLATES {
+            for text in [template.subject, template.body] {
+                let rendered = fill(text, template, &data).unwrap();
+                // A leftover brace means the text uses an undeclared placeholder
+                assert!(!rendered.contains('{'), "{:?}/{:?}: {}", template.id, template.locale, rendered);
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+// ==================
+// ENTROPY POOL
+// ==================
+// CSPRNG backing `getrandom` inside the canister. The pool is seeded from the
+// management canister's `raw_rand` (threshold-BLS randomness from the subnet)
+// and expanded through ChaCha20. It starts unseeded after `init` and every
+// upgrade; until the first reseed lands, all randomness requests fail closed.
+
+use rand_chacha::rand_core::{RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::time::Duration;
+
+/// How often fresh `raw_rand` output is mixed into the pool
+const RESEED_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+thread_local! {
+    static ENTROPY_POOL: RefCell<Option<ChaCha20Rng>> = const { RefCell::new(None) };
+}
+
+pub fn is_seeded() -> bool {
+    ENTROPY_POOL.with(|pool| pool.borrow().is_some())
+}
+
+/// Fill `buf` from the pool. Errors if the pool has not been seeded yet.
+pub fn fill_random(buf: &mut [u8]) -> Result<(), String> {
+    ENTROPY_POOL.with(|pool| match pool.borrow_mut().as_mut() {
+        Some(rng) => {
+            rng.fill_bytes(buf);
+            Ok(())
+        }
+        None => Err("Secure randomness is not available yet, please retry shortly".to_string()),
+    })
+}
+
+/// Mix fresh entropy into the pool. The new ChaCha20 seed is
+/// SHA-256(domain || current pool output || fresh), so a reseed never
+/// reduces the entropy already held.
+pub fn mix_seed(fresh: &[u8]) {
+    ENTROPY_POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+
+        let mut hasher = Sha256::new();
+        hasher.update(b"bonded-entropy-reseed-v1");
+        if let Some(rng) = pool.as_mut() {
+            let mut carry = [0u8; 32];
+            rng.fill_bytes(&mut carry);
+            hasher.update(carry);
+        }
+        hasher.update(fresh);
+
+        *pool = Some(ChaCha20Rng::from_seed(hasher.finalize().into()));
+    });
+}
+
+/// Fetch 32 bytes from `raw_rand` and mix them into the pool
+pub async fn reseed_from_raw_rand() {
+    match ic_cdk::api::management_canister::main::raw_rand().await {
+        Ok((bytes,)) => {
+            mix_seed(&bytes);
+            ic_cdk::println!("🎲 Entropy pool reseeded from raw_rand");
+        }
+        Err((code, msg)) => {
+            ic_cdk::println!("❌ raw_rand failed ({:?}): {}", code, msg);
+        }
+    }
+}
+
+/// Seed immediately after install/upgrade and keep reseeding periodically.
+/// `raw_rand` is an inter-canister call, so it cannot run inside
+/// `init`/`post_upgrade` directly; a zero-delay timer runs it right after.
+pub fn schedule_reseeding() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(reseed_from_raw_rand()));
+    ic_cdk_timers::set_timer_interval(RESEED_INTERVAL, || ic_cdk::spawn(reseed_from_raw_rand()));
+}
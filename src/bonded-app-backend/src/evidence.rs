@@ -1,6 +1,13 @@
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use crate::attestation::{evidence_signature_message, verify_relationship_signature};
+use crate::commitment::update_relationship_commitment;
+use crate::evidence_index::{index_evidence, page_relationship_evidence, unindex_evidence, TimelineFilter};
+use crate::key_rotation::{current_key_version, remove_wrapped_keys, store_initial_wrapped_key, validate_wrapped_key};
+use crate::quotas::{check_relationship_quota, charge_evidence_usage, release_evidence_usage};
+use crate::uploads::remove_evidence_content;
 use candid::Principal;
 use ic_cdk_macros::{query, update};
 
@@ -8,7 +15,7 @@ use ic_cdk_macros::{query, update};
 // EVIDENCE METHODS
 // ==================
 
-#[update]
+#[update(name = "upload_evidence_v2")]
 pub fn upload_evidence(
     relationship_id: String,
     encrypted_data: Vec<u8>,
@@ -16,33 +23,127 @@ pub fn upload_evidence(
 ) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "upload_evidence") {
+        return BondedResult::err(msg);
+    }
+    
     // Validate inputs
     if let Err(msg) = validate_encrypted_data(&encrypted_data) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
     if let Err(msg) = validate_evidence_metadata(&metadata) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
     // Check if relationship exists and user has access
     let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
+    }
+    
+    if let Err(msg) = check_relationship_quota(&relationship, encrypted_data.len() as u64) {
+        return BondedResult::err(msg);
+    }
+    
+    let hash = generate_evidence_hash(&encrypted_data, &metadata);
+    let evidence_id = record_evidence(relationship, caller, encrypted_data, metadata, hash, None, None);
+    
+    BondedResult::ok(evidence_id)
+}
+
+/// Upload with a client-computed hash and a signature over it. The hash must
+/// match the canister's own hash over the data and every metadata field; the
+/// signature is checked against the relationship public key of the key
+/// version the evidence is stored under.
+#[update]
+pub fn upload_signed_evidence(request: UploadEvidenceRequest) -> BondedResult<String> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "upload_signed_evidence") {
+        return BondedResult::err(msg);
+    }
+    
+    // Validate inputs
+    if let Err(msg) = validate_encrypted_data(&request.encrypted_data) {
+        return BondedResult::err(msg);
+    }
+    
+    if let Err(msg) = validate_evidence_metadata(&request.metadata) {
+        return BondedResult::err(msg);
+    }
+    
+    // Check if relationship exists and user has access
+    let relationship = match with_relationship_store_read(|store| store.get(&request.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+    
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+    
+    if let Err(msg) = check_relationship_quota(&relationship, request.encrypted_data.len() as u64) {
+        return BondedResult::err(msg);
+    }
+    
+    let hash = generate_evidence_hash(&request.encrypted_data, &request.metadata);
+    if !hash.eq_ignore_ascii_case(&request.hash) {
+        return BondedResult::err(BondedError::invalid_input("hash", "Hash does not match the evidence data and metadata"));
+    }
+    
+    let key_version = current_key_version(&relationship);
+    let message = evidence_signature_message(&relationship.id, &hash, &request.metadata);
+    if let Err(msg) = verify_relationship_signature(&relationship, key_version, &message, &request.signature) {
+        return BondedResult::err(msg);
+    }
+    
+    if let Some(wrapped_key) = &request.wrapped_key {
+        if let Err(msg) = validate_wrapped_key(wrapped_key) {
+            return BondedResult::err(msg);
+        }
+    }
+    
+    let evidence_id = record_evidence(
+        relationship,
+        caller,
+        request.encrypted_data,
+        request.metadata,
+        hash,
+        Some(request.signature),
+        None,
+    );
+    
+    if let Some(wrapped_key) = request.wrapped_key {
+        store_initial_wrapped_key(&request.relationship_id, &evidence_id, key_version, wrapped_key);
     }
     
-    // Generate evidence ID and hash
+    BondedResult::ok(evidence_id)
+}
+
+/// Persist a validated evidence item and do the bookkeeping shared by the
+/// single-message and chunked upload paths
+pub(crate) fn record_evidence(
+    relationship: Relationship,
+    uploader: Principal,
+    encrypted_data: Vec<u8>,
+    metadata: EvidenceMetadata,
+    hash: String,
+    signature: Option<Vec<u8>>,
+    content: Option<EvidenceContent>,
+) -> String {
+    let relationship_id = relationship.id.clone();
+    
+    // Generate evidence ID
     let evidence_id = with_canister_state(|state| {
         state.next_evidence_id += 1;
         generate_id("evidence", state.next_evidence_id)
     });
     
-    let hash = generate_evidence_hash(&encrypted_data, &metadata);
-    
     // Create evidence record
     let evidence = Evidence {
         id: evidence_id.clone(),
@@ -51,11 +152,16 @@ pub fn upload_evidence(
         metadata,
         upload_timestamp: current_time(),
         hash,
-        uploader: caller,
-        signature: None, // MVP: signatures can be added later
+        uploader,
+        signed_at: signature.as_ref().map(|_| current_time()),
+        signature, // Verified by the caller; can also be attached later with sign_evidence
+        content,
+        countersignature: None,
     };
     
-    // Store evidence
+    // Store and index evidence
+    index_evidence(&evidence);
+    charge_evidence_usage(&evidence);
     with_evidence_store(|store| {
         store.insert(evidence_id.clone(), evidence);
     });
@@ -66,19 +172,22 @@ pub fn upload_evidence(
     updated_relationship.last_activity = current_time();
     
     with_relationship_store(|store| {
-        store.insert(relationship_id, updated_relationship);
+        store.insert(relationship_id.clone(), updated_relationship);
     });
     
+    // Re-certify the relationship's data commitment
+    update_relationship_commitment(&relationship_id);
+    
     // Update user profile
-    update_user_evidence_count(caller);
+    update_user_evidence_count(uploader);
     
     // Log audit event
-    log_audit_event(caller, "upload_evidence", Some(evidence_id.clone()));
+    log_relationship_audit_event(uploader, &relationship_id, "upload_evidence", Some(evidence_id.clone()));
     
-    BondedResult::ok(evidence_id)
+    evidence_id
 }
 
-#[query]
+#[query(name = "get_timeline_v2")]
 pub fn get_timeline(
     relationship_id: String,
     page: u32,
@@ -89,142 +198,87 @@ pub fn get_timeline(
     // Verify access to relationship
     let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
-    let skip = page * page_size;
-    
-    // Get all evidence for this relationship
-    let mut all_evidence: Vec<Evidence> = with_evidence_store_read(|store| {
-        store
-            .iter()
-            .filter_map(|(_, evidence)| {
-                if evidence.relationship_id == relationship_id {
-                    Some(evidence)
-                } else {
-                    None
-                }
-            })
-            .collect()
-    });
-    
-    // Sort by upload timestamp (newest first)
-    all_evidence.sort_by(|a, b| b.upload_timestamp.cmp(&a.upload_timestamp));
-    
-    let total_count = all_evidence.len() as u64;
-    let has_more = total_count > (skip + page_size) as u64;
-    
-    // Paginate
-    let evidence: Vec<Evidence> = all_evidence
-        .into_iter()
-        .skip(skip as usize)
-        .take(page_size as usize)
-        .collect();
+    // Legacy offset paging, newest upload first; prefer get_timeline_with_filters with a cursor
+    let filter = TimelineFilter {
+        relationship_id: &relationship_id,
+        sort: TimelineSortField::UploadTimestamp,
+        tag: None,
+        start: None,
+        end: None,
+    };
     
-    BondedResult::ok(TimelineResponse {
-        evidence,
-        total_count,
-        has_more,
-    })
+    match page_relationship_evidence(&filter, None, PageDirection::Next, page, Some(page_size)) {
+        Ok(response) => BondedResult::ok(response),
+        Err(msg) => BondedResult::err(msg),
+    }
 }
 
-#[query]
+#[query(name = "get_timeline_with_filters_v2")]
 pub fn get_timeline_with_filters(query: TimelineQuery) -> BondedResult<TimelineResponse> {
     let caller = caller_principal();
     
     // Verify access to relationship
     let relationship = match with_relationship_store_read(|store| store.get(&query.relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
-    let page = query.page.unwrap_or(0);
-    let page_size = 20u32;
-    let skip = page * page_size;
-    
-    // Get and filter evidence
-    let mut all_evidence: Vec<Evidence> = with_evidence_store_read(|store| {
-        store
-            .iter()
-            .filter_map(|(_, evidence)| {
-                if evidence.relationship_id != query.relationship_id {
-                    return None;
-                }
-                
-                // Apply filters
-                if let Some(ref category) = query.category_filter {
-                    if !evidence.metadata.tags.contains(category) {
-                        return None;
-                    }
-                }
-                
-                if let Some(start_date) = query.start_date {
-                    if evidence.metadata.timestamp < start_date {
-                        return None;
-                    }
-                }
-                
-                if let Some(end_date) = query.end_date {
-                    if evidence.metadata.timestamp > end_date {
-                        return None;
-                    }
-                }
-                
-                Some(evidence)
-            })
-            .collect()
-    });
-    
-    // Sort by original timestamp (newest first)
-    all_evidence.sort_by(|a, b| b.metadata.timestamp.cmp(&a.metadata.timestamp));
-    
-    let total_count = all_evidence.len() as u64;
-    let has_more = total_count > (skip + page_size) as u64;
-    
-    // Paginate
-    let evidence: Vec<Evidence> = all_evidence
-        .into_iter()
-        .skip(skip as usize)
-        .take(page_size as usize)
-        .collect();
+    let filter = TimelineFilter {
+        relationship_id: &query.relationship_id,
+        sort: query.sort_by.unwrap_or(TimelineSortField::MetadataTimestamp),
+        tag: query.category_filter.as_deref(),
+        start: query.start_date,
+        end: query.end_date,
+    };
     
-    BondedResult::ok(TimelineResponse {
-        evidence,
-        total_count,
-        has_more,
-    })
+    // `page` is only honoured for clients that have not moved to cursors yet
+    match page_relationship_evidence(
+        &filter,
+        query.cursor.as_deref(),
+        query.direction.unwrap_or(PageDirection::Next),
+        query.page.unwrap_or(0),
+        query.page_size,
+    ) {
+        Ok(response) => BondedResult::ok(response),
+        Err(msg) => BondedResult::err(msg),
+    }
 }
 
-#[update]
+#[update(name = "delete_evidence_v2")]
 pub fn delete_evidence(evidence_id: String, relationship_id: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "delete_evidence") {
+        return BondedResult::err(msg);
+    }
+    
     // Verify evidence exists and user has access
     let evidence = match with_evidence_store_read(|store| store.get(&evidence_id)) {
         Some(ev) => ev,
-        None => return BondedResult::err("Evidence not found"),
+        None => return BondedResult::err(BondedError::not_found("Evidence")),
     };
     
     if evidence.relationship_id != relationship_id {
-        return BondedResult::err("Evidence does not belong to specified relationship");
+        return BondedResult::err(BondedError::invalid_input("relationship_id", "Evidence does not belong to specified relationship"));
     }
     
     if evidence.uploader != caller {
-        return BondedResult::err("Only the uploader can delete evidence");
+        return BondedResult::err(BondedError::unauthorized("Only the uploader can delete evidence"));
     }
     
     // Delete the evidence
-    with_evidence_store(|store| {
-        store.remove(&evidence_id);
-    });
+    remove_evidence(&evidence_id);
     
     // Update relationship evidence count
     if let Some(mut relationship) = with_relationship_store_read(|store| store.get(&relationship_id)) {
@@ -232,38 +286,52 @@ pub fn delete_evidence(evidence_id: String, relationship_id: String) -> BondedRe
         relationship.last_activity = current_time();
         
         with_relationship_store(|store| {
-            store.insert(relationship_id, relationship);
+            store.insert(relationship_id.clone(), relationship);
         });
     }
     
+    // Re-certify the relationship's data commitment
+    update_relationship_commitment(&relationship_id);
+    
     // Log audit event
-    log_audit_event(caller, "delete_evidence", Some(evidence_id.clone()));
+    log_relationship_audit_event(caller, &relationship_id, "delete_evidence", Some(evidence_id.clone()));
     
     BondedResult::ok(format!("Evidence {} deleted successfully", evidence_id))
 }
 
-#[query]
+#[query(name = "get_evidence_by_id_v2")]
 pub fn get_evidence_by_id(evidence_id: String) -> BondedResult<Evidence> {
     let caller = caller_principal();
     
     let evidence = match with_evidence_store_read(|store| store.get(&evidence_id)) {
         Some(ev) => ev,
-        None => return BondedResult::err("Evidence not found"),
+        None => return BondedResult::err(BondedError::not_found("Evidence")),
     };
     
     // Verify user has access to the relationship
     let relationship = match with_relationship_store_read(|store| store.get(&evidence.relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
     BondedResult::ok(evidence)
 }
 
+/// Remove an evidence record together with its index entries and any
+/// chunked content it owns
+pub(crate) fn remove_evidence(evidence_id: &str) -> Option<Evidence> {
+    let evidence = with_evidence_store(|store| store.remove(&evidence_id.to_string()))?;
+    unindex_evidence(&evidence);
+    release_evidence_usage(&evidence);
+    remove_evidence_content(&evidence);
+    remove_wrapped_keys(evidence_id);
+    Some(evidence)
+}
+
 // Helper function to update user evidence count
 fn update_user_evidence_count(user: Principal) {
     with_user_store(|store| {
@@ -279,6 +347,8 @@ fn update_user_evidence_count(user: Principal) {
                 relationships: vec![],
                 total_evidence_uploaded: 1,
                 kyc_verified: false,
+                quota_tier: None,
+                email: None,
                 last_seen: current_time(),
             };
             store.insert(user, profile);
@@ -0,0 +1,660 @@
+use crate::downloads::summarize_evidence;
+use crate::storage::*;
+use crate::types::*;
+use std::ops::{Bound, Range};
+
+// ==================
+// EVIDENCE INDEXES
+// ==================
+// Secondary indexes over EVIDENCE_STORE so per-relationship reads are range
+// scans instead of full-store scans:
+//
+// - EVIDENCE_INDEX_STORE:     "<relationship_id>:<timestamp:020>:<evidence_id>" -> EvidenceSummary
+// - EVIDENCE_TAG_INDEX_STORE: "<relationship_id>:<hex(tag)>:<timestamp:020>:<evidence_id>" -> primary index key
+// - EVIDENCE_UPLOAD_INDEX_STORE: "<relationship_id>:<upload_timestamp:020>:<evidence_id>" -> primary index key
+// - EVIDENCE_COUNT_STORE:     "<relationship_id>" and "<relationship_id>:<hex(tag)>" -> number of indexed items
+//
+// `timestamp` is `metadata.timestamp`; timestamps are zero-padded so
+// lexicographic order is chronological. Tags are hex-encoded so they cannot contain the separator.
+// Both indexes are written in the same message as the evidence record itself,
+// so they cannot drift from it short of a trap, which rolls back all of them.
+//
+// Evidence stored before the indexes existed is indexed by a background
+// migration. Until it completes, whole-relationship reads fall back to
+// scanning EVIDENCE_STORE; timeline pages may still be incomplete.
+
+const DEFAULT_TIMELINE_PAGE_SIZE: u32 = 20;
+const MAX_TIMELINE_PAGE_SIZE: u32 = 100;
+
+fn primary_key(relationship_id: &str, timestamp: u64, evidence_id: &str) -> String {
+    format!("{}:{:020}:{}", relationship_id, timestamp, evidence_id)
+}
+
+fn upload_key(relationship_id: &str, upload_timestamp: u64, evidence_id: &str) -> String {
+    format!("{}:{:020}:{}", relationship_id, upload_timestamp, evidence_id)
+}
+
+fn tag_scope(relationship_id: &str, tag: &str) -> String {
+    format!("{}:{}", relationship_id, hex::encode(tag))
+}
+
+fn tag_key(relationship_id: &str, tag: &str, timestamp: u64, evidence_id: &str) -> String {
+    format!("{}:{:020}:{}", tag_scope(relationship_id, tag), timestamp, evidence_id)
+}
+
+/// Keys of `scope` whose timestamp lies in `[start, end]`
+fn timestamp_range(scope: &str, start: Option<u64>, end: Option<u64>) -> Range<String> {
+    let start = format!("{}:{:020}:", scope, start.unwrap_or(0));
+    let end = format!("{}:{:020};", scope, end.unwrap_or(u64::MAX)); // ';' sorts directly after ':'
+    start..end
+}
+
+/// Keep a scope's count in step with its index entries
+fn adjust_evidence_count(scope: String, added: bool) {
+    with_evidence_count_store(|counts| {
+        let count = counts.get(&scope).unwrap_or(0);
+        let count = if added { count + 1 } else { count.saturating_sub(1) };
+        if count == 0 {
+            counts.remove(&scope);
+        } else {
+            counts.insert(scope, count);
+        }
+    });
+}
+
+/// Indexing is idempotent: counts only move for entries that are new
+pub fn index_evidence(evidence: &Evidence) {
+    let key = primary_key(&evidence.relationship_id, evidence.metadata.timestamp, &evidence.id);
+
+    let mut tags = evidence.metadata.tags.clone();
+    tags.sort();
+    tags.dedup();
+
+    for tag in &tags {
+        let tag_entry = tag_key(&evidence.relationship_id, tag, evidence.metadata.timestamp, &evidence.id);
+        if with_evidence_tag_index_store(|index| index.insert(tag_entry, key.clone())).is_none() {
+            adjust_evidence_count(tag_scope(&evidence.relationship_id, tag), true);
+        }
+    }
+
+    with_evidence_upload_index_store(|index| {
+        index.insert(upload_key(&evidence.relationship_id, evidence.upload_timestamp, &evidence.id), key.clone());
+    });
+
+    if with_evidence_index_store(|index| index.insert(key, summarize_evidence(evidence.clone()))).is_none() {
+        adjust_evidence_count(evidence.relationship_id.clone(), true);
+    }
+}
+
+pub fn unindex_evidence(evidence: &Evidence) {
+    let key = primary_key(&evidence.relationship_id, evidence.metadata.timestamp, &evidence.id);
+    if with_evidence_index_store(|index| index.remove(&key)).is_some() {
+        adjust_evidence_count(evidence.relationship_id.clone(), false);
+    }
+
+    for tag in &evidence.metadata.tags {
+        let tag_entry = tag_key(&evidence.relationship_id, tag, evidence.metadata.timestamp, &evidence.id);
+        if with_evidence_tag_index_store(|index| index.remove(&tag_entry)).is_some() {
+            adjust_evidence_count(tag_scope(&evidence.relationship_id, tag), false);
+        }
+    }
+
+    with_evidence_upload_index_store(|index| {
+        index.remove(&upload_key(&evidence.relationship_id, evidence.upload_timestamp, &evidence.id));
+    });
+}
+
+/// Evidence of a relationship with `metadata.timestamp` in `[start, end]`,
+/// oldest first
+pub fn relationship_evidence(relationship_id: &str, start: Option<u64>, end: Option<u64>) -> Vec<EvidenceSummary> {
+    // Kill switch, account deletion and key rotation must see every item
+    if !indexes_complete() {
+        return scan_relationship_evidence(relationship_id, start, end);
+    }
+
+    with_evidence_index_store_read(|index| {
+        index
+            .range(timestamp_range(relationship_id, start, end))
+            .map(|(_, summary)| summary)
+            .collect()
+    })
+}
+
+/// Up to `limit` of a relationship's evidence, newest `metadata.timestamp` first
+pub fn latest_relationship_evidence(relationship_id: &str, limit: usize) -> Vec<EvidenceSummary> {
+    if !indexes_complete() {
+        let mut evidence = scan_relationship_evidence(relationship_id, None, None);
+        evidence.reverse();
+        evidence.truncate(limit);
+        return evidence;
+    }
+
+    with_evidence_index_store_read(|index| {
+        index
+            .range(timestamp_range(relationship_id, None, None))
+            .rev()
+            .take(limit)
+            .map(|(_, summary)| summary)
+            .collect()
+    })
+}
+
+pub fn relationship_evidence_ids(relationship_id: &str) -> Vec<String> {
+    relationship_evidence(relationship_id, None, None)
+        .into_iter()
+        .map(|summary| summary.id)
+        .collect()
+}
+
+/// Migration id of the index backfill (see `migrations.rs`)
+pub const EVIDENCE_INDEX_MIGRATION: &str = "0004_evidence_indexes";
+
+/// Whether evidence stored before the indexes existed has been indexed
+fn indexes_complete() -> bool {
+    crate::migrations::migration_completed(EVIDENCE_INDEX_MIGRATION)
+}
+
+/// Relationship evidence straight from EVIDENCE_STORE, oldest first, for
+/// use while the backfill is still running
+fn scan_relationship_evidence(relationship_id: &str, start: Option<u64>, end: Option<u64>) -> Vec<EvidenceSummary> {
+    let mut evidence: Vec<EvidenceSummary> = with_evidence_store_read(|store| {
+        store
+            .iter()
+            .map(|(_, evidence)| evidence)
+            .filter(|evidence| evidence.relationship_id == relationship_id)
+            .filter(|evidence| start.is_none_or(|start| evidence.metadata.timestamp >= start))
+            .filter(|evidence| end.is_none_or(|end| evidence.metadata.timestamp <= end))
+            .map(summarize_evidence)
+            .collect()
+    });
+    evidence.sort_by(|a, b| (a.metadata.timestamp, &a.id).cmp(&(b.metadata.timestamp, &b.id)));
+    evidence
+}
+
+/// Index the next batch of stored evidence after `cursor`. Indexing is
+/// idempotent, so evidence written meanwhile (and already indexed by
+/// `record_evidence`) is unaffected.
+pub(crate) fn index_existing_evidence(cursor: Option<String>, batch_size: usize) -> (Option<String>, u64) {
+    let start = match cursor {
+        Some(evidence_id) => Bound::Excluded(evidence_id),
+        None => Bound::Unbounded,
+    };
+
+    let batch: Vec<Evidence> = with_evidence_store_read(|store| {
+        store
+            .range((start, Bound::Unbounded))
+            .take(batch_size)
+            .map(|(_, evidence)| evidence)
+            .collect()
+    });
+    for evidence in &batch {
+        index_evidence(evidence);
+    }
+
+    let processed = batch.len() as u64;
+    let cursor = if batch.len() < batch_size { None } else { batch.last().map(|evidence| evidence.id.clone()) };
+    (cursor, processed)
+}
+
+// ==================
+// CURSOR PAGINATION
+// ==================
+// Timelines are returned newest first. A cursor names the position of an
+// entry, `(sort timestamp, evidence_id)`, so paging continues from exactly
+// that entry regardless of evidence added or removed in the meantime.
+
+pub struct TimelineFilter<'a> {
+    pub relationship_id: &'a str,
+    pub sort: TimelineSortField,
+    pub tag: Option<&'a str>,
+    pub start: Option<u64>, // Inclusive bound on metadata.timestamp
+    pub end: Option<u64>,   // Inclusive bound on metadata.timestamp
+}
+
+impl TimelineFilter<'_> {
+    /// The index to walk and the key scope within it
+    fn scope(&self) -> String {
+        match (self.sort, self.tag) {
+            (TimelineSortField::MetadataTimestamp, Some(tag)) => tag_scope(self.relationship_id, tag),
+            _ => self.relationship_id.to_string(),
+        }
+    }
+
+    /// Index key range for the filter; the upload index cannot narrow by
+    /// capture date, so its range covers the whole relationship
+    fn base_range(&self) -> Range<String> {
+        match self.sort {
+            TimelineSortField::MetadataTimestamp => timestamp_range(&self.scope(), self.start, self.end),
+            TimelineSortField::UploadTimestamp => timestamp_range(&self.scope(), None, None),
+        }
+    }
+
+    /// Key of the maintained count covering the filter, ignoring any date range
+    fn count_scope(&self) -> String {
+        match self.tag {
+            Some(tag) => tag_scope(self.relationship_id, tag),
+            None => self.relationship_id.to_string(),
+        }
+    }
+
+    fn sort_timestamp(&self, summary: &EvidenceSummary) -> u64 {
+        match self.sort {
+            TimelineSortField::MetadataTimestamp => summary.metadata.timestamp,
+            TimelineSortField::UploadTimestamp => summary.upload_timestamp,
+        }
+    }
+
+    fn position(&self, summary: &EvidenceSummary) -> String {
+        format!("{}:{:020}:{}", self.scope(), self.sort_timestamp(summary), summary.id)
+    }
+
+    fn matches(&self, summary: &EvidenceSummary) -> bool {
+        self.tag.is_none_or(|tag| summary.metadata.tags.iter().any(|t| t == tag))
+            && self.start.is_none_or(|start| summary.metadata.timestamp >= start)
+            && self.end.is_none_or(|end| summary.metadata.timestamp <= end)
+    }
+
+    /// Visit matching evidence in `range`, stopping when `visit` returns false
+    fn visit(
+        &self,
+        range: (Bound<String>, Bound<String>),
+        descending: bool,
+        mut visit: impl FnMut(EvidenceSummary) -> bool,
+    ) {
+        fn walk<V>(
+            map: &ic_stable_structures::StableBTreeMap<String, V, Memory>,
+            range: (Bound<String>, Bound<String>),
+            descending: bool,
+        ) -> Box<dyn Iterator<Item = (String, V)> + '_>
+        where
+            V: ic_stable_structures::Storable,
+        {
+            if descending {
+                Box::new(map.range(range).rev())
+            } else {
+                Box::new(map.range(range))
+            }
+        }
+
+        // A cursor outside the filter's range leaves nothing to visit
+        match (&range.0, &range.1) {
+            (Bound::Included(lower), Bound::Excluded(upper)) | (Bound::Excluded(lower), Bound::Excluded(upper))
+                if lower >= upper =>
+            {
+                return;
+            }
+            _ => {}
+        }
+
+        let mut visit_summary = |summary: EvidenceSummary| !self.matches(&summary) || visit(summary);
+
+        match (self.sort, self.tag) {
+            (TimelineSortField::MetadataTimestamp, None) => with_evidence_index_store_read(|index| {
+                for (_, summary) in walk(index, range, descending) {
+                    if !visit_summary(summary) {
+                        break;
+                    }
+                }
+            }),
+            (TimelineSortField::MetadataTimestamp, Some(_)) => with_evidence_tag_index_store_read(|tags| {
+                for (_, key) in walk(tags, range, descending) {
+                    let Some(summary) = with_evidence_index_store_read(|index| index.get(&key)) else {
+                        continue;
+                    };
+                    if !visit_summary(summary) {
+                        break;
+                    }
+                }
+            }),
+            (TimelineSortField::UploadTimestamp, _) => with_evidence_upload_index_store_read(|uploads| {
+                for (_, key) in walk(uploads, range, descending) {
+                    let Some(summary) = with_evidence_index_store_read(|index| index.get(&key)) else {
+                        continue;
+                    };
+                    if !visit_summary(summary) {
+                        break;
+                    }
+                }
+            }),
+        }
+    }
+
+    /// Whether any matching entry lies strictly older (`older == true`) or
+    /// strictly newer than `position`
+    fn has_beyond(&self, position: &str, older: bool) -> bool {
+        let base = self.base_range();
+        let range = if older {
+            (Bound::Included(base.start), Bound::Excluded(position.to_string()))
+        } else {
+            (Bound::Excluded(position.to_string()), Bound::Excluded(base.end))
+        };
+
+        let mut found = false;
+        self.visit(range, older, |_| {
+            found = true;
+            false
+        });
+        found
+    }
+}
+
+fn sort_code(sort: TimelineSortField) -> &'static str {
+    match sort {
+        TimelineSortField::MetadataTimestamp => "m",
+        TimelineSortField::UploadTimestamp => "u",
+    }
+}
+
+fn encode_cursor(sort: TimelineSortField, timestamp: u64, evidence_id: &str) -> String {
+    hex::encode(format!("{}:{}:{}", sort_code(sort), timestamp, evidence_id))
+}
+
+fn decode_cursor(cursor: &str, sort: TimelineSortField) -> Result<(u64, String), BondedError> {
+    let invalid = || BondedError::invalid_input("cursor", "Invalid timeline cursor");
+
+    let bytes = hex::decode(cursor).map_err(|_| invalid())?;
+    let text = String::from_utf8(bytes).map_err(|_| invalid())?;
+    let mut parts = text.splitn(3, ':');
+    let (Some(code), Some(timestamp), Some(evidence_id)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+
+    if code != sort_code(sort) {
+        return Err(BondedError::invalid_input("cursor", "Cursor was issued for a different sort order"));
+    }
+
+    let timestamp = timestamp.parse::<u64>().map_err(|_| invalid())?;
+    Ok((timestamp, evidence_id.to_string()))
+}
+
+/// One page of a relationship's timeline. Without a cursor the page starts
+/// at the newest entry, `page` pages in (legacy offset paging); with one,
+/// `page` is ignored.
+pub fn page_relationship_evidence(
+    filter: &TimelineFilter,
+    cursor: Option<&str>,
+    direction: PageDirection,
+    page: u32,
+    page_size: Option<u32>,
+) -> Result<TimelineResponse, BondedError> {
+    let limit = page_size.unwrap_or(DEFAULT_TIMELINE_PAGE_SIZE).clamp(1, MAX_TIMELINE_PAGE_SIZE) as usize;
+    let base = filter.base_range();
+
+    // usize is 32 bits on wasm32, so the offset is computed in u64
+    let offset = (page as u64)
+        .checked_mul(limit as u64)
+        .and_then(|offset| usize::try_from(offset).ok())
+        .ok_or_else(|| BondedError::invalid_input("page", "Page is too far into the timeline"))?;
+
+    let (range, descending, mut skip) = match cursor {
+        Some(cursor) => {
+            let (timestamp, evidence_id) = decode_cursor(cursor, filter.sort)?;
+            let position = format!("{}:{:020}:{}", filter.scope(), timestamp, evidence_id);
+            match direction {
+                PageDirection::Next => (
+                    (Bound::Included(base.start.clone()), Bound::Excluded(position.min(base.end.clone()))),
+                    true,
+                    0,
+                ),
+                PageDirection::Previous => {
+                    let lower = if position >= base.start {
+                        Bound::Excluded(position)
+                    } else {
+                        Bound::Included(base.start.clone())
+                    };
+                    ((lower, Bound::Excluded(base.end.clone())), false, 0)
+                }
+            }
+        }
+        None => (
+            (Bound::Included(base.start.clone()), Bound::Excluded(base.end.clone())),
+            true,
+            offset,
+        ),
+    };
+
+    let mut evidence = Vec::with_capacity(limit + 1);
+    filter.visit(range, descending, |summary| {
+        if skip > 0 {
+            skip -= 1;
+            return true;
+        }
+        evidence.push(summary);
+        evidence.len() <= limit
+    });
+
+    let has_more = evidence.len() > limit;
+    evidence.truncate(limit);
+    if !descending {
+        evidence.reverse();
+    }
+
+    let cursor_for = |summary: &EvidenceSummary| encode_cursor(filter.sort, filter.sort_timestamp(summary), &summary.id);
+    let next_cursor = evidence
+        .last()
+        .filter(|last| if descending { has_more } else { filter.has_beyond(&filter.position(last), true) })
+        .map(cursor_for);
+    let prev_cursor = evidence
+        .first()
+        .filter(|first| if descending { filter.has_beyond(&filter.position(first), false) } else { has_more })
+        .map(cursor_for);
+
+    // Whole relationships and tags have maintained counts; a date range has
+    // to be counted, so that only happens for the first page
+    let total_count = if filter.start.is_none() && filter.end.is_none() {
+        Some(with_evidence_count_store_read(|counts| counts.get(&filter.count_scope())).unwrap_or(0))
+    } else if cursor.is_none() {
+        let mut count = 0u64;
+        filter.visit((Bound::Included(base.start), Bound::Excluded(base.end)), true, |_| {
+            count += 1;
+            true
+        });
+        Some(count)
+    } else {
+        None
+    };
+
+    Ok(TimelineResponse {
+        evidence,
+        total_count,
+        has_more,
+        next_cursor,
+        prev_cursor,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    const RELATIONSHIP_ID: &str = "relationship_1";
+    const PAGE_SIZE: u32 = 3;
+
+    fn evidence(id: &str, timestamp: u64, tags: &[&str]) -> Evidence {
+        Evidence {
+            id: id.to_string(),
+            relationship_id: RELATIONSHIP_ID.to_string(),
+            encrypted_data: vec![1, 2, 3],
+            metadata: EvidenceMetadata {
+                timestamp,
+                content_type: "image/jpeg".to_string(),
+                location: None,
+                description: None,
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            },
+            upload_timestamp: timestamp,
+            hash: String::new(),
+            uploader: Principal::anonymous(),
+            signature: None,
+            signed_at: None,
+            content: None,
+            countersignature: None,
+        }
+    }
+
+    fn filter(tag: Option<&str>) -> TimelineFilter<'_> {
+        TimelineFilter {
+            relationship_id: RELATIONSHIP_ID,
+            sort: TimelineSortField::MetadataTimestamp,
+            tag,
+            start: None,
+            end: None,
+        }
+    }
+
+    /// Follow `next_cursor` to the end, calling `between_pages` after each
+    /// page that has a successor; returns the evidence IDs in page order
+    fn walk_timeline(filter: &TimelineFilter, mut between_pages: impl FnMut(usize)) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        for page in 0.. {
+            let response = page_relationship_evidence(filter, cursor.as_deref(), PageDirection::Next, 0, Some(PAGE_SIZE)).unwrap();
+            assert!(response.evidence.len() <= PAGE_SIZE as usize);
+            seen.extend(response.evidence.into_iter().map(|summary| summary.id));
+            match response.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+            between_pages(page);
+        }
+        seen
+    }
+
+    #[test]
+    fn cursor_walk_visits_equal_timestamps_exactly_once() {
+        let items = [
+            evidence("a", 100, &["trip"]),
+            evidence("b", 100, &[]),
+            evidence("c", 100, &["trip"]),
+            evidence("d", 200, &["trip"]),
+            evidence("e", 200, &[]),
+            evidence("f", 300, &["trip"]),
+            evidence("g", 300, &["trip"]),
+        ];
+        for item in &items {
+            index_evidence(item);
+        }
+
+        // Newest first; equal timestamps fall back to the evidence ID
+        assert_eq!(walk_timeline(&filter(None), |_| {}), ["g", "f", "e", "d", "c", "b", "a"]);
+        assert_eq!(walk_timeline(&filter(Some("trip")), |_| {}), ["g", "f", "d", "c", "a"]);
+    }
+
+    #[test]
+    fn cursor_walk_survives_inserts_and_deletes_between_pages() {
+        let items: Vec<Evidence> = (1..=9).map(|n| evidence(&format!("e{}", n), n * 10, &[])).collect();
+        for item in &items {
+            index_evidence(item);
+        }
+
+        let seen = walk_timeline(&filter(None), |page| {
+            if page == 0 {
+                // Newer than the cursor: belongs to earlier pages, not this walk
+                index_evidence(&evidence("newer", 1_000, &[]));
+                // Older than the cursor: still ahead, so it must show up
+                index_evidence(&evidence("older", 5, &[]));
+                // Already returned, and not yet reached
+                unindex_evidence(&items[8]);
+                unindex_evidence(&items[1]);
+            }
+        });
+
+        assert_eq!(seen, ["e9", "e8", "e7", "e6", "e5", "e4", "e3", "e1", "older"]);
+    }
+
+    #[test]
+    fn previous_pages_retrace_the_walk() {
+        for n in 1..=7 {
+            index_evidence(&evidence(&format!("e{}", n), 100, &[]));
+        }
+
+        let last_page = {
+            let mut cursor = None;
+            loop {
+                let response = page_relationship_evidence(&filter(None), cursor.as_deref(), PageDirection::Next, 0, Some(PAGE_SIZE)).unwrap();
+                match response.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break response,
+                }
+            }
+        };
+
+        let mut seen: Vec<String> = last_page.evidence.iter().map(|summary| summary.id.clone()).collect();
+        let mut cursor = last_page.prev_cursor;
+        while let Some(previous) = cursor {
+            let response = page_relationship_evidence(&filter(None), Some(&previous), PageDirection::Previous, 0, Some(PAGE_SIZE)).unwrap();
+            let mut page: Vec<String> = response.evidence.into_iter().map(|summary| summary.id).collect();
+            page.extend(seen);
+            seen = page;
+            cursor = response.prev_cursor;
+        }
+
+        assert_eq!(seen, ["e7", "e6", "e5", "e4", "e3", "e2", "e1"]);
+    }
+
+    fn count(scope: String) -> Option<u64> {
+        with_evidence_count_store_read(|counts| counts.get(&scope))
+    }
+
+    fn tag_entries(tag: &str) -> Vec<String> {
+        with_evidence_tag_index_store_read(|index| {
+            index.range(timestamp_range(&tag_scope(RELATIONSHIP_ID, tag), None, None)).map(|(key, _)| key).collect()
+        })
+    }
+
+    fn primary_entries() -> Vec<String> {
+        with_evidence_index_store_read(|index| {
+            index.range(timestamp_range(RELATIONSHIP_ID, None, None)).map(|(_, summary)| summary.id).collect()
+        })
+    }
+
+    fn upload_entries() -> Vec<String> {
+        with_evidence_upload_index_store_read(|index| {
+            index.range(timestamp_range(RELATIONSHIP_ID, None, None)).map(|(_, primary)| primary).collect()
+        })
+    }
+
+    #[test]
+    fn indexes_stay_consistent_after_delete() {
+        let items = [
+            evidence("a", 100, &["trip", "trip"]),
+            evidence("b", 200, &["trip", "home"]),
+            evidence("c", 300, &["home"]),
+        ];
+        for item in &items {
+            index_evidence(item);
+        }
+        // Re-indexing must not double count
+        index_evidence(&items[1]);
+
+        assert_eq!(count(RELATIONSHIP_ID.to_string()), Some(3));
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "trip")), Some(2));
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "home")), Some(2));
+
+        unindex_evidence(&items[1]);
+
+        assert_eq!(primary_entries(), ["a", "c"]);
+        assert_eq!(count(RELATIONSHIP_ID.to_string()), Some(2));
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "trip")), Some(1));
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "home")), Some(1));
+        assert_eq!(tag_entries("trip"), [tag_key(RELATIONSHIP_ID, "trip", 100, "a")]);
+        assert_eq!(tag_entries("home"), [tag_key(RELATIONSHIP_ID, "home", 300, "c")]);
+        assert_eq!(upload_entries(), [primary_key(RELATIONSHIP_ID, 100, "a"), primary_key(RELATIONSHIP_ID, 300, "c")]);
+
+        // Deleting again is a no-op rather than an undercount
+        unindex_evidence(&items[1]);
+        assert_eq!(count(RELATIONSHIP_ID.to_string()), Some(2));
+
+        unindex_evidence(&items[0]);
+        unindex_evidence(&items[2]);
+
+        assert!(primary_entries().is_empty());
+        assert_eq!(count(RELATIONSHIP_ID.to_string()), None);
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "trip")), None);
+        assert_eq!(count(tag_scope(RELATIONSHIP_ID, "home")), None);
+        assert!(tag_entries("trip").is_empty());
+        assert!(tag_entries("home").is_empty());
+        assert!(upload_entries().is_empty());
+    }
+}
@@ -0,0 +1,444 @@
+use crate::evidence_index::relationship_evidence_ids;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use crate::vetkd::relationship_public_key;
+use candid::Principal;
+use ic_cdk_macros::{query, update};
+
+// =========================
+// RELATIONSHIP KEY ROTATION
+// =========================
+// Evidence is encrypted under a per-item data-encryption key (DEK); clients
+// store that DEK wrapped under the current relationship key. Rotating the
+// relationship key moves to the next key version: vetKD relationships derive
+// it (see `vetkd.rs`), older ones are issued fresh shares. The previous
+// version's key and wrapped DEKs stay available until every item
+// has been re-wrapped under the new version and both partners have
+// acknowledged the rotation; only then is the old version retired.
+//
+// One rotation is in progress at a time.
+
+const MAX_WRAPPED_KEY_SIZE: usize = 512;
+const MAX_WRAPPED_KEYS_PER_CALL: usize = 500;
+
+pub fn current_key_version(relationship: &Relationship) -> u32 {
+    relationship.relationship_keys.last().map_or(1, |key| key.version)
+}
+
+/// Entry for a vetKD key version. `public_key` is the relationship context's
+/// vetKD public key (see `vetkd::relationship_public_key`), under which the
+/// version's key verifies for its derivation input.
+pub(crate) fn vetkd_relationship_key(version: u32, public_key: Vec<u8>, activated_at: u64) -> RelationshipKey {
+    RelationshipKey {
+        version,
+        public_key,
+        activated_at,
+        revoked_at: None,
+    }
+}
+
+/// Key version that was current at `at`, e.g. when a chunked upload began
+pub fn key_version_at(relationship: &Relationship, at: u64) -> u32 {
+    crate::attestation::relationship_key_at(relationship, at)
+        .map_or_else(|| current_key_version(relationship), |key| key.version)
+}
+
+pub fn validate_wrapped_key(wrapped_key: &[u8]) -> Result<(), BondedError> {
+    if wrapped_key.is_empty() || wrapped_key.len() > MAX_WRAPPED_KEY_SIZE {
+        return Err(BondedError::invalid_input(
+            "wrapped_key",
+            format!("Wrapped key must be between 1 and {} bytes", MAX_WRAPPED_KEY_SIZE),
+        ));
+    }
+    Ok(())
+}
+
+fn wrapped_key_id(evidence_id: &str, key_version: u32) -> String {
+    format!("{}:{:010}", evidence_id, key_version)
+}
+
+fn wrapped_key_prefix(evidence_id: &str) -> String {
+    format!("{}:", evidence_id)
+}
+
+fn put_wrapped_key(relationship_id: &str, evidence_id: &str, key_version: u32, wrapped_key: Vec<u8>) {
+    let wrapped = WrappedKey {
+        evidence_id: evidence_id.to_string(),
+        relationship_id: relationship_id.to_string(),
+        key_version,
+        wrapped_key,
+        created_at: current_time(),
+    };
+    with_wrapped_key_store(|store| {
+        store.insert(wrapped_key_id(evidence_id, key_version), wrapped);
+    });
+}
+
+/// Record the DEK wrap supplied with a new upload
+pub(crate) fn store_initial_wrapped_key(relationship_id: &str, evidence_id: &str, key_version: u32, wrapped_key: Vec<u8>) {
+    put_wrapped_key(relationship_id, evidence_id, key_version, wrapped_key);
+}
+
+fn has_wrapped_key(evidence_id: &str, key_version: u32) -> bool {
+    with_wrapped_key_store_read(|store| store.contains_key(&wrapped_key_id(evidence_id, key_version)))
+}
+
+/// Remove every wrapped DEK of an evidence item, across key versions
+pub(crate) fn remove_wrapped_keys(evidence_id: &str) {
+    let prefix = wrapped_key_prefix(evidence_id);
+    with_wrapped_key_store(|store| {
+        let keys: Vec<String> = store
+            .range(prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .collect();
+        for key in keys {
+            store.remove(&key);
+        }
+    });
+}
+
+/// Items wrapped under the rotation's previous version but not yet under the new one
+fn pending_rewrap(relationship_id: &str, rotation: &KeyRotation) -> u64 {
+    relationship_evidence_ids(relationship_id)
+        .iter()
+        .filter(|evidence_id| {
+            has_wrapped_key(evidence_id, rotation.from_version) && !has_wrapped_key(evidence_id, rotation.to_version)
+        })
+        .count() as u64
+}
+
+fn rotation_status(relationship: &Relationship) -> KeyRotationStatus {
+    KeyRotationStatus {
+        relationship_id: relationship.id.clone(),
+        key_version: current_key_version(relationship),
+        pending_rewrap: relationship
+            .key_rotation
+            .as_ref()
+            .map_or(0, |rotation| pending_rewrap(&relationship.id, rotation)),
+        rotation: relationship.key_rotation.clone(),
+    }
+}
+
+fn partners(relationship: &Relationship) -> Vec<Principal> {
+    [Some(relationship.partner1), relationship.partner2].into_iter().flatten().collect()
+}
+
+/// Drop the previous key version's shares and wrapped DEKs
+fn retire_previous_key_version(relationship: &mut Relationship) {
+    let Some(rotation) = relationship.key_rotation.take() else {
+        return;
+    };
+
+    for partner in partners(relationship) {
+        remove_archived_key_share(partner, &relationship.id, rotation.from_version);
+    }
+    with_wrapped_key_store(|store| {
+        for evidence_id in relationship_evidence_ids(&relationship.id) {
+            store.remove(&wrapped_key_id(&evidence_id, rotation.from_version));
+        }
+    });
+    remove_bonded_key_share(&relationship.id, rotation.from_version);
+}
+
+// =========================
+// ROTATION ENDPOINTS
+// =========================
+
+/// Start a rotation to the next key version. vetKD relationships derive the
+/// new key with `derive_vetkd_key`; older ones get fresh shares, the caller's
+/// returned here and the partner's through `get_key_share`.
+#[update]
+pub async fn rotate_relationship_key(relationship_id: String) -> BondedResult<KeyRotationResponse> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "rotate_relationship_key") {
+        return BondedResult::err(msg);
+    }
+    
+    // A relationship's key scheme never changes, so the vetKD public key can
+    // be fetched before the relationship is loaded and nothing below spans the call
+    let is_vetkd = with_relationship_store_read(|store| store.get(&relationship_id))
+        .is_some_and(|rel| rel.key_scheme == RelationshipKeyScheme::VetKd);
+    let vetkd_public_key = if is_vetkd {
+        match relationship_public_key().await {
+            Ok(public_key) => public_key,
+            Err(msg) => return BondedResult::err(msg),
+        }
+    } else {
+        Vec::new()
+    };
+    let now = current_time();
+
+    let mut relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let partner2 = match (&relationship.status, relationship.partner2) {
+        (RelationshipStatus::Active, Some(partner2)) => partner2,
+        _ => return BondedResult::err(BondedError::conflict("Only active relationships can rotate their key")),
+    };
+
+    if let Some(rotation) = &relationship.key_rotation {
+        return BondedResult::err(BondedError::conflict(format!(
+            "Rotation to key version {} is still in progress",
+            rotation.to_version
+        )));
+    }
+
+    let from_version = current_key_version(&relationship);
+    let to_version = from_version + 1;
+
+    let (new_key, user_key_share) = match relationship.key_scheme {
+        RelationshipKeyScheme::VetKd => (vetkd_relationship_key(to_version, vetkd_public_key, now), Vec::new()),
+        RelationshipKeyScheme::ThresholdShares => {
+            // Fails closed until the entropy pool has been seeded from raw_rand
+            let master_key = match generate_master_key() {
+                Ok(key) => key,
+                Err(e) => return BondedResult::err(BondedError::unavailable(format!("Failed to generate master key: {}", e))),
+            };
+            let shares = match split_key_threshold(&master_key) {
+                Ok(shares) => shares,
+                Err(e) => return BondedResult::err(BondedError::internal(format!("Failed to generate key shares: {}", e))),
+            };
+            let public_key = match derive_public_key(&master_key) {
+                Ok(pk) => pk,
+                Err(e) => return BondedResult::err(BondedError::internal(format!("Failed to derive public key: {}", e))),
+            };
+
+            // Shares of the previous version are archived by store_user_key_share
+            for (partner, share) in [(relationship.partner1, &shares.partner1), (partner2, &shares.partner2)] {
+                if let Err(e) = store_user_key_share(partner, &relationship_id, share.clone(), to_version) {
+                    return BondedResult::err(BondedError::internal(format!("Failed to store key share: {}", e)));
+                }
+            }
+
+            store_bonded_key_share(&relationship_id, to_version, shares.bonded);
+
+            let user_key_share = if caller == partner2 { shares.partner2 } else { shares.partner1 };
+            let new_key = RelationshipKey {
+                version: to_version,
+                public_key,
+                activated_at: now,
+                revoked_at: None,
+            };
+            (new_key, user_key_share)
+        }
+    };
+    let public_key = new_key.public_key.clone();
+
+    // First-release relationships never recorded their version 1 key; add
+    // it so that lookups for times before this rotation still resolve
+    if relationship.relationship_keys.is_empty() {
+        relationship.relationship_keys.push(RelationshipKey {
+            version: from_version,
+            public_key: Vec::new(),
+            activated_at: relationship.created_at,
+            revoked_at: None,
+        });
+    }
+    if let Some(previous) = relationship.relationship_keys.last_mut() {
+        previous.revoked_at = Some(now);
+    }
+    relationship.relationship_keys.push(new_key);
+    relationship.key_rotation = Some(KeyRotation {
+        from_version,
+        to_version,
+        started_at: now,
+        started_by: caller,
+        acknowledged_by: Vec::new(),
+    });
+    relationship.last_activity = now;
+
+    with_relationship_store(|store| {
+        store.insert(relationship_id.clone(), relationship);
+    });
+
+    log_relationship_audit_event(caller, &relationship_id, "rotate_relationship_key", Some(format!("key_version:{}", to_version)));
+
+    BondedResult::ok(KeyRotationResponse {
+        relationship_id,
+        key_version: to_version,
+        user_key_share,
+        public_key,
+    })
+}
+
+/// Store DEKs re-wrapped (or newly wrapped) under the current key version
+#[update]
+pub fn store_wrapped_keys(relationship_id: String, key_version: u32, wrapped_keys: Vec<WrappedKeyInput>) -> BondedResult<KeyRotationStatus> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "store_wrapped_keys") {
+        return BondedResult::err(msg);
+    }
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    if key_version != current_key_version(&relationship) {
+        return BondedResult::err(BondedError::invalid_input("key_version", "Keys can only be wrapped under the current key version"));
+    }
+
+    if wrapped_keys.len() > MAX_WRAPPED_KEYS_PER_CALL {
+        return BondedResult::err(BondedError::invalid_input(
+            "wrapped_keys",
+            format!("At most {} wrapped keys per call", MAX_WRAPPED_KEYS_PER_CALL),
+        ));
+    }
+
+    for input in &wrapped_keys {
+        if let Err(msg) = validate_wrapped_key(&input.wrapped_key) {
+            return BondedResult::err(msg);
+        }
+        let belongs = with_evidence_store_read(|store| store.get(&input.evidence_id))
+            .is_some_and(|evidence| evidence.relationship_id == relationship_id);
+        if !belongs {
+            return BondedResult::err(BondedError::not_found(format!("Evidence {}", input.evidence_id)));
+        }
+    }
+
+    let stored = wrapped_keys.len();
+    for input in wrapped_keys {
+        put_wrapped_key(&relationship_id, &input.evidence_id, key_version, input.wrapped_key);
+    }
+
+    log_relationship_audit_event(caller, &relationship_id, "store_wrapped_keys", Some(format!("key_version:{},count:{}", key_version, stored)));
+
+    BondedResult::ok(rotation_status(&relationship))
+}
+
+/// An evidence item's wrapped DEK under `key_version` (default: current)
+#[query]
+pub fn get_wrapped_key(evidence_id: String, key_version: Option<u32>) -> BondedResult<WrappedKey> {
+    let caller = caller_principal();
+
+    let evidence = match with_evidence_store_read(|store| store.get(&evidence_id)) {
+        Some(ev) => ev,
+        None => return BondedResult::err(BondedError::not_found("Evidence")),
+    };
+
+    let relationship = match with_relationship_store_read(|store| store.get(&evidence.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let key_version = key_version.unwrap_or_else(|| current_key_version(&relationship));
+    match with_wrapped_key_store_read(|store| store.get(&wrapped_key_id(&evidence_id, key_version))) {
+        Some(wrapped) => BondedResult::ok(wrapped),
+        None => BondedResult::err(BondedError::not_found("Wrapped key")),
+    }
+}
+
+/// The caller's share of a specific key version, e.g. the previous version
+/// while re-wrapping during a rotation
+#[query]
+pub fn get_key_share_version(relationship_id: String, key_version: u32) -> BondedResult<UserKeyShare> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    match get_user_key_share_version(caller, &relationship_id, key_version) {
+        Some(share) => BondedResult::ok(share),
+        None => BondedResult::err(BondedError::not_found("Key share")),
+    }
+}
+
+#[query]
+pub fn get_key_rotation_status(relationship_id: String) -> BondedResult<KeyRotationStatus> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    BondedResult::ok(rotation_status(&relationship))
+}
+
+/// Confirm that the caller holds the new key version and no longer needs the
+/// previous one. Once both partners have acknowledged and every item is
+/// wrapped under the new version, the previous version is retired.
+#[update]
+pub fn acknowledge_key_rotation(relationship_id: String, key_version: u32) -> BondedResult<KeyRotationStatus> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "acknowledge_key_rotation") {
+        return BondedResult::err(msg);
+    }
+
+    let mut relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let rotation = match relationship.key_rotation.as_mut() {
+        Some(rotation) => rotation,
+        None => return BondedResult::err(BondedError::not_found("Key rotation")),
+    };
+
+    if rotation.to_version != key_version {
+        return BondedResult::err(BondedError::invalid_input("key_version", format!("Rotation in progress is to key version {}", rotation.to_version)));
+    }
+
+    let pending = pending_rewrap(&relationship_id, rotation);
+    if pending > 0 {
+        return BondedResult::err(BondedError::conflict(format!(
+            "{} evidence items are not yet wrapped under key version {}",
+            pending, key_version
+        )));
+    }
+
+    if !rotation.acknowledged_by.contains(&caller) {
+        rotation.acknowledged_by.push(caller);
+    }
+
+    let all_acknowledged = partners(&relationship)
+        .iter()
+        .all(|partner| relationship.key_rotation.as_ref().is_some_and(|rotation| rotation.acknowledged_by.contains(partner)));
+    if all_acknowledged {
+        retire_previous_key_version(&mut relationship);
+        log_relationship_audit_event(caller, &relationship_id, "retire_relationship_key", Some(format!("key_version:{}", key_version - 1)));
+    } else {
+        log_relationship_audit_event(caller, &relationship_id, "acknowledge_key_rotation", Some(format!("key_version:{}", key_version)));
+    }
+
+    relationship.last_activity = current_time();
+    with_relationship_store(|store| {
+        store.insert(relationship_id.clone(), relationship.clone());
+    });
+
+    BondedResult::ok(rotation_status(&relationship))
+}
@@ -0,0 +1,233 @@
+use crate::commitment::remove_relationship_commitment;
+use crate::evidence_index::{relationship_evidence_ids, unindex_evidence};
+use crate::key_rotation::remove_wrapped_keys;
+use crate::quotas::remove_relationship_usage;
+use crate::storage::*;
+use crate::uploads::{destroy_evidence_content, discard_relationship_uploads};
+use crate::types::*;
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use candid::Principal;
+use ed25519_dalek::VerifyingKey;
+use ic_cdk_macros::{query, update};
+
+// =========================
+// UNILATERAL KILL SWITCH
+// =========================
+// See docs/Kill-Switch-Spec.md. Either partner can irreversibly destroy the
+// relationship's canister-stored evidence on their own, authenticated by a
+// signature from the solo key they registered under
+// `kill_switch:<relationshipID>:<partnerPrincipal>`.
+//
+// Keys are registered during relationship setup (spec 3.1): each partner
+// supplies theirs to the call that makes them part of the relationship, and
+// a registered key can never be replaced.
+
+/// Maximum age (and future skew) of a signed delete_all_data message
+const KILL_SWITCH_MESSAGE_WINDOW_NS: u64 = 5 * 60 * 1_000_000_000;
+
+pub fn kill_switch_context(relationship_id: &str, partner: Principal) -> String {
+    format!("kill_switch:{}:{}", relationship_id, partner.to_text())
+}
+
+/// The exact bytes a partner signs to trigger the kill switch
+pub fn kill_switch_message(relationship_id: &str, partner: Principal, issued_at: u64) -> Vec<u8> {
+    format!(
+        "bonded:delete_all_data:v1\n{}\n{}",
+        kill_switch_context(relationship_id, partner),
+        issued_at
+    )
+    .into_bytes()
+}
+
+fn kill_switch_key_id(relationship_id: &str, partner: Principal) -> String {
+    format!("{}_{}", relationship_id, partner.to_text())
+}
+
+/// Reject anything but a valid Ed25519 verifying key
+pub(crate) fn validate_kill_switch_key(public_key: &[u8]) -> Result<(), BondedError> {
+    let key_bytes: [u8; 32] = public_key
+        .try_into()
+        .map_err(|_| BondedError::invalid_input("kill_switch_public_key", "Kill switch public key must be exactly 32 bytes"))?;
+    if VerifyingKey::from_bytes(&key_bytes).is_err() {
+        return Err(BondedError::invalid_input("kill_switch_public_key", "Kill switch public key is not a valid Ed25519 key"));
+    }
+    Ok(())
+}
+
+/// Register `partner`'s kill switch key for the relationship; a key that is
+/// already registered stays in place
+pub(crate) fn store_kill_switch_key(relationship_id: &str, partner: Principal, public_key: Vec<u8>) -> Result<KillSwitchKey, BondedError> {
+    validate_kill_switch_key(&public_key)?;
+
+    let key_id = kill_switch_key_id(relationship_id, partner);
+    if with_kill_switch_key_store_read(|store| store.contains_key(&key_id)) {
+        return Err(BondedError::conflict("Kill switch key already registered for this relationship"));
+    }
+
+    let kill_switch_key = KillSwitchKey {
+        relationship_id: relationship_id.to_string(),
+        partner,
+        context: kill_switch_context(relationship_id, partner),
+        public_key,
+        registered_at: current_time(),
+    };
+
+    with_kill_switch_key_store(|store| {
+        store.insert(key_id, kill_switch_key.clone());
+    });
+
+    Ok(kill_switch_key)
+}
+
+/// For relationships set up before keys were required at setup. Every later
+/// relationship has both partners' keys from the start, so this conflicts.
+#[update]
+pub fn register_kill_switch_key(relationship_id: String, public_key: Vec<u8>) -> BondedResult<KillSwitchKey> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "register_kill_switch_key") {
+        return BondedResult::err(msg);
+    }
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let kill_switch_key = match store_kill_switch_key(&relationship_id, caller, public_key) {
+        Ok(key) => key,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    log_relationship_audit_event(caller, &relationship_id, "register_kill_switch_key", None);
+
+    BondedResult::ok(kill_switch_key)
+}
+
+#[query]
+pub fn get_kill_switch_key(relationship_id: String) -> BondedResult<KillSwitchKey> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    match with_kill_switch_key_store_read(|store| store.get(&kill_switch_key_id(&relationship_id, caller))) {
+        Some(key) => BondedResult::ok(key),
+        None => BondedResult::err(BondedError::not_found("Kill switch key")),
+    }
+}
+
+#[update]
+pub fn execute_kill_switch(request: KillSwitchRequest) -> BondedResult<KillSwitchReceipt> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "execute_kill_switch") {
+        return BondedResult::err(msg);
+    }
+    let now = current_time();
+
+    let mut relationship = match with_relationship_store_read(|store| store.get(&request.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if !is_relationship_partner(&relationship, caller) {
+        return BondedResult::err(BondedError::unauthorized("Not authorized to access this relationship"));
+    }
+
+    // Only ever activates once; replays from either partner fail consistently
+    if matches!(relationship.status, RelationshipStatus::Destroyed) {
+        return BondedResult::err(BondedError::AlreadyDeleted { resource: "relationship data".to_string() });
+    }
+
+    let kill_switch_key = match with_kill_switch_key_store_read(|store| {
+        store.get(&kill_switch_key_id(&request.relationship_id, caller))
+    }) {
+        Some(key) => key,
+        None => return BondedResult::err(BondedError::not_found("Kill switch key")),
+    };
+
+    if request.issued_at.abs_diff(now) > KILL_SWITCH_MESSAGE_WINDOW_NS {
+        return BondedResult::err(BondedError::expired("Kill switch request"));
+    }
+
+    let message = kill_switch_message(&request.relationship_id, caller, request.issued_at);
+    match verify_signature(&kill_switch_key.public_key, &message, &request.signature) {
+        Ok(true) => {}
+        Ok(false) => return BondedResult::err(BondedError::unauthorized("Invalid kill switch signature")),
+        Err(e) => return BondedResult::err(BondedError::unauthorized(format!("Invalid kill switch signature: {}", e))),
+    }
+
+    let evidence_destroyed = destroy_relationship_evidence(&request.relationship_id);
+    discard_relationship_uploads(&request.relationship_id);
+
+    // Key material for the relationship goes with the evidence
+    for partner in [Some(relationship.partner1), relationship.partner2].into_iter().flatten() {
+        remove_user_key_shares(partner, &request.relationship_id);
+    }
+    remove_bonded_key_shares(&request.relationship_id);
+    relationship.key_rotation = None;
+
+    relationship.status = RelationshipStatus::Destroyed;
+    relationship.evidence_count = 0;
+    relationship.last_activity = now;
+    relationship.destroyed_at = Some(now);
+    relationship.destroyed_by = Some(caller);
+
+    with_relationship_store(|store| {
+        store.insert(request.relationship_id.clone(), relationship);
+    });
+
+    remove_relationship_commitment(&request.relationship_id);
+
+    // Activation time and partner identity go into the hash-chained audit log
+    log_relationship_audit_event(
+        caller,
+        &request.relationship_id,
+        "execute_kill_switch",
+        Some(format!("evidence_destroyed:{}", evidence_destroyed)),
+    );
+
+    BondedResult::ok(KillSwitchReceipt {
+        relationship_id: request.relationship_id,
+        destroyed_at: now,
+        destroyed_by: caller,
+        evidence_destroyed,
+    })
+}
+
+/// Overwrite every evidence blob of the relationship with zeros, then remove it
+fn destroy_relationship_evidence(relationship_id: &str) -> u64 {
+    let evidence_ids = relationship_evidence_ids(relationship_id);
+
+    with_evidence_store(|store| {
+        for evidence_id in &evidence_ids {
+            if let Some(mut evidence) = store.get(evidence_id) {
+                // Same-length zero overwrite before removal, so the ciphertext
+                // is not left behind in the previously allocated chunks
+                evidence.encrypted_data.iter_mut().for_each(|byte| *byte = 0);
+                evidence.signature = None;
+                destroy_evidence_content(&evidence);
+                remove_wrapped_keys(evidence_id);
+                unindex_evidence(&evidence);
+                store.insert(evidence_id.clone(), evidence);
+            }
+            store.remove(evidence_id);
+        }
+    });
+
+    remove_relationship_usage(relationship_id);
+
+    evidence_ids.len() as u64
+}
@@ -8,10 +8,28 @@
 mod types;
 mod storage;
 mod utils;
+mod shamir;
+mod entropy;
 mod evidence;
 mod relationships;
 mod users;
 mod client_storage;
+mod audit;
+mod commitment;
+mod passport;
+mod kill_switch;
+mod vetkd;
+mod uploads;
+mod downloads;
+mod evidence_index;
+mod compat;
+mod migrations;
+mod attestation;
+mod key_rotation;
+mod email;
+mod email_templates;
+mod rate_limit;
+mod quotas;
 
 // BFT modules commented out until properly implemented
 // mod bft_consensus;
@@ -26,6 +44,20 @@ pub use relationships::*;
 pub use users::*;
 pub use types::*;
 pub use client_storage::*;
+pub use audit::*;
+pub use commitment::*;
+pub use passport::*;
+pub use kill_switch::*;
+pub use vetkd::*;
+pub use uploads::*;
+pub use downloads::*;
+pub use migrations::*;
+pub use attestation::*;
+pub use key_rotation::*;
+pub use email::*;
+pub use email_templates::*;
+pub use rate_limit::*;
+pub use quotas::*;
 
 // Re-export BFT functions - commented out
 // pub use bft_evidence::*;
@@ -35,7 +67,7 @@ pub use client_storage::*;
 // Imports
 use candid::Principal;
 use ic_cdk_macros::query;
-use storage::{get_storage_stats, with_user_store_read, with_settings_store_read, with_relationship_store_read, with_evidence_store_read};
+use storage::{get_storage_stats, with_user_store_read, with_settings_store_read, with_relationship_store_read};
 use utils::current_time;
 use std::collections::HashMap;
 
@@ -55,9 +87,11 @@ fn health_check() -> String {
         • Users: {} registered\n\
         • Settings: {} configured\n\
         🔐 Security: Threshold cryptography ready\n\
+        🎲 Entropy: {}\n\
         💾 Storage: Stable memory operational\n\
         🌐 Network: Internet Computer blockchain",
-        evidence_count, relationship_count, user_count, settings_count
+        evidence_count, relationship_count, user_count, settings_count,
+        if entropy::is_seeded() { "seeded from raw_rand" } else { "awaiting raw_rand" }
     )
 }
 
@@ -65,13 +99,15 @@ fn health_check() -> String {
 // BATCH OPERATIONS FOR PERFORMANCE
 // ==============
 
-#[query]
-fn get_user_dashboard_data() -> Result<UserDashboardData, String> {
+#[query(name = "get_user_dashboard_data_v2")]
+fn get_user_dashboard_data() -> BondedResult<UserDashboardData> {
     let user = ic_cdk::api::caller();
     
     // Single optimized call to get all user data
-    let profile = with_user_store_read(|store| store.get(&user))
-        .ok_or("User not found")?;
+    let profile = match with_user_store_read(|store| store.get(&user)) {
+        Some(profile) => profile,
+        None => return BondedResult::err(BondedError::not_found("User profile")),
+    };
     
     let settings = with_settings_store_read(|store| store.get(&user))
         .unwrap_or_default();
@@ -83,17 +119,13 @@ fn get_user_dashboard_data() -> Result<UserDashboardData, String> {
         .collect();
     
     // Get recent evidence (limited to 10 for performance)
-    let recent_evidence: Vec<Evidence> = with_evidence_store_read(|store| {
-        store.iter()
-            .filter(|(_, evidence)| {
-                relationships.iter().any(|rel| rel.id == evidence.relationship_id)
-            })
-            .take(10)
-            .map(|(_, evidence)| evidence)
-            .collect()
-    });
+    let mut recent_evidence: Vec<EvidenceSummary> = relationships.iter()
+        .flat_map(|rel| evidence_index::latest_relationship_evidence(&rel.id, 10))
+        .collect();
+    recent_evidence.sort_by_key(|evidence| std::cmp::Reverse(evidence.metadata.timestamp));
+    recent_evidence.truncate(10);
     
-    Ok(UserDashboardData {
+    BondedResult::ok(UserDashboardData {
         profile,
         settings,
         relationships,
@@ -131,6 +163,13 @@ fn init() {
     ic_cdk::println!("🚀 Bonded Backend Canister initialized!");
     ic_cdk::println!("🔐 Stable memory ready for encrypted evidence storage");
     ic_cdk::println!("💝 Threshold cryptography system activated");
+    migrations::mark_migrations_complete();
+    entropy::schedule_reseeding();
+    uploads::schedule_upload_expiry();
+    relationships::schedule_relationship_request_expiry();
+    relationships::schedule_invite_expiry();
+    rate_limit::schedule_bucket_pruning();
+    commitment::restore_certified_commitments();
 }
 
 #[ic_cdk_macros::pre_upgrade]
@@ -143,6 +182,13 @@ fn pre_upgrade() {
 fn post_upgrade() {
     ic_cdk::println!("✅ Canister upgrade completed successfully!");
     ic_cdk::println!("💾 All evidence and relationships preserved");
+    migrations::resume_migrations();
+    entropy::schedule_reseeding();
+    uploads::schedule_upload_expiry();
+    relationships::schedule_relationship_request_expiry();
+    relationships::schedule_invite_expiry();
+    rate_limit::schedule_bucket_pruning();
+    commitment::restore_certified_commitments();
 }
 
 // Export candid interface
@@ -0,0 +1,690 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::commitment::commit_existing_relationships;
+use crate::evidence_index::{index_existing_evidence, EVIDENCE_INDEX_MIGRATION};
+use crate::quotas::{charge_relationship_usage, charge_user_usage, evidence_size, record_size, UsageStore};
+use ic_cdk_macros::query;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use std::borrow::Cow;
+use std::ops::Bound;
+use std::time::Duration;
+
+// ==================
+// SCHEMA MIGRATIONS
+// ==================
+// Records written under an older schema stay readable right after an upgrade
+// (see `VersionedRecord`). Migrations rewrite them in the current layout in
+// the background: `post_upgrade` runs as much as fits in its instruction
+// budget and zero-delay timers continue from there. Progress is kept in
+// stable memory, so an interrupted migration resumes where it stopped, even
+// across another upgrade.
+//
+// Migrations run once, in registry order. Never remove or reorder entries;
+// append new ones at the end.
+
+const MIGRATION_BATCH_SIZE: usize = 200;
+const POST_UPGRADE_INSTRUCTION_BUDGET: u64 = 100_000_000_000;
+const TIMER_INSTRUCTION_BUDGET: u64 = 20_000_000_000;
+
+/// Records processed by one migration step; `cursor` is None once the
+/// migration has nothing left to do
+struct MigrationBatch {
+    cursor: Option<Vec<u8>>,
+    processed: u64,
+}
+
+struct Migration {
+    id: &'static str,
+    step: fn(Option<Vec<u8>>) -> MigrationBatch,
+}
+
+// Everything after the first release ships in one upgrade, so each store
+// holding records from that release is rewritten once, at the current
+// schema version.
+const MIGRATIONS: &[Migration] = &[
+    // Rewrite records stored as bare candid in the versioned envelope
+    Migration { id: "0001_envelope_evidence", step: |cursor| with_evidence_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_relationships", step: |cursor| with_relationship_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_users", step: |cursor| with_user_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_settings", step: |cursor| with_settings_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_invites", step: |cursor| with_invite_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_timeline_data", step: |cursor| with_timeline_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_face_embeddings", step: |cursor| with_face_embedding_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_auto_scanner", step: |cursor| with_auto_scanner_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_capture_settings", step: |cursor| with_capture_settings_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_email_logs", step: |cursor| with_email_log_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_geo_cache", step: |cursor| with_geo_cache_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_scheduler", step: |cursor| with_scheduler_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_content", step: |cursor| with_content_store(|store| rewrite_records(store, cursor)) },
+    Migration { id: "0001_envelope_key_shares", step: |cursor| with_key_share_store(|store| rewrite_records(store, cursor)) },
+    // Invites keyed by the hash of a random token instead of a sequential ID
+    Migration { id: "0002_invite_token_hashes", step: rekey_legacy_invites },
+    // Storage quota totals for data written before usage was counted
+    Migration {
+        id: UsageStore::Evidence.backfill_id(),
+        step: |cursor| with_evidence_store_read(|store| {
+            count_usage(store, cursor, |evidence| charge_relationship_usage(&evidence.relationship_id, evidence_size(evidence)))
+        }),
+    },
+    Migration { id: UsageStore::TimelineData.backfill_id(), step: |cursor| with_timeline_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration { id: UsageStore::FaceEmbeddings.backfill_id(), step: |cursor| with_face_embedding_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration { id: UsageStore::AutoScanner.backfill_id(), step: |cursor| with_auto_scanner_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration { id: UsageStore::CaptureSettings.backfill_id(), step: |cursor| with_capture_settings_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration { id: UsageStore::Scheduler.backfill_id(), step: |cursor| with_scheduler_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration { id: UsageStore::Content.backfill_id(), step: |cursor| with_content_store_read(|store| count_usage(store, cursor, |record| charge_client_record(record.user, record))) },
+    Migration {
+        id: UsageStore::EmailLogs.backfill_id(),
+        step: |cursor| with_email_log_store_read(|store| {
+            // Only logs saved by the client; delivery records are the canister's own
+            count_usage(store, cursor, |record| if record.delivery.is_none() { charge_client_record(record.user, record) })
+        }),
+    },
+    Migration {
+        id: UsageStore::GeoCache.backfill_id(),
+        step: |cursor| with_geo_cache_store_read(|store| {
+            count_usage(store, cursor, |record| if let Some(user) = record.user { charge_client_record(user, record) })
+        }),
+    },
+    // Timeline indexes for evidence stored before they existed
+    Migration { id: EVIDENCE_INDEX_MIGRATION, step: index_existing_evidence_batch },
+    // Certified commitments for relationships created before they existed;
+    // reads the evidence indexes, so runs after them
+    Migration { id: "0005_relationship_commitments", step: commit_existing_relationships_batch },
+];
+
+/// Re-insert the next batch of records after `cursor`, which decodes them
+/// through any upgrade path and encodes them at the current schema version
+fn rewrite_records<K, V>(store: &mut StableBTreeMap<K, V, Memory>, cursor: Option<Vec<u8>>) -> MigrationBatch
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let start = match cursor {
+        Some(key) => Bound::Excluded(K::from_bytes(Cow::Owned(key))),
+        None => Bound::Unbounded,
+    };
+
+    let batch: Vec<(K, V)> = store.range((start, Bound::Unbounded)).take(MIGRATION_BATCH_SIZE).collect();
+    let processed = batch.len() as u64;
+    let mut last_key = None;
+    for (key, value) in batch {
+        last_key = Some(key.to_bytes().into_owned());
+        store.insert(key, value);
+    }
+
+    MigrationBatch {
+        cursor: if processed < MIGRATION_BATCH_SIZE as u64 { None } else { last_key },
+        processed,
+    }
+}
+
+/// Add the next batch of records after `cursor` to the storage quota totals.
+/// Live writes leave records past the cursor to the backfill (see
+/// `usage_backfill_passed`), so each record is counted exactly once.
+fn count_usage<K, V>(store: &StableBTreeMap<K, V, Memory>, cursor: Option<Vec<u8>>, charge: impl Fn(&V)) -> MigrationBatch
+where
+    K: Storable + Ord + Clone,
+    V: Storable,
+{
+    let start = match cursor {
+        Some(key) => Bound::Excluded(K::from_bytes(Cow::Owned(key))),
+        None => Bound::Unbounded,
+    };
+
+    let mut processed = 0u64;
+    let mut last_key = None;
+    for (key, value) in store.range((start, Bound::Unbounded)).take(MIGRATION_BATCH_SIZE) {
+        charge(&value);
+        last_key = Some(key.to_bytes().into_owned());
+        processed += 1;
+    }
+
+    MigrationBatch {
+        cursor: if processed < MIGRATION_BATCH_SIZE as u64 { None } else { last_key },
+        processed,
+    }
+}
+
+fn index_existing_evidence_batch(cursor: Option<Vec<u8>>) -> MigrationBatch {
+    let cursor = cursor.map(|key| String::from_bytes(Cow::Owned(key)));
+    let (cursor, processed) = index_existing_evidence(cursor, MIGRATION_BATCH_SIZE);
+    MigrationBatch { cursor: cursor.map(String::into_bytes), processed }
+}
+
+fn commit_existing_relationships_batch(cursor: Option<Vec<u8>>) -> MigrationBatch {
+    let cursor = cursor.map(|key| String::from_bytes(Cow::Owned(key)));
+    let (cursor, processed) = commit_existing_relationships(cursor, MIGRATION_BATCH_SIZE);
+    MigrationBatch { cursor: cursor.map(String::into_bytes), processed }
+}
+
+fn charge_client_record<V: Storable>(user: candid::Principal, record: &V) {
+    charge_user_usage(user, None, record_size(record));
+}
+
+/// Legacy invites were keyed by their sequential `invite_...` ID, which was
+/// also the secret in the link. Re-key them under the ID's hash so
+/// INVITE_STORE holds no link secrets, and cancel the ones still pending:
+/// their links were guessable. Hash keys are hex and sort before `invite_`,
+/// so re-keyed entries never reappear in the scanned range.
+fn rekey_legacy_invites(cursor: Option<Vec<u8>>) -> MigrationBatch {
+    const LEGACY_PREFIX: &str = "invite_";
+
+    let start = match cursor {
+        Some(key) => Bound::Excluded(String::from_bytes(Cow::Owned(key))),
+        None => Bound::Included(LEGACY_PREFIX.to_string()),
+    };
+
+    with_invite_store(|store| {
+        let batch: Vec<(String, PartnerInvite)> = store
+            .range((start, Bound::Unbounded))
+            .take_while(|(key, _)| key.starts_with(LEGACY_PREFIX))
+            .take(MIGRATION_BATCH_SIZE)
+            .collect();
+        let processed = batch.len() as u64;
+        let mut last_key = None;
+        for (legacy_id, mut invite) in batch {
+            store.remove(&legacy_id);
+            invite.id = hash_invite_token(&legacy_id);
+            if invite.status == InviteStatus::Pending {
+                invite.status = InviteStatus::Cancelled;
+                invite.closed_at = Some(current_time());
+            }
+            store.insert(invite.id.clone(), invite);
+            last_key = Some(legacy_id.into_bytes());
+        }
+
+        MigrationBatch {
+            cursor: if processed < MIGRATION_BATCH_SIZE as u64 { None } else { last_key },
+            processed,
+        }
+    })
+}
+
+fn migration_state(id: &str) -> MigrationState {
+    with_migration_store_read(|store| store.get(&id.to_string())).unwrap_or(MigrationState {
+        id: id.to_string(),
+        cursor: None,
+        records_migrated: 0,
+        started_at: None,
+        completed_at: None,
+    })
+}
+
+pub fn migration_completed(id: &str) -> bool {
+    migration_state(id).completed_at.is_some()
+}
+
+/// Whether the usage backfill `id` has counted the record under `key`, so
+/// that changes to it must go into the live totals. Records it has yet to
+/// reach are counted in whatever state it finds them.
+pub(crate) fn usage_backfill_passed<K: Storable + Ord>(id: &str, key: &K) -> bool {
+    let state = migration_state(id);
+    if state.completed_at.is_some() {
+        return true;
+    }
+    state.cursor.is_some_and(|cursor| *key <= K::from_bytes(Cow::Owned(cursor)))
+}
+
+fn save_migration_state(state: MigrationState) {
+    with_migration_store(|store| {
+        store.insert(state.id.clone(), state);
+    });
+}
+
+/// Run pending migrations until they finish or `instruction_budget` is
+/// spent. Returns true once every migration has completed.
+fn run_migrations(instruction_budget: u64) -> bool {
+    for migration in MIGRATIONS {
+        let mut state = migration_state(migration.id);
+        if state.completed_at.is_some() {
+            continue;
+        }
+        state.started_at.get_or_insert_with(current_time);
+
+        while state.completed_at.is_none() {
+            if ic_cdk::api::instruction_counter() >= instruction_budget {
+                save_migration_state(state);
+                return false;
+            }
+
+            let batch = (migration.step)(state.cursor.take());
+            state.records_migrated += batch.processed;
+            state.cursor = batch.cursor;
+            if state.cursor.is_none() {
+                state.completed_at = Some(current_time());
+                ic_cdk::println!("🧬 Migration {} completed ({} records)", migration.id, state.records_migrated);
+            }
+        }
+
+        save_migration_state(state);
+    }
+
+    true
+}
+
+fn schedule_migration_round() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        if !run_migrations(TIMER_INSTRUCTION_BUDGET) {
+            schedule_migration_round();
+        }
+    });
+}
+
+/// Called from `post_upgrade`
+pub fn resume_migrations() {
+    if !run_migrations(POST_UPGRADE_INSTRUCTION_BUDGET) {
+        schedule_migration_round();
+    }
+}
+
+/// A fresh install has no records in older layouts
+pub fn mark_migrations_complete() {
+    let now = current_time();
+    for migration in MIGRATIONS {
+        let mut state = migration_state(migration.id);
+        state.completed_at.get_or_insert(now);
+        save_migration_state(state);
+    }
+}
+
+// ==================
+// MIGRATION STATUS
+// ==================
+
+#[query]
+pub fn get_migration_status() -> BondedResult<Vec<MigrationState>> {
+    if let Err(msg) = verify_controller(caller_principal()) {
+        return BondedResult::err(msg);
+    }
+
+    BondedResult::ok(MIGRATIONS.iter().map(|migration| migration_state(migration.id)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quotas::{reserve_user_storage, user_usage};
+    use candid::{CandidType, Encode, Principal};
+    use ic_stable_structures::memory_manager::{MemoryId, MemoryManager};
+    use ic_stable_structures::DefaultMemoryImpl;
+    use serde::Deserialize;
+
+    // Record layouts as the first release stored them: bare candid, no envelope
+
+    #[derive(CandidType, Deserialize)]
+    struct EvidenceFixture {
+        id: String,
+        relationship_id: String,
+        encrypted_data: Vec<u8>,
+        metadata: EvidenceMetadata,
+        upload_timestamp: u64,
+        hash: String,
+        uploader: Principal,
+        signature: Option<Vec<u8>>,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct RelationshipFixture {
+        id: String,
+        partner1: Principal,
+        partner2: Option<Principal>,
+        status: RelationshipStatus,
+        created_at: u64,
+        bonded_key_share: Vec<u8>,
+        evidence_count: u64,
+        last_activity: u64,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct UserProfileFixture {
+        principal: Principal,
+        created_at: u64,
+        relationships: Vec<String>,
+        total_evidence_uploaded: u64,
+        kyc_verified: bool,
+        last_seen: u64,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct PartnerInviteFixture {
+        id: String,
+        inviter_principal: Principal,
+        partner_email: String,
+        inviter_name: String,
+        status: InviteStatus,
+        created_at: u64,
+        expires_at: u64,
+        metadata: Option<String>,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct EmailLogFixture {
+        id: String,
+        user: Principal,
+        log_data: String,
+        created_at: u64,
+    }
+
+    #[derive(CandidType, Deserialize)]
+    struct UserKeyShareFixture {
+        key_id: String,
+        user: Principal,
+        relationship_id: String,
+        key_share: Vec<u8>,
+        created_at: u64,
+    }
+
+    /// Stored bytes as they are, whatever their layout
+    struct Raw(Vec<u8>);
+
+    impl Storable for Raw {
+        const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+        fn to_bytes(&self) -> Cow<'_, [u8]> {
+            Cow::Borrowed(&self.0)
+        }
+
+        fn from_bytes(bytes: Cow<[u8]>) -> Self {
+            Raw(bytes.into_owned())
+        }
+    }
+
+    fn principal(id: u8) -> Principal {
+        Principal::from_slice(&[id; 29])
+    }
+
+    /// Store `fixtures` as bare candid, run `rewrite_records` over the store
+    /// to completion and return every record with the schema version it was
+    /// rewritten at
+    fn migrate<K, V, F>(fixtures: Vec<(K, F)>) -> Vec<(K, V, u16)>
+    where
+        K: Storable + Ord + Clone,
+        V: Storable,
+        F: CandidType,
+    {
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+
+        let mut raw: StableBTreeMap<K, Raw, Memory> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+        for (key, fixture) in fixtures {
+            raw.insert(key, Raw(Encode!(&fixture).unwrap()));
+        }
+
+        let mut store: StableBTreeMap<K, V, Memory> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+        let mut cursor = None;
+        loop {
+            cursor = rewrite_records(&mut store, cursor).cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        let raw: StableBTreeMap<K, Raw, Memory> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+        raw.iter()
+            .map(|(key, bytes)| {
+                let version = split_record(&bytes.0).0;
+                (key, V::from_bytes(Cow::Owned(bytes.0)), version)
+            })
+            .collect()
+    }
+
+    fn metadata() -> EvidenceMetadata {
+        EvidenceMetadata {
+            timestamp: 1_700_000_000_000_000_000,
+            content_type: "image/jpeg".to_string(),
+            location: Some("Lisbon".to_string()),
+            description: None,
+            tags: vec!["travel".to_string()],
+        }
+    }
+
+    #[test]
+    fn evidence_v1_gains_empty_attestation_and_content() {
+        let fixture = EvidenceFixture {
+            id: "evidence_1".to_string(),
+            relationship_id: "relationship_1".to_string(),
+            encrypted_data: vec![1, 2, 3],
+            metadata: metadata(),
+            upload_timestamp: 42,
+            hash: "abc".to_string(),
+            uploader: principal(1),
+            signature: Some(vec![9; 64]),
+        };
+
+        let migrated: Vec<(String, Evidence, u16)> = migrate(vec![("evidence_1".to_string(), fixture)]);
+        let (_, evidence, version) = &migrated[0];
+
+        assert_eq!(*version, Evidence::SCHEMA_VERSION);
+        assert_eq!(evidence.encrypted_data, vec![1, 2, 3]);
+        assert_eq!(evidence.metadata.tags, vec!["travel".to_string()]);
+        assert_eq!(evidence.signature, Some(vec![9; 64]));
+        assert!(evidence.signed_at.is_none());
+        assert!(evidence.content.is_none());
+        assert!(evidence.countersignature.is_none());
+    }
+
+    #[test]
+    fn relationship_v1_upgrades_to_current_layout() {
+        let fixture = |id: &str, status| RelationshipFixture {
+            id: id.to_string(),
+            partner1: principal(1),
+            partner2: Some(principal(2)),
+            status,
+            created_at: 1_000,
+            bonded_key_share: vec![7; 33],
+            evidence_count: 3,
+            last_activity: 2_000,
+        };
+
+        let migrated: Vec<(String, Relationship, u16)> = migrate(vec![
+            ("relationship_1".to_string(), fixture("relationship_1", RelationshipStatus::Active)),
+            ("relationship_2".to_string(), fixture("relationship_2", RelationshipStatus::Pending)),
+        ]);
+
+        let (_, active, version) = &migrated[0];
+        assert_eq!(*version, Relationship::SCHEMA_VERSION);
+        assert_eq!(active.partner2, Some(principal(2)));
+        let bonded_share = with_bonded_key_share_store(|store| store.get(&bonded_key_share_id("relationship_1", 1)));
+        assert_eq!(bonded_share, Some(vec![7; 33]));
+        assert_eq!(active.evidence_count, 3);
+        assert_eq!(active.accepted_at, Some(1_000));
+        assert!(active.signing_keys.is_empty());
+        assert!(active.relationship_keys.is_empty());
+        assert_eq!(active.key_scheme, RelationshipKeyScheme::ThresholdShares);
+        assert!(active.destroyed_at.is_none());
+        assert!(active.expires_at.is_none());
+
+        let (_, pending, _) = &migrated[1];
+        assert!(pending.accepted_at.is_none());
+    }
+
+    #[test]
+    fn user_profile_v1_has_no_tier_or_email() {
+        let fixture = UserProfileFixture {
+            principal: principal(1),
+            created_at: 1_000,
+            relationships: vec!["relationship_1".to_string()],
+            total_evidence_uploaded: 5,
+            kyc_verified: true,
+            last_seen: 2_000,
+        };
+
+        let migrated: Vec<(Principal, UserProfile, u16)> = migrate(vec![(principal(1), fixture)]);
+        let (_, profile, version) = &migrated[0];
+
+        assert_eq!(*version, UserProfile::SCHEMA_VERSION);
+        assert_eq!(profile.relationships, vec!["relationship_1".to_string()]);
+        assert_eq!(profile.total_evidence_uploaded, 5);
+        assert!(profile.quota_tier.is_none());
+        assert!(profile.email.is_none());
+    }
+
+    #[test]
+    fn partner_invite_v1_replaces_plaintext_email_with_hash() {
+        let fixture = PartnerInviteFixture {
+            id: "invite_1".to_string(),
+            inviter_principal: principal(1),
+            partner_email: "Jane@Example.com".to_string(),
+            inviter_name: "Alex".to_string(),
+            status: InviteStatus::Pending,
+            created_at: 1_000,
+            expires_at: 2_000,
+            metadata: None,
+        };
+
+        let migrated: Vec<(String, PartnerInvite, u16)> = migrate(vec![("invite_1".to_string(), fixture)]);
+        let (_, invite, version) = &migrated[0];
+
+        assert_eq!(*version, PartnerInvite::SCHEMA_VERSION);
+        let salt = legacy_invite_email_salt("invite_1");
+        assert_eq!(invite.partner_email_salt, salt);
+        assert_eq!(invite.partner_email_hash, hash_invite_email(&salt, "jane@example.com"));
+        assert_eq!(invite.partner_email_hint, "j***@example.com");
+        assert!(invite.closed_at.is_none());
+    }
+
+    #[test]
+    fn user_key_share_v1_belongs_to_key_version_1() {
+        let fixture = UserKeyShareFixture {
+            key_id: "relationship_1_user".to_string(),
+            user: principal(1),
+            relationship_id: "relationship_1".to_string(),
+            key_share: vec![5; 33],
+            created_at: 1_000,
+        };
+
+        let migrated: Vec<(String, UserKeyShare, u16)> = migrate(vec![("relationship_1_user".to_string(), fixture)]);
+        let (_, share, version) = &migrated[0];
+
+        assert_eq!(*version, UserKeyShare::SCHEMA_VERSION);
+        assert_eq!(share.key_share, vec![5; 33]);
+        assert_eq!(share.key_version, 1);
+    }
+
+    #[test]
+    fn email_log_v1_is_a_client_log() {
+        let fixture = EmailLogFixture {
+            id: "email_log_1".to_string(),
+            user: principal(1),
+            log_data: "{}".to_string(),
+            created_at: 1_000,
+        };
+
+        let migrated: Vec<(String, EmailLog, u16)> = migrate(vec![("email_log_1".to_string(), fixture)]);
+        let (_, log, version) = &migrated[0];
+
+        assert_eq!(*version, EmailLog::SCHEMA_VERSION);
+        assert_eq!(log.log_data, "{}");
+        assert!(log.delivery.is_none());
+    }
+
+    #[test]
+    fn unchanged_layouts_are_wrapped_in_the_envelope() {
+        let settings = UserSettings { upload_schedule: "daily".to_string(), ..Default::default() };
+        let migrated: Vec<(Principal, UserSettings, u16)> = migrate(vec![(principal(1), settings)]);
+        assert_eq!((migrated[0].1.upload_schedule.as_str(), migrated[0].2), ("daily", UserSettings::SCHEMA_VERSION));
+
+        let timeline = TimelineData { id: "timeline_1".to_string(), user: principal(1), timeline_items: vec!["a".to_string()], updated_at: 1 };
+        let migrated: Vec<(String, TimelineData, u16)> = migrate(vec![("timeline_1".to_string(), timeline)]);
+        assert_eq!((migrated[0].1.timeline_items.clone(), migrated[0].2), (vec!["a".to_string()], TimelineData::SCHEMA_VERSION));
+
+        let embedding = UserFaceEmbedding { user: principal(1), embedding_data: vec![0.5], partner_id: None, created_at: 1, updated_at: 1 };
+        let migrated: Vec<(Principal, UserFaceEmbedding, u16)> = migrate(vec![(principal(1), embedding)]);
+        assert_eq!((migrated[0].1.embedding_data.clone(), migrated[0].2), (vec![0.5], UserFaceEmbedding::SCHEMA_VERSION));
+
+        let scanner = AutoScannerSettings { user: principal(1), settings_data: "{}".to_string(), updated_at: 1 };
+        let migrated: Vec<(Principal, AutoScannerSettings, u16)> = migrate(vec![(principal(1), scanner)]);
+        assert_eq!((migrated[0].1.settings_data.as_str(), migrated[0].2), ("{}", AutoScannerSettings::SCHEMA_VERSION));
+
+        let capture = CaptureSettings { user: principal(1), settings_data: "{}".to_string(), file_type_overrides: "[]".to_string(), updated_at: 1 };
+        let migrated: Vec<(Principal, CaptureSettings, u16)> = migrate(vec![(principal(1), capture)]);
+        assert_eq!((migrated[0].1.file_type_overrides.as_str(), migrated[0].2), ("[]", CaptureSettings::SCHEMA_VERSION));
+
+        let geo = GeolocationCache { cache_key: "geo_1".to_string(), user: None, cache_data: "{}".to_string(), expires_at: 2, created_at: 1 };
+        let migrated: Vec<(String, GeolocationCache, u16)> = migrate(vec![("geo_1".to_string(), geo)]);
+        assert_eq!((migrated[0].1.expires_at, migrated[0].2), (2, GeolocationCache::SCHEMA_VERSION));
+
+        let scheduler = SchedulerSettings { user: principal(1), settings_data: "{}".to_string(), updated_at: 1 };
+        let migrated: Vec<(Principal, SchedulerSettings, u16)> = migrate(vec![(principal(1), scheduler)]);
+        assert_eq!((migrated[0].1.settings_data.as_str(), migrated[0].2), ("{}", SchedulerSettings::SCHEMA_VERSION));
+
+        let content = ProcessedContent {
+            id: "content_1".to_string(),
+            user: principal(1),
+            relationship_id: None,
+            content_data: "{}".to_string(),
+            content_type: "timeline".to_string(),
+            created_at: 1,
+            updated_at: 1,
+        };
+        let migrated: Vec<(String, ProcessedContent, u16)> = migrate(vec![("content_1".to_string(), content)]);
+        assert_eq!((migrated[0].1.content_type.as_str(), migrated[0].2), ("timeline", ProcessedContent::SCHEMA_VERSION));
+    }
+
+    #[test]
+    fn rewrite_continues_across_batches() {
+        let fixtures: Vec<(String, EmailLogFixture)> = (0..MIGRATION_BATCH_SIZE * 2 + 7)
+            .map(|i| {
+                let id = format!("email_log_{:05}", i);
+                (id.clone(), EmailLogFixture { id, user: principal(1), log_data: "{}".to_string(), created_at: i as u64 })
+            })
+            .collect();
+
+        let migrated: Vec<(String, EmailLog, u16)> = migrate(fixtures);
+
+        assert_eq!(migrated.len(), MIGRATION_BATCH_SIZE * 2 + 7);
+        assert!(migrated.iter().all(|(_, _, version)| *version == EmailLog::SCHEMA_VERSION));
+    }
+
+    fn set_backfill_cursor(id: &str, cursor: Option<&str>, completed: bool) {
+        save_migration_state(MigrationState {
+            id: id.to_string(),
+            cursor: cursor.map(|key| key.as_bytes().to_vec()),
+            records_migrated: 0,
+            started_at: Some(1),
+            completed_at: completed.then_some(2),
+        });
+    }
+
+    #[test]
+    fn usage_backfill_passes_keys_up_to_its_cursor() {
+        let id = "test_backfill";
+        assert!(!usage_backfill_passed(id, &"a".to_string()));
+
+        set_backfill_cursor(id, Some("m"), false);
+        assert!(usage_backfill_passed(id, &"a".to_string()));
+        assert!(usage_backfill_passed(id, &"m".to_string()));
+        assert!(!usage_backfill_passed(id, &"n".to_string()));
+
+        set_backfill_cursor(id, None, true);
+        assert!(usage_backfill_passed(id, &"z".to_string()));
+    }
+
+    #[test]
+    fn records_written_during_the_backfill_are_counted_once() {
+        let user = principal(3);
+        let backfill = UsageStore::TimelineData.backfill_id();
+        let manager = MemoryManager::init(DefaultMemoryImpl::default());
+        let mut store: StableBTreeMap<String, TimelineData, Memory> = StableBTreeMap::init(manager.get(MemoryId::new(0)));
+        let record = |id: &str| TimelineData { id: id.to_string(), user, timeline_items: vec![], updated_at: 1 };
+
+        // The backfill has counted "timeline_a" and stopped there
+        store.insert("timeline_a".to_string(), record("timeline_a"));
+        set_backfill_cursor(backfill, Some("timeline_a"), false);
+        charge_client_record(user, &record("timeline_a"));
+
+        // Written live, ahead of the cursor: left to the backfill
+        let ahead = record("timeline_b");
+        reserve_user_storage(user, UsageStore::TimelineData, &ahead.id, None, record_size(&ahead)).unwrap();
+        store.insert(ahead.id.clone(), ahead);
+        assert_eq!(user_usage(user).items, 1);
+
+        let batch = count_usage(&store, Some(b"timeline_a".to_vec()), |record| charge_client_record(record.user, record));
+        assert_eq!(batch.processed, 1);
+        set_backfill_cursor(backfill, None, true);
+
+        let usage = user_usage(user);
+        assert_eq!(usage.items, 2);
+        assert_eq!(usage.bytes, record_size(&record("timeline_a")) * 2);
+    }
+}
@@ -0,0 +1,223 @@
+use crate::evidence_index::relationship_evidence;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use ic_cdk_macros::query;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+// ==================================
+// PERMANENT PASSPORT: TIMELINE DOCUMENT
+// ==================================
+// Renders the canonical timeline document T for a relationship straight from
+// stable memory. The rendering is a pure function of the stored evidence
+// metadata, the requested range and the relationship's certified commitment,
+// so an off-chain prover re-running `timeline_logic_v1` gets identical bytes.
+//
+// Canonical form (timeline_logic_v1):
+// - compact UTF-8 JSON, no insignificant whitespace, no floating point
+// - object keys in the fixed order of the structs below
+// - entries sorted by (timestamp, upload_timestamp, evidence_id)
+// - absent optional values rendered as `null`
+
+pub const TIMELINE_LOGIC_VERSION: &str = "timeline_logic_v1";
+const TIMELINE_DOCUMENT_FORMAT: &str = "application/json";
+
+#[derive(Serialize)]
+struct TimelineDocument {
+    version: &'static str,
+    canister_id: String,
+    relationship_id: String,
+    range: TimelineDocumentRange,
+    input_commitment: String,
+    evidence_count: u64,
+    entries: Vec<TimelineDocumentEntry>,
+}
+
+#[derive(Serialize)]
+struct TimelineDocumentRange {
+    start: Option<u64>,
+    end: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TimelineDocumentEntry {
+    evidence_id: String,
+    timestamp: u64,
+    upload_timestamp: u64,
+    content_type: String,
+    location: Option<String>,
+    description: Option<String>,
+    tags: Vec<String>,
+    hash: String,
+    uploader: String,
+}
+
+#[query]
+pub fn generate_relationship_timeline(
+    relationship_id: String,
+    range: Option<TimelineRange>,
+) -> BondedResult<RelationshipTimelineDocument> {
+    let caller = caller_principal();
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    let range = range.unwrap_or(TimelineRange { start: None, end: None });
+    if let (Some(start), Some(end)) = (range.start, range.end) {
+        if start > end {
+            return BondedResult::err(BondedError::invalid_input("range", "Range start must not be after range end"));
+        }
+    }
+
+    // H_input binds the document to the certified state it was rendered from
+    let input_commitment = match with_commitment_store_read(|store| store.get(&relationship_id)) {
+        Some(commitment) => hex::encode(&commitment.root_hash),
+        None => return BondedResult::err(BondedError::not_found("Commitment")),
+    };
+
+    let evidence = relationship_evidence(&relationship_id, range.start, range.end);
+    let canister_id = ic_cdk::api::id().to_text();
+    let (document, timeline_hash) = match render_timeline_document(&canister_id, &relationship_id, &range, &input_commitment, evidence) {
+        Ok(rendered) => rendered,
+        Err(e) => return BondedResult::err(BondedError::internal(format!("Failed to render timeline: {}", e))),
+    };
+
+    BondedResult::ok(RelationshipTimelineDocument {
+        version: TIMELINE_LOGIC_VERSION.to_string(),
+        format: TIMELINE_DOCUMENT_FORMAT.to_string(),
+        relationship_id,
+        timeline_hash,
+        input_commitment,
+        document,
+    })
+}
+
+/// Canonical document bytes and their hash H_t, hex-encoded
+fn render_timeline_document(
+    canister_id: &str,
+    relationship_id: &str,
+    range: &TimelineRange,
+    input_commitment: &str,
+    evidence: Vec<EvidenceSummary>,
+) -> Result<(Vec<u8>, String), serde_json::Error> {
+    let mut entries: Vec<TimelineDocumentEntry> = evidence
+        .into_iter()
+        .map(|evidence| TimelineDocumentEntry {
+            evidence_id: evidence.id,
+            timestamp: evidence.metadata.timestamp,
+            upload_timestamp: evidence.upload_timestamp,
+            content_type: evidence.metadata.content_type,
+            location: evidence.metadata.location,
+            description: evidence.metadata.description,
+            tags: evidence.metadata.tags,
+            hash: evidence.hash,
+            uploader: evidence.uploader.to_text(),
+        })
+        .collect();
+
+    entries.sort_by(|a, b| {
+        (a.timestamp, a.upload_timestamp, &a.evidence_id).cmp(&(b.timestamp, b.upload_timestamp, &b.evidence_id))
+    });
+
+    let document = TimelineDocument {
+        version: TIMELINE_LOGIC_VERSION,
+        canister_id: canister_id.to_string(),
+        relationship_id: relationship_id.to_string(),
+        range: TimelineDocumentRange {
+            start: range.start,
+            end: range.end,
+        },
+        input_commitment: input_commitment.to_string(),
+        evidence_count: entries.len() as u64,
+        entries,
+    };
+
+    let document_bytes = serde_json::to_vec(&document)?;
+    let timeline_hash = hex::encode(Sha256::digest(&document_bytes));
+    Ok((document_bytes, timeline_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Principal;
+
+    const CANISTER_ID: &str = "rrkah-fqaaa-aaaaa-aaaaq-cai";
+    const RELATIONSHIP_ID: &str = "relationship_1";
+    const INPUT_COMMITMENT: &str = "00112233";
+
+    fn summary(id: &str, timestamp: u64, upload_timestamp: u64, location: Option<&str>, tags: &[&str]) -> EvidenceSummary {
+        EvidenceSummary {
+            id: id.to_string(),
+            relationship_id: RELATIONSHIP_ID.to_string(),
+            metadata: EvidenceMetadata {
+                timestamp,
+                content_type: "image/jpeg".to_string(),
+                location: location.map(str::to_string),
+                description: None,
+                tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            },
+            upload_timestamp,
+            hash: format!("hash-{}", id),
+            uploader: Principal::from_slice(&[1; 29]),
+            signature: None,
+            signed_at: None,
+            size: 3,
+            chunk_count: 1,
+            countersignature: None,
+            attestation: AttestationState::Unsigned,
+        }
+    }
+
+    fn render(evidence: Vec<EvidenceSummary>) -> (Vec<u8>, String) {
+        let range = TimelineRange { start: None, end: Some(500) };
+        render_timeline_document(CANISTER_ID, RELATIONSHIP_ID, &range, INPUT_COMMITMENT, evidence).unwrap()
+    }
+
+    #[test]
+    fn document_does_not_depend_on_evidence_order() {
+        // Equal timestamps exercise both tie-breaks
+        let evidence = vec![
+            summary("c", 100, 20, None, &[]),
+            summary("a", 100, 10, Some("Lisbon"), &["travel"]),
+            summary("b", 100, 10, None, &[]),
+            summary("d", 50, 30, None, &["home", "dinner"]),
+        ];
+        let mut reordered = evidence.clone();
+        reordered.reverse();
+        reordered.swap(0, 2);
+
+        let (document, timeline_hash) = render(evidence);
+        let (reordered_document, reordered_hash) = render(reordered);
+
+        assert_eq!(document, reordered_document);
+        assert_eq!(timeline_hash, reordered_hash);
+    }
+
+    #[test]
+    fn document_bytes_match_timeline_logic_v1() {
+        let (document, timeline_hash) = render(vec![
+            summary("b", 200, 20, None, &[]),
+            summary("a", 100, 10, Some("Lisbon"), &["travel"]),
+        ]);
+
+        let expected = concat!(
+            r#"{"version":"timeline_logic_v1","canister_id":"rrkah-fqaaa-aaaaa-aaaaq-cai","relationship_id":"relationship_1","#,
+            r#""range":{"start":null,"end":500},"input_commitment":"00112233","evidence_count":2,"entries":["#,
+            r#"{"evidence_id":"a","timestamp":100,"upload_timestamp":10,"content_type":"image/jpeg","location":"Lisbon","#,
+            r#""description":null,"tags":["travel"],"hash":"hash-a","uploader":"wmzac-nabae-aqcai-baeaq-caiba-eaqca-ibaea-qcaib-aeaqc-aibae-aqc"},"#,
+            r#"{"evidence_id":"b","timestamp":200,"upload_timestamp":20,"content_type":"image/jpeg","location":null,"#,
+            r#""description":null,"tags":[],"hash":"hash-b","uploader":"wmzac-nabae-aqcai-baeaq-caiba-eaqca-ibaea-qcaib-aeaqc-aibae-aqc"}]}"#,
+        );
+
+        assert_eq!(String::from_utf8(document).unwrap(), expected);
+        assert_eq!(timeline_hash, "0ac358d99d6f36c81622c9f7465d3e1785aef8d5bd9b3fe515595eb83033ebab");
+    }
+}
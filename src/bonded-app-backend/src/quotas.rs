@@ -0,0 +1,285 @@
+use crate::downloads::evidence_layout;
+use crate::migrations::usage_backfill_passed;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::Principal;
+use ic_cdk_macros::{query, update};
+use ic_stable_structures::Storable;
+
+// ==================
+// STORAGE QUOTAS
+// ==================
+// Evidence counts against its relationship (ciphertext bytes and items);
+// everything written through `client_storage.rs` counts against the user who
+// wrote it (stored record bytes and records). The totals in
+// RELATIONSHIP_USAGE_STORE and USER_USAGE_STORE are adjusted in the same
+// message as every insert and remove, never recomputed by scanning.
+//
+// Writes are checked against the caller's tier before they are stored. Tiers
+// are granted by a controller, e.g. once billing or KYC has been confirmed.
+// Removals are never blocked, so a user over quota (e.g. after a tier
+// change) can always free space.
+//
+// Data written before usage was counted is added by backfill migrations.
+// While one runs, live writes only adjust the totals for records it has
+// already counted; it counts the rest in the state it finds them.
+
+/// Stores whose records count towards usage, each with its backfill
+#[derive(Clone, Copy)]
+pub(crate) enum UsageStore {
+    Evidence,
+    TimelineData,
+    FaceEmbeddings,
+    AutoScanner,
+    CaptureSettings,
+    Scheduler,
+    Content,
+    EmailLogs,
+    GeoCache,
+}
+
+impl UsageStore {
+    /// Migration id of the store's usage backfill (see `migrations.rs`)
+    pub(crate) const fn backfill_id(self) -> &'static str {
+        match self {
+            UsageStore::Evidence => "0003_usage_evidence",
+            UsageStore::TimelineData => "0003_usage_timeline_data",
+            UsageStore::FaceEmbeddings => "0003_usage_face_embeddings",
+            UsageStore::AutoScanner => "0003_usage_auto_scanner",
+            UsageStore::CaptureSettings => "0003_usage_capture_settings",
+            UsageStore::Scheduler => "0003_usage_scheduler",
+            UsageStore::Content => "0003_usage_content",
+            UsageStore::EmailLogs => "0003_usage_email_logs",
+            UsageStore::GeoCache => "0003_usage_geo_cache",
+        }
+    }
+
+    /// Whether the record under `key` is already part of the totals
+    fn counted<K: Storable + Ord>(self, key: &K) -> bool {
+        usage_backfill_passed(self.backfill_id(), key)
+    }
+}
+
+const MIB: u64 = 1024 * 1024;
+const GIB: u64 = 1024 * MIB;
+
+fn relationship_limits(tier: QuotaTier) -> QuotaLimits {
+    match tier {
+        QuotaTier::Standard => QuotaLimits { max_bytes: 5 * GIB, max_items: 10_000 },
+        QuotaTier::Extended => QuotaLimits { max_bytes: 50 * GIB, max_items: 100_000 },
+    }
+}
+
+fn client_storage_limits(tier: QuotaTier) -> QuotaLimits {
+    match tier {
+        QuotaTier::Standard => QuotaLimits { max_bytes: 20 * MIB, max_items: 2_000 },
+        QuotaTier::Extended => QuotaLimits { max_bytes: 100 * MIB, max_items: 10_000 },
+    }
+}
+
+/// Tiers are granted with `set_user_quota_tier`, never by the user
+pub fn user_tier(user: Principal) -> QuotaTier {
+    with_user_store_read(|store| store.get(&user))
+        .and_then(|profile| profile.quota_tier)
+        .unwrap_or(QuotaTier::Standard)
+}
+
+pub fn relationship_tier(relationship: &Relationship) -> QuotaTier {
+    let mut partners = std::iter::once(relationship.partner1).chain(relationship.partner2);
+    if partners.all(|partner| user_tier(partner) == QuotaTier::Extended) {
+        QuotaTier::Extended
+    } else {
+        QuotaTier::Standard
+    }
+}
+
+/// Ciphertext bytes an evidence item holds, inline or chunked
+pub(crate) fn evidence_size(evidence: &Evidence) -> u64 {
+    evidence_layout(evidence).0
+}
+
+/// Bytes a client storage record takes in stable memory
+pub(crate) fn record_size<V: Storable>(record: &V) -> u64 {
+    record.to_bytes().len() as u64
+}
+
+/// Reject growth that would take `usage` past `limits`
+fn check_limits(resource: &str, usage: StorageUsage, limits: QuotaLimits, bytes: u64, items: u64) -> Result<(), BondedError> {
+    if items > 0 && usage.items + items > limits.max_items {
+        return Err(BondedError::quota_exceeded(format!("{} items", resource), limits.max_items));
+    }
+    if bytes > 0 && usage.bytes + bytes > limits.max_bytes {
+        return Err(BondedError::quota_exceeded(format!("{} bytes", resource), limits.max_bytes));
+    }
+    Ok(())
+}
+
+fn adjust(usage: &mut StorageUsage, added: StorageUsage, removed: StorageUsage) {
+    usage.bytes = (usage.bytes + added.bytes).saturating_sub(removed.bytes);
+    usage.items = (usage.items + added.items).saturating_sub(removed.items);
+}
+
+// Relationship evidence
+
+pub fn relationship_usage(relationship_id: &str) -> StorageUsage {
+    with_relationship_usage_store_read(|store| store.get(&relationship_id.to_string())).unwrap_or_default()
+}
+
+fn adjust_relationship_usage(relationship_id: &str, added: StorageUsage, removed: StorageUsage) {
+    with_relationship_usage_store(|store| {
+        let mut usage = store.get(&relationship_id.to_string()).unwrap_or_default();
+        adjust(&mut usage, added, removed);
+        store.insert(relationship_id.to_string(), usage);
+    });
+}
+
+/// Room for one more evidence item of `bytes` in the relationship
+pub(crate) fn check_relationship_quota(relationship: &Relationship, bytes: u64) -> Result<(), BondedError> {
+    let limits = relationship_limits(relationship_tier(relationship));
+    check_limits("Relationship evidence", relationship_usage(&relationship.id), limits, bytes, 1)
+}
+
+pub(crate) fn charge_relationship_usage(relationship_id: &str, bytes: u64) {
+    adjust_relationship_usage(relationship_id, StorageUsage { bytes, items: 1 }, StorageUsage::default());
+}
+
+/// Charge a newly stored evidence item to its relationship
+pub(crate) fn charge_evidence_usage(evidence: &Evidence) {
+    if UsageStore::Evidence.counted(&evidence.id) {
+        charge_relationship_usage(&evidence.relationship_id, evidence_size(evidence));
+    }
+}
+
+/// Release a removed evidence item from its relationship
+pub(crate) fn release_evidence_usage(evidence: &Evidence) {
+    if UsageStore::Evidence.counted(&evidence.id) {
+        adjust_relationship_usage(
+            &evidence.relationship_id,
+            StorageUsage::default(),
+            StorageUsage { bytes: evidence_size(evidence), items: 1 },
+        );
+    }
+}
+
+/// Drop the totals of a relationship whose evidence has all been destroyed
+pub(crate) fn remove_relationship_usage(relationship_id: &str) {
+    with_relationship_usage_store(|store| {
+        store.remove(&relationship_id.to_string());
+    });
+}
+
+// Client storage
+
+pub fn user_usage(user: Principal) -> StorageUsage {
+    with_user_usage_store_read(|store| store.get(&user)).unwrap_or_default()
+}
+
+fn adjust_user_usage(user: Principal, added: StorageUsage, removed: StorageUsage) {
+    with_user_usage_store(|store| {
+        let mut usage = store.get(&user).unwrap_or_default();
+        adjust(&mut usage, added, removed);
+        store.insert(user, usage);
+    });
+}
+
+/// Room for a record of `bytes`, replacing one of `replaced` bytes if the
+/// key is already taken by the same user
+fn check_user_quota(user: Principal, replaced: Option<u64>, bytes: u64) -> Result<(), BondedError> {
+    let limits = client_storage_limits(user_tier(user));
+    let (growth, items) = match replaced {
+        Some(replaced) => (bytes.saturating_sub(replaced), 0),
+        None => (bytes, 1),
+    };
+    check_limits("Client storage", user_usage(user), limits, growth, items)
+}
+
+pub(crate) fn charge_user_usage(user: Principal, replaced: Option<u64>, bytes: u64) {
+    let (added, removed) = match replaced {
+        Some(replaced) => (StorageUsage { bytes, items: 0 }, StorageUsage { bytes: replaced, items: 0 }),
+        None => (StorageUsage { bytes, items: 1 }, StorageUsage::default()),
+    };
+    adjust_user_usage(user, added, removed);
+}
+
+/// Check and charge a client storage write to `key` of `store` in one step;
+/// call right before the record is stored
+pub(crate) fn reserve_user_storage<K: Storable + Ord>(
+    user: Principal,
+    store: UsageStore,
+    key: &K,
+    replaced: Option<u64>,
+    bytes: u64,
+) -> Result<(), BondedError> {
+    check_user_quota(user, replaced, bytes)?;
+    if store.counted(key) {
+        charge_user_usage(user, replaced, bytes);
+    }
+    Ok(())
+}
+
+pub(crate) fn release_user_usage<K: Storable + Ord>(user: Principal, store: UsageStore, key: &K, bytes: u64) {
+    if store.counted(key) {
+        adjust_user_usage(user, StorageUsage::default(), StorageUsage { bytes, items: 1 });
+    }
+}
+
+// ==================
+// USAGE REPORTING
+// ==================
+
+#[query]
+pub fn get_my_usage() -> BondedResult<UsageReport> {
+    let caller = caller_principal();
+    let tier = user_tier(caller);
+
+    let relationship_ids = with_user_store_read(|store| store.get(&caller))
+        .map(|profile| profile.relationships)
+        .unwrap_or_default();
+    let relationships = relationship_ids
+        .iter()
+        .filter_map(|relationship_id| with_relationship_store_read(|store| store.get(relationship_id)))
+        .filter(|relationship| is_relationship_partner(relationship, caller))
+        .map(|relationship| {
+            let tier = relationship_tier(&relationship);
+            RelationshipUsage {
+                usage: relationship_usage(&relationship.id),
+                relationship_id: relationship.id,
+                tier,
+                limits: relationship_limits(tier),
+            }
+        })
+        .collect();
+
+    BondedResult::ok(UsageReport {
+        tier,
+        client_storage: user_usage(caller),
+        client_storage_limits: client_storage_limits(tier),
+        relationships,
+    })
+}
+
+#[update]
+pub fn set_user_quota_tier(user: Principal, tier: QuotaTier) -> BondedResult<QuotaTier> {
+    let caller = caller_principal();
+
+    if let Err(msg) = verify_controller(caller) {
+        return BondedResult::err(msg);
+    }
+
+    let updated = with_user_store(|store| match store.get(&user) {
+        Some(mut profile) => {
+            profile.quota_tier = Some(tier);
+            store.insert(user, profile);
+            true
+        }
+        None => false,
+    });
+    if !updated {
+        return BondedResult::err(BondedError::not_found("User profile"));
+    }
+
+    log_audit_event(caller, "set_user_quota_tier", Some(format!("user:{} tier:{:?}", user, tier)));
+
+    BondedResult::ok(tier)
+}
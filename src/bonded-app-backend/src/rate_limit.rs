@@ -0,0 +1,290 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use candid::Principal;
+use ic_cdk_macros::{inspect_message, query, update};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::time::Duration;
+
+// ==================
+// RATE LIMITING
+// ==================
+// Every update call spends a token from a bucket keyed by (caller, method).
+// Buckets refill at a fixed rate up to their capacity; an empty bucket
+// rejects the call with `RateLimited` until the next token is due.
+//
+// Ingress messages are checked in `canister_inspect_message` so that abusive
+// callers are turned away before they cost the canister cycles. That check
+// runs on a single replica and its state changes are discarded, so it cannot
+// be relied on: update endpoints repeat it with `verify_update_allowed`,
+// which is where the token is actually spent.
+//
+// Controllers are not limited. Anonymous callers may not make update calls.
+
+/// Idle buckets are dropped once they have refilled completely
+const BUCKET_PRUNE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+const SECOND_NS: u64 = 1_000_000_000;
+const MINUTE_NS: u64 = 60 * SECOND_NS;
+
+struct TokenBucket {
+    tokens: u32,
+    updated_at: u64, // Time the last token was credited
+}
+
+thread_local! {
+    // Heap only: an upgrade hands every caller a full bucket
+    static BUCKETS: RefCell<HashMap<(Principal, String), TokenBucket>> = RefCell::new(HashMap::new());
+}
+
+impl TokenBucket {
+    /// Credit the tokens accrued since the last refill
+    fn refill(&mut self, limit: RateLimit, now: u64) {
+        let accrued = now.saturating_sub(self.updated_at) / limit.refill_interval_ns;
+        let tokens = (self.tokens as u64 + accrued).min(limit.capacity as u64) as u32;
+        if tokens >= limit.capacity {
+            self.updated_at = now;
+        } else {
+            self.updated_at += accrued * limit.refill_interval_ns;
+        }
+        self.tokens = tokens;
+    }
+
+    fn is_full(&self, limit: RateLimit, now: u64) -> bool {
+        let accrued = now.saturating_sub(self.updated_at) / limit.refill_interval_ns;
+        self.tokens as u64 + accrued >= limit.capacity as u64
+    }
+}
+
+fn limit(capacity: u32, refill_interval_ns: u64) -> RateLimit {
+    RateLimit { capacity, refill_interval_ns }
+}
+
+fn method_limit(method: &str, limit: RateLimit) -> MethodRateLimit {
+    MethodRateLimit { method: method.to_string(), limit }
+}
+
+/// Limits in force until a controller configures others
+fn default_rate_limit_config() -> RateLimitConfig {
+    RateLimitConfig {
+        enabled: true,
+        default_limit: limit(60, SECOND_NS),
+        method_limits: vec![
+            // Invites and email cost outcall cycles and reach third parties
+            method_limit("create_partner_invite", limit(10, 6 * MINUTE_NS)),
+            method_limit("resend_partner_invite", limit(5, 12 * MINUTE_NS)),
+            method_limit("send_invite_email", limit(5, 12 * MINUTE_NS)),
+            method_limit("send_notification_email", limit(5, 12 * MINUTE_NS)),
+            method_limit("request_invite_code", limit(5, 12 * MINUTE_NS)),
+            method_limit("create_relationship", limit(10, 6 * MINUTE_NS)),
+            // Storage writes
+            method_limit("upload_evidence", limit(30, 10 * SECOND_NS)),
+            method_limit("upload_signed_evidence", limit(30, 10 * SECOND_NS)),
+            method_limit("begin_evidence_upload", limit(30, 10 * SECOND_NS)),
+            method_limit("store_client_data", limit(60, 2 * SECOND_NS)),
+            method_limit("derive_vetkd_key", limit(20, 30 * SECOND_NS)),
+        ],
+    }
+}
+
+pub fn rate_limit_config() -> RateLimitConfig {
+    get_canister_config().rate_limits.unwrap_or_else(default_rate_limit_config)
+}
+
+/// Legacy and typed endpoints share a bucket
+fn canonical_method(method: &str) -> &str {
+    method.strip_suffix("_v2").unwrap_or(method)
+}
+
+fn limit_for(config: &RateLimitConfig, method: &str) -> RateLimit {
+    config
+        .method_limits
+        .iter()
+        .find(|rule| rule.method == method)
+        .map(|rule| rule.limit)
+        .unwrap_or(config.default_limit)
+}
+
+fn take_token(caller: Principal, method: &str, now: u64) -> Result<(), BondedError> {
+    let config = rate_limit_config();
+    if !config.enabled {
+        return Ok(());
+    }
+    let limit = limit_for(&config, method);
+
+    BUCKETS.with(|buckets| {
+        let mut buckets = buckets.borrow_mut();
+        let bucket = buckets
+            .entry((caller, method.to_string()))
+            .or_insert(TokenBucket { tokens: limit.capacity, updated_at: now });
+        bucket.refill(limit, now);
+
+        if bucket.tokens == 0 {
+            let next_token_at = bucket.updated_at + limit.refill_interval_ns;
+            return Err(BondedError::RateLimited { retry_after_ns: next_token_at.saturating_sub(now) });
+        }
+        bucket.tokens -= 1;
+        Ok(())
+    })
+}
+
+/// Gate for every mutating endpoint: rejects anonymous callers and spends a
+/// token from the caller's bucket for `method`
+pub fn verify_update_allowed(caller: Principal, method: &str) -> Result<(), BondedError> {
+    if caller == Principal::anonymous() {
+        return Err(BondedError::unauthorized("Anonymous callers cannot make update calls"));
+    }
+    if ic_cdk::api::is_controller(&caller) {
+        return Ok(());
+    }
+    take_token(caller, canonical_method(method), current_time())
+}
+
+/// Turn away ingress that the update itself would reject, before it is paid for
+#[inspect_message]
+fn inspect_message() {
+    let method = ic_cdk::api::call::method_name();
+    match verify_update_allowed(caller_principal(), &method) {
+        Ok(()) => ic_cdk::api::call::accept_message(),
+        Err(msg) => ic_cdk::trap(&msg.to_string()),
+    }
+}
+
+fn prune_idle_buckets() {
+    let config = rate_limit_config();
+    let now = current_time();
+    BUCKETS.with(|buckets| {
+        buckets
+            .borrow_mut()
+            .retain(|(_, method), bucket| !bucket.is_full(limit_for(&config, method), now));
+    });
+}
+
+pub fn schedule_bucket_pruning() {
+    ic_cdk_timers::set_timer_interval(BUCKET_PRUNE_INTERVAL, prune_idle_buckets);
+}
+
+// ==================
+// RATE LIMIT CONFIGURATION
+// ==================
+
+fn validate_rate_limit(field: &str, limit: &RateLimit) -> Result<(), BondedError> {
+    if limit.capacity == 0 || limit.refill_interval_ns == 0 {
+        return Err(BondedError::invalid_input(field, "Capacity and refill interval must be positive"));
+    }
+    Ok(())
+}
+
+/// Limits currently in force, so clients can pace themselves
+#[query]
+pub fn get_rate_limit_config() -> RateLimitConfig {
+    rate_limit_config()
+}
+
+#[update]
+pub fn set_rate_limit_config(config: RateLimitConfig) -> BondedResult<RateLimitConfig> {
+    let caller = caller_principal();
+
+    if let Err(msg) = verify_controller(caller) {
+        return BondedResult::err(msg);
+    }
+
+    if let Err(msg) = validate_rate_limit("default_limit", &config.default_limit) {
+        return BondedResult::err(msg);
+    }
+    for rule in &config.method_limits {
+        if canonical_method(&rule.method) != rule.method || rule.method.is_empty() {
+            return BondedResult::err(BondedError::invalid_input("method", "Use the endpoint name without the _v2 suffix"));
+        }
+        if let Err(msg) = validate_rate_limit("method_limits", &rule.limit) {
+            return BondedResult::err(msg);
+        }
+    }
+
+    update_canister_config(|canister_config| {
+        canister_config.rate_limits = Some(config.clone());
+    });
+
+    log_audit_event(
+        caller,
+        "set_rate_limit_config",
+        Some(format!("enabled:{} rules:{}", config.enabled, config.method_limits.len())),
+    );
+
+    BondedResult::ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const START: u64 = 1_700_000_000 * SECOND_NS;
+
+    fn caller() -> Principal {
+        Principal::from_slice(&[7; 29])
+    }
+
+    fn retry_after(result: Result<(), BondedError>) -> Option<u64> {
+        match result {
+            Err(BondedError::RateLimited { retry_after_ns }) => Some(retry_after_ns),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn refill_after_partial_drain_keeps_partial_progress() {
+        let limit = limit(5, 10);
+        let mut bucket = TokenBucket { tokens: 2, updated_at: 0 };
+
+        // Two whole intervals have passed; the half interval carries over
+        bucket.refill(limit, 25);
+        assert_eq!(bucket.tokens, 4);
+        assert_eq!(bucket.updated_at, 20);
+
+        bucket.refill(limit, 29);
+        assert_eq!(bucket.tokens, 4);
+        bucket.refill(limit, 30);
+        assert_eq!(bucket.tokens, 5);
+    }
+
+    #[test]
+    fn refill_clamps_to_capacity() {
+        let limit = limit(5, 10);
+        let mut bucket = TokenBucket { tokens: 1, updated_at: 0 };
+
+        bucket.refill(limit, 1_000);
+        assert_eq!(bucket.tokens, 5);
+        assert_eq!(bucket.updated_at, 1_000);
+        assert!(bucket.is_full(limit, 1_000));
+    }
+
+    #[test]
+    fn empty_bucket_reports_time_to_next_token() {
+        // upload_evidence: 30 tokens, one every 10 seconds
+        for _ in 0..30 {
+            assert!(take_token(caller(), "upload_evidence", START).is_ok());
+        }
+
+        assert_eq!(retry_after(take_token(caller(), "upload_evidence", START)), Some(10 * SECOND_NS));
+        assert_eq!(retry_after(take_token(caller(), "upload_evidence", START + 4 * SECOND_NS)), Some(6 * SECOND_NS));
+        assert!(take_token(caller(), "upload_evidence", START + 10 * SECOND_NS).is_ok());
+        assert_eq!(retry_after(take_token(caller(), "upload_evidence", START + 10 * SECOND_NS)), Some(10 * SECOND_NS));
+
+        // Other callers and methods have their own buckets
+        assert!(take_token(Principal::from_slice(&[8; 29]), "upload_evidence", START).is_ok());
+        assert!(take_token(caller(), "begin_evidence_upload", START).is_ok());
+    }
+
+    #[test]
+    fn typed_and_legacy_names_share_a_bucket() {
+        // create_relationship: 10 tokens
+        for call in 0..10 {
+            let method = if call % 2 == 0 { "create_relationship_v2" } else { "create_relationship" };
+            assert!(take_token(caller(), canonical_method(method), START).is_ok());
+        }
+
+        assert!(retry_after(take_token(caller(), canonical_method("create_relationship"), START)).is_some());
+        assert!(retry_after(take_token(caller(), canonical_method("create_relationship_v2"), START)).is_some());
+    }
+}
@@ -1,210 +1,728 @@
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use crate::commitment::update_relationship_commitment;
+use crate::evidence::remove_evidence;
+use crate::email::send_email;
+use crate::email_templates::{render_email, TemplateData};
+use crate::evidence_index::relationship_evidence_ids;
+use crate::kill_switch::{store_kill_switch_key, validate_kill_switch_key};
+use crate::key_rotation::vetkd_relationship_key;
+use crate::vetkd::relationship_public_key;
 use candid::Principal;
 use ic_cdk_macros::{query, update};
+use std::time::Duration;
+
+/// How long partner2 has to answer a relationship request
+const RELATIONSHIP_REQUEST_TTL_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000;
+const RELATIONSHIP_REQUEST_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Allowed window between creating (or resending) an invite and its expiry
+const MIN_INVITE_TTL_NS: u64 = 60 * 60 * 1_000_000_000;
+const MAX_INVITE_TTL_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+/// How long expired and cancelled invites are kept before being pruned
+const INVITE_RETENTION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000;
+const INVITE_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Email verification codes for invite acceptance
+const INVITE_CODE_TTL_NS: u64 = 10 * 60 * 1_000_000_000;
+const INVITE_CODE_RESEND_COOLDOWN_NS: u64 = 60 * 1_000_000_000;
+const MAX_INVITE_CODE_ATTEMPTS: u32 = 5;
+const INVITE_CODE_LOCKOUT_NS: u64 = 30 * 60 * 1_000_000_000;
+
+/// Evidence counts that can be announced with the EvidenceMilestone template
+const EVIDENCE_MILESTONES: &[u64] = &[10, 25, 50, 100, 250, 500, 1000];
+
+/// `inviter_name` ends up in templated email
+const MAX_INVITER_NAME_LENGTH: usize = 64;
 
 // =========================
 // PARTNER INVITE SYSTEM
 // =========================
 
-#[update]
+fn validate_invite_expiry(expires_at: u64, now: u64) -> Result<(), BondedError> {
+    let ttl = expires_at.saturating_sub(now);
+    if !(MIN_INVITE_TTL_NS..=MAX_INVITE_TTL_NS).contains(&ttl) {
+        return Err(BondedError::invalid_input(
+            "expires_at",
+            format!(
+                "Invites must expire between {} hour and {} days from now",
+                MIN_INVITE_TTL_NS / (60 * 60 * 1_000_000_000),
+                MAX_INVITE_TTL_NS / (24 * 60 * 60 * 1_000_000_000)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Keep the name a name: it is shown in email, so no links or template syntax
+fn validate_inviter_name(name: &str) -> Result<(), BondedError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_INVITER_NAME_LENGTH {
+        return Err(BondedError::invalid_input("inviter_name", format!("Name must be 1 to {} characters", MAX_INVITER_NAME_LENGTH)));
+    }
+    if name.chars().any(|c| c.is_control() || matches!(c, '<' | '>' | '{' | '}'))
+        || name.contains("://")
+        || name.to_lowercase().contains("www.")
+    {
+        return Err(BondedError::invalid_input("inviter_name", "Name cannot contain links or markup"));
+    }
+    Ok(())
+}
+
+fn invite_link(frontend_url: Option<String>, invite_token: &str) -> String {
+    match frontend_url {
+        Some(frontend_url) => format!("{}/accept-invite?invite={}", frontend_url, invite_token),
+        // Fallback to a default URL (this should not happen in production)
+        None => format!("https://bonded.app/accept-invite?invite={}", invite_token),
+    }
+}
+
+#[update(name = "create_partner_invite_v2")]
 pub fn create_partner_invite(request: CreatePartnerInviteRequest) -> BondedResult<CreatePartnerInviteResponse> {
     let inviter = caller_principal();
     
-    // Generate secure invite ID
-    let invite_id = with_canister_state(|state| {
-        state.next_invite_id += 1;
-        generate_id("invite", state.next_invite_id)
-    });
+    if let Err(msg) = verify_update_allowed(inviter, "create_partner_invite") {
+        return BondedResult::err(msg);
+    }
+    
+    if let Err(msg) = validate_invite_expiry(request.expires_at, current_time()) {
+        return BondedResult::err(msg);
+    }
+    
+    if !is_valid_email(&request.partner_email) {
+        return BondedResult::err(BondedError::invalid_input("partner_email", "Invalid email address format"));
+    }
+    
+    if let Err(msg) = validate_inviter_name(&request.inviter_name) {
+        return BondedResult::err(msg);
+    }
+    
+    // Registered for the inviter once the relationship exists
+    if let Err(msg) = validate_kill_switch_key(&request.kill_switch_public_key) {
+        return BondedResult::err(msg);
+    }
+    
+    // The address itself is never stored, only a salted hash and a masked hint
+    let email_salt = match generate_invite_email_salt() {
+        Ok(salt) => salt,
+        Err(e) => return BondedResult::err(BondedError::unavailable(format!("Failed to generate email salt: {}", e))),
+    };
+    
+    // Only the token's hash is stored; the token itself goes out in the link
+    let invite_token = match generate_invite_token() {
+        Ok(token) => token,
+        Err(e) => return BondedResult::err(BondedError::unavailable(format!("Failed to generate invite token: {}", e))),
+    };
+    let invite_id = hash_invite_token(&invite_token);
     
     let invite = PartnerInvite {
         id: invite_id.clone(),
         inviter_principal: inviter,
-        partner_email: request.partner_email.clone(),
+        partner_email_hash: hash_invite_email(&email_salt, &request.partner_email),
+        partner_email_salt: email_salt,
+        partner_email_hint: mask_email(&request.partner_email),
         inviter_name: request.inviter_name.clone(),
         status: InviteStatus::Pending,
         created_at: current_time(),
         expires_at: request.expires_at,
         metadata: request.metadata,
+        closed_at: None,
+        inviter_kill_switch_key: Some(request.kill_switch_public_key),
     };
     
     // Store the invite
     with_invite_store(|store| {
-        store.insert(invite_id.clone(), invite.clone());
-        ic_cdk::println!("🎯 INVITE STORED: ID={}, Email={}, Inviter={}", 
-                        invite_id, 
-                        request.partner_email, 
-                        inviter.to_text());
+        store.insert(invite_id, invite);
     });
     
-    // Verify the invite was stored correctly
-    let verification = with_invite_store_read(|store| store.get(&invite_id));
-    match verification {
-        Some(stored_invite) => {
-            ic_cdk::println!("✅ INVITE VERIFICATION: Found stored invite with ID={}", stored_invite.id);
-        },
-        None => {
-            ic_cdk::println!("❌ INVITE VERIFICATION: Failed to find stored invite with ID={}", invite_id);
-        }
+    // Log audit event
+    log_audit_event(inviter, "create_partner_invite", Some(format!("email:{}", mask_email(&request.partner_email))));
+    
+    BondedResult::ok(CreatePartnerInviteResponse {
+        invite_link: invite_link(request.frontend_url, &invite_token),
+        invite_id: invite_token, // What the partner presents to get/accept the invite
+        expires_at: request.expires_at,
+    })
+}
+
+/// Invites the caller has sent, newest first
+#[query]
+pub fn list_my_invites() -> BondedResult<Vec<PartnerInvite>> {
+    let caller = caller_principal();
+    
+    let mut invites: Vec<PartnerInvite> = with_invite_store_read(|store| {
+        store
+            .iter()
+            .filter(|(_, invite)| invite.inviter_principal == caller)
+            .map(|(_, invite)| invite)
+            .collect()
+    });
+    invites.sort_by_key(|invite| std::cmp::Reverse(invite.created_at));
+    
+    BondedResult::ok(invites)
+}
+
+/// Load one of the caller's own invites by `PartnerInvite.id`
+fn owned_invite(invite_id: &str, caller: Principal) -> Result<PartnerInvite, BondedError> {
+    match with_invite_store_read(|store| store.get(&invite_id.to_string())) {
+        Some(invite) if invite.inviter_principal == caller => Ok(invite),
+        _ => Err(BondedError::not_found("Invite")),
     }
+}
+
+#[update]
+pub fn cancel_partner_invite(invite_id: String) -> BondedResult<PartnerInvite> {
+    let caller = caller_principal();
     
-    // Log audit event
-    log_audit_event(inviter, "create_partner_invite", Some(format!("email:{}", request.partner_email)));
+    if let Err(msg) = verify_update_allowed(caller, "cancel_partner_invite") {
+        return BondedResult::err(msg);
+    }
     
-    // Generate dynamic invite link based on the frontend_url provided in the request
-    let invite_link = if let Some(frontend_url) = request.frontend_url {
-        format!("{}/accept-invite?invite={}", frontend_url, invite_id)
-    } else {
-        // Fallback to a default URL (this should not happen in production)
-        format!("https://bonded.app/accept-invite?invite={}", invite_id)
+    let mut invite = match owned_invite(&invite_id, caller) {
+        Ok(invite) => invite,
+        Err(msg) => return BondedResult::err(msg),
     };
+    
+    if invite.status != InviteStatus::Pending {
+        return BondedResult::err(BondedError::conflict("Only pending invites can be cancelled"));
+    }
+    
+    invite.status = InviteStatus::Cancelled;
+    invite.closed_at = Some(current_time());
+    with_invite_store(|store| {
+        store.insert(invite_id.clone(), invite.clone());
+    });
+    with_invite_verification_store(|store| {
+        store.remove(&invite_id);
+    });
+    
+    log_audit_event(caller, "cancel_partner_invite", None);
+    
+    BondedResult::ok(invite)
+}
 
+/// Issue a fresh link for a pending or expired invite. The token is rotated,
+/// so the previous link stops working.
+#[update]
+pub fn resend_partner_invite(request: ResendPartnerInviteRequest) -> BondedResult<CreatePartnerInviteResponse> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "resend_partner_invite") {
+        return BondedResult::err(msg);
+    }
+    let now = current_time();
+    
+    let mut invite = match owned_invite(&request.invite_id, caller) {
+        Ok(invite) => invite,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    if !matches!(invite.status, InviteStatus::Pending | InviteStatus::Expired) {
+        return BondedResult::err(BondedError::conflict("Only pending or expired invites can be resent"));
+    }
+    
+    let expires_at = request
+        .expires_at
+        .unwrap_or_else(|| now + invite.expires_at.saturating_sub(invite.created_at));
+    if let Err(msg) = validate_invite_expiry(expires_at, now) {
+        return BondedResult::err(msg);
+    }
+    
+    let invite_token = match generate_invite_token() {
+        Ok(token) => token,
+        Err(e) => return BondedResult::err(BondedError::unavailable(format!("Failed to generate invite token: {}", e))),
+    };
+    
+    invite.id = hash_invite_token(&invite_token);
+    invite.status = InviteStatus::Pending;
+    invite.created_at = now;
+    invite.expires_at = expires_at;
+    invite.closed_at = None;
+    
+    with_invite_store(|store| {
+        store.remove(&request.invite_id);
+        store.insert(invite.id.clone(), invite.clone());
+    });
+    with_invite_verification_store(|store| {
+        store.remove(&request.invite_id);
+    });
+    
+    log_audit_event(caller, "resend_partner_invite", Some(format!("email:{}", invite.partner_email_hint)));
+    
     BondedResult::ok(CreatePartnerInviteResponse {
-        invite_id: invite_id.clone(),
-        invite_link,
-        expires_at: request.expires_at,
+        invite_link: invite_link(request.frontend_url, &invite_token),
+        invite_id: invite_token,
+        expires_at,
     })
 }
 
-#[update]
-pub fn send_invite_email(request: SendInviteEmailRequest) -> BondedResult<SendEmailResponse> {
+/// Move stale invites to Expired and prune closed ones past retention
+fn sweep_invites() {
+    let now = current_time();
+    let (expired, pruned): (Vec<String>, Vec<String>) = with_invite_store_read(|store| {
+        let mut expired = Vec::new();
+        let mut pruned = Vec::new();
+        for (invite_id, invite) in store.iter() {
+            match invite.status {
+                InviteStatus::Pending if invite.expires_at < now => expired.push(invite_id),
+                InviteStatus::Expired | InviteStatus::Cancelled => {
+                    let closed_at = invite.closed_at.unwrap_or(invite.expires_at);
+                    if closed_at.saturating_add(INVITE_RETENTION_NS) <= now {
+                        pruned.push(invite_id);
+                    }
+                }
+                _ => {}
+            }
+        }
+        (expired, pruned)
+    });
+    
+    if expired.is_empty() && pruned.is_empty() {
+        return;
+    }
+    
+    with_invite_store(|store| {
+        for invite_id in &expired {
+            if let Some(mut invite) = store.get(invite_id) {
+                invite.status = InviteStatus::Expired;
+                invite.closed_at = Some(now);
+                store.insert(invite_id.clone(), invite);
+            }
+        }
+        for invite_id in &pruned {
+            store.remove(invite_id);
+        }
+    });
+    with_invite_verification_store(|store| {
+        for invite_id in expired.iter().chain(&pruned) {
+            store.remove(invite_id);
+        }
+    });
+    ic_cdk::println!("🧹 Expired {} invites, pruned {}", expired.len(), pruned.len());
+}
+
+/// Periodically expire and prune invites.
+/// Timers do not survive upgrades, so call from `init` and `post_upgrade`.
+pub fn schedule_invite_expiry() {
+    ic_cdk_timers::set_timer_interval(INVITE_SWEEP_INTERVAL, sweep_invites);
+}
+
+/// Mail the invite link, rendered from the Invite or InviteReminder template,
+/// to the invited address
+#[update(name = "send_invite_email_v2")]
+pub async fn send_invite_email(request: SendInviteEmailRequest) -> BondedResult<SendEmailResponse> {
     let caller = caller_principal();
     
-    // Validate email format
-    if !is_valid_email(&request.recipient_email) {
-        return BondedResult::err("Invalid email address format");
+    if let Err(msg) = verify_update_allowed(caller, "send_invite_email") {
+        return BondedResult::err(msg);
     }
     
-    // Validate email content length (prevent abuse)
-    if request.email_content.len() > 50000 { // 50KB max
-        return BondedResult::err("Email content too large");
+    if !matches!(request.template, EmailTemplateId::Invite | EmailTemplateId::InviteReminder) {
+        return BondedResult::err(BondedError::invalid_input("template", "Invite emails use the Invite or InviteReminder template"));
     }
     
-    // Log the email attempt with proper audit trail
+    let invite = match open_invite(&request.invite_id, current_time()) {
+        Ok(invite) if invite.inviter_principal == caller => invite,
+        Ok(_) => return BondedResult::err(BondedError::not_found("Invite")),
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    // Only the invited address can receive the invite
+    let email_hash = hash_invite_email(&invite.partner_email_salt, &request.recipient_email);
+    if !constant_time_eq(email_hash.as_bytes(), invite.partner_email_hash.as_bytes()) {
+        return BondedResult::err(BondedError::invalid_input("recipient_email", "Recipient is not the invited address"));
+    }
+    
+    let email = match render_email(
+        request.template,
+        request.locale.unwrap_or_default(),
+        request.recipient_email,
+        &TemplateData::from_invite(&invite, &request.invite_id),
+    ) {
+        Ok(email) => email,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    let email_log = match send_email(email).await {
+        Ok(email_log) => email_log,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
     log_audit_event(
-        caller, 
-        "send_invite_email", 
-        Some(format!("recipient:{}, subject_len:{}, content_len:{}", 
-                    request.recipient_email, 
-                    request.subject.len(),
-                    request.email_content.len()))
+        caller,
+        "send_invite_email",
+        Some(format!("recipient:{}, email_log:{}", invite.partner_email_hint, email_log.id)),
     );
     
-    // Generate a unique message ID for tracking
-    let message_id = format!("bonded_{}_{}", 
-                           current_time(), 
-                           generate_id("email", current_time()));
+    BondedResult::ok(SendEmailResponse {
+        success: true,
+        provider: email_log.delivery.map(|delivery| delivery.provider).unwrap_or_default(),
+        message_id: email_log.id, // Look up delivery status with get_email_delivery
+    })
+}
+
+/// Largest milestone reached, see EVIDENCE_MILESTONES
+fn evidence_milestone(evidence_count: u64) -> Option<u64> {
+    EVIDENCE_MILESTONES.iter().rev().copied().find(|milestone| *milestone <= evidence_count)
+}
+
+/// Mail a relationship notification, rendered from a template with the
+/// relationship's own data, to the other partner's stored address. Only sent
+/// when the event has actually happened.
+#[update]
+pub async fn send_notification_email(request: SendNotificationEmailRequest) -> BondedResult<SendEmailResponse> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "send_notification_email") {
+        return BondedResult::err(msg);
+    }
+    
+    let relationship = match with_relationship_store_read(|store| store.get(&request.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+    
+    // Not verify_relationship_access: destroyed relationships can still be notified about
+    if !is_relationship_partner(&relationship, caller) {
+        return BondedResult::err(BondedError::unauthorized("Not authorized to access this relationship"));
+    }
+    
+    let mut data = TemplateData::from_relationship(&relationship);
+    let happened = match request.template {
+        EmailTemplateId::RelationshipAccepted => matches!(relationship.status, RelationshipStatus::Active),
+        EmailTemplateId::KillSwitchExecuted => matches!(relationship.status, RelationshipStatus::Destroyed),
+        EmailTemplateId::EvidenceMilestone => {
+            data.evidence_count = evidence_milestone(relationship.evidence_count);
+            data.evidence_count.is_some() && matches!(relationship.status, RelationshipStatus::Active)
+        }
+        _ => {
+            return BondedResult::err(BondedError::invalid_input(
+                "template",
+                "Notifications use the RelationshipAccepted, KillSwitchExecuted or EvidenceMilestone template",
+            ))
+        }
+    };
+    if !happened {
+        return BondedResult::err(BondedError::conflict("The relationship has not reached this event"));
+    }
+    
+    // Only ever the partner's own address, never one chosen by the caller
+    let partner = if relationship.partner1 == caller { relationship.partner2 } else { Some(relationship.partner1) };
+    let recipient_email = partner
+        .and_then(|partner| with_user_store_read(|store| store.get(&partner)))
+        .and_then(|profile| profile.email);
+    let recipient_email = match recipient_email {
+        Some(email) => email,
+        None => return BondedResult::err(BondedError::not_found("Partner email address")),
+    };
+    
+    let email = match render_email(request.template, request.locale.unwrap_or_default(), recipient_email.clone(), &data) {
+        Ok(email) => email,
+        Err(msg) => return BondedResult::err(msg),
+    };
     
-    // Store email delivery record for audit purposes
-    let _email_record = format!("{{\"to\":\"{}\",\"subject\":\"{}\",\"sent_at\":{},\"message_id\":\"{}\"}}",
-                              request.recipient_email,
-                              request.subject,
-                              current_time(),
-                              message_id);
+    let email_log = match send_email(email).await {
+        Ok(email_log) => email_log,
+        Err(msg) => return BondedResult::err(msg),
+    };
     
-    // In production, this integrates with email delivery service
-    // The canister trusts that the frontend has properly sent the email
-    // and we maintain delivery records for compliance and audit
+    log_relationship_audit_event(
+        caller,
+        &request.relationship_id,
+        "send_notification_email",
+        Some(format!("recipient:{}, email_log:{}", mask_email(&recipient_email), email_log.id)),
+    );
     
     BondedResult::ok(SendEmailResponse {
         success: true,
-        message_id,
-        provider: "bonded_email_service".to_string(),
+        provider: email_log.delivery.map(|delivery| delivery.provider).unwrap_or_default(),
+        message_id: email_log.id,
     })
 }
 
-#[query]
+/// Look up an invite by the token from its link. The store is keyed by the
+/// token's hash, so lookups reveal nothing about stored tokens.
+fn find_invite(invite_token: &str) -> Option<PartnerInvite> {
+    with_invite_store_read(|store| store.get(&hash_invite_token(invite_token)))
+}
+
+/// `invite_id` is the token from the invite link
+#[query(name = "get_partner_invite_v2")]
 pub fn get_partner_invite(invite_id: String) -> BondedResult<PartnerInvite> {
-    ic_cdk::println!("🔍 GET_PARTNER_INVITE: Looking for ID={}", invite_id);
+    let invite = match find_invite(&invite_id) {
+        Some(invite) => invite,
+        None => return BondedResult::err(BondedError::not_found("Invite")),
+    };
     
-    // First, let's see what invites are stored
-    let stored_invites: Vec<String> = with_invite_store_read(|store| {
-        store.iter().map(|(id, _)| id).collect()
+    if current_time() > invite.expires_at {
+        BondedResult::err(BondedError::expired("Invite"))
+    } else if invite.status != InviteStatus::Pending {
+        BondedResult::err(BondedError::conflict("Invite is no longer valid"))
+    } else {
+        BondedResult::ok(invite)
+    }
+}
+
+// =========================
+// INVITE EMAIL VERIFICATION
+// =========================
+// Holding the link is not enough to accept: the accepter proves control of
+// the invited address by entering a 6-digit code mailed to it. The code is
+// bound to the principal that enters it.
+
+/// A pending, unexpired invite for the token from its link
+fn open_invite(invite_token: &str, now: u64) -> Result<PartnerInvite, BondedError> {
+    let invite = find_invite(invite_token).ok_or_else(|| BondedError::not_found("Invite"))?;
+    if now > invite.expires_at {
+        return Err(BondedError::expired("Invite"));
+    }
+    if invite.status != InviteStatus::Pending {
+        return Err(BondedError::conflict("Invite is no longer valid"));
+    }
+    Ok(invite)
+}
+
+/// Uniformly random 6-digit code
+fn generate_invite_code() -> Result<String, String> {
+    // Largest multiple of 1_000_000 below 2^32, so the modulo is unbiased
+    const LIMIT: u32 = 4_294_000_000;
+    loop {
+        let mut bytes = [0u8; 4];
+        crate::entropy::fill_random(&mut bytes)?;
+        let value = u32::from_be_bytes(bytes);
+        if value < LIMIT {
+            return Ok(format!("{:06}", value % 1_000_000));
+        }
+    }
+}
+
+fn hash_invite_code(invite_id: &str, code: &str) -> String {
+    use sha2::{Digest, Sha256};
+    let mut hasher = Sha256::new();
+    hasher.update(b"bonded:invite-code:v1\n");
+    hasher.update(invite_id.as_bytes());
+    hasher.update(b"\n");
+    hasher.update(code.trim().as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn save_invite_verification(verification: InviteVerification) {
+    with_invite_verification_store(|store| {
+        store.insert(verification.invite_id.clone(), verification);
     });
-    ic_cdk::println!("📋 STORED INVITES: Found {} invites: {:?}", stored_invites.len(), stored_invites);
-    
-    match with_invite_store_read(|store| store.get(&invite_id)) {
-        Some(invite) => {
-            ic_cdk::println!("✅ INVITE FOUND: ID={}, Status={:?}, Created={}, Expires={}", 
-                           invite.id, 
-                           invite.status, 
-                           invite.created_at, 
-                           invite.expires_at);
-            
-            let current_time_val = current_time();
-            ic_cdk::println!("⏰ TIME CHECK: Current={}, Expires={}, Expired={}", 
-                           current_time_val, 
-                           invite.expires_at, 
-                           current_time_val > invite.expires_at);
-            
-            // Check if invite has expired
-            if current_time_val > invite.expires_at {
-                ic_cdk::println!("❌ INVITE EXPIRED");
-                BondedResult::err("Invite has expired")
-            } else if invite.status != InviteStatus::Pending {
-                ic_cdk::println!("❌ INVITE NOT PENDING: Status={:?}", invite.status);
-                BondedResult::err("Invite is no longer valid")
+}
+
+/// Mail a verification code to `email`, which must be the invited address
+#[update]
+pub async fn request_invite_code(invite_id: String, email: String, locale: Option<EmailLocale>) -> BondedResult<InviteCodeResponse> {
+    if let Err(msg) = verify_update_allowed(caller_principal(), "request_invite_code") {
+        return BondedResult::err(msg);
+    }
+    
+    let now = current_time();
+    
+    let invite = match open_invite(&invite_id, now) {
+        Ok(invite) => invite,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    let email_hash = hash_invite_email(&invite.partner_email_salt, &email);
+    if !constant_time_eq(email_hash.as_bytes(), invite.partner_email_hash.as_bytes()) {
+        return BondedResult::err(BondedError::unauthorized("Email does not match this invite"));
+    }
+    
+    let previous = with_invite_verification_store_read(|store| store.get(&invite.id));
+    let mut attempts = 0;
+    if let Some(previous) = &previous {
+        if let Some(locked_until) = previous.locked_until.filter(|locked_until| *locked_until > now) {
+            return BondedResult::err(BondedError::RateLimited { retry_after_ns: locked_until - now });
+        }
+        let next_allowed = previous.issued_at + INVITE_CODE_RESEND_COOLDOWN_NS;
+        if previous.code_hash.is_some() && next_allowed > now {
+            return BondedResult::err(BondedError::RateLimited { retry_after_ns: next_allowed - now });
+        }
+        // Failed attempts carry over to new codes until a lockout resets them
+        if previous.locked_until.is_none() {
+            attempts = previous.attempts;
+        }
+    }
+    
+    let code = match generate_invite_code() {
+        Ok(code) => code,
+        Err(e) => return BondedResult::err(BondedError::unavailable(format!("Failed to generate verification code: {}", e))),
+    };
+    
+    let data = TemplateData {
+        inviter_name: Some(invite.inviter_name.clone()),
+        verification_code: Some(code.clone()),
+        code_valid_minutes: Some(INVITE_CODE_TTL_NS / (60 * 1_000_000_000)),
+        ..Default::default()
+    };
+    let message = match render_email(EmailTemplateId::InviteCode, locale.unwrap_or_default(), email.clone(), &data) {
+        Ok(message) => message,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    let expires_at = now + INVITE_CODE_TTL_NS;
+    save_invite_verification(InviteVerification {
+        invite_id: invite.id.clone(),
+        code_hash: Some(hash_invite_code(&invite.id, &code)),
+        issued_at: now,
+        expires_at,
+        attempts,
+        locked_until: None,
+        verified_by: None,
+        email: Some(email),
+    });
+    
+    if let Err(msg) = send_email(message).await {
+        // Undelivered codes do not count against the resend cooldown
+        with_invite_verification_store(|store| match previous {
+            Some(previous) => store.insert(invite.id.clone(), previous),
+            None => store.remove(&invite.id),
+        });
+        return BondedResult::err(msg);
+    }
+    
+    log_audit_event(caller_principal(), "request_invite_code", Some(format!("email:{}", invite.partner_email_hint)));
+    
+    BondedResult::ok(InviteCodeResponse {
+        email_hint: invite.partner_email_hint,
+        expires_at,
+    })
+}
+
+/// Check the mailed code; on success the caller may accept the invite
+#[update]
+pub fn verify_invite_code(invite_id: String, code: String) -> BondedResult<String> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "verify_invite_code") {
+        return BondedResult::err(msg);
+    }
+    let now = current_time();
+    
+    let invite = match open_invite(&invite_id, now) {
+        Ok(invite) => invite,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    let mut verification = match with_invite_verification_store_read(|store| store.get(&invite.id)) {
+        Some(verification) => verification,
+        None => return BondedResult::err(BondedError::not_found("Verification code")),
+    };
+    
+    match check_invite_code(&mut verification, &code, caller, now) {
+        Ok(InviteCodeCheck::AlreadyVerified) => BondedResult::ok("Email already verified".to_string()),
+        Ok(InviteCodeCheck::Verified) => {
+            save_invite_verification(verification);
+            log_audit_event(caller, "verify_invite_code", None);
+            BondedResult::ok("Email verified".to_string())
+        }
+        Ok(InviteCodeCheck::Incorrect { locked }) => {
+            save_invite_verification(verification);
+            log_audit_event(caller, "verify_invite_code_failed", Some(format!("locked:{}", locked)));
+            if locked {
+                BondedResult::err(BondedError::RateLimited { retry_after_ns: INVITE_CODE_LOCKOUT_NS })
             } else {
-                ic_cdk::println!("✅ INVITE VALID: Returning invite data");
-                BondedResult::ok(invite)
+                BondedResult::err(BondedError::unauthorized("Incorrect verification code"))
             }
-        },
-        None => {
-            ic_cdk::println!("❌ INVITE NOT FOUND: ID={}", invite_id);
-            BondedResult::err("Invite not found")
-        },
+        }
+        Err(msg) => BondedResult::err(msg),
     }
 }
 
-#[query]
-pub fn debug_list_all_invites() -> BondedResult<Vec<String>> {
-    let invites: Vec<String> = with_invite_store_read(|store| {
-        store.iter().map(|(id, invite)| {
-            format!("ID: {}, Email: {}, Status: {:?}, Created: {}", 
-                   id, 
-                   invite.partner_email, 
-                   invite.status, 
-                   invite.created_at)
-        }).collect()
-    });
+enum InviteCodeCheck {
+    Verified,
+    AlreadyVerified,
+    Incorrect { locked: bool },
+}
+
+/// Compare `code` with the stored verification, counting failed attempts
+/// towards the lockout; the caller persists `verification` unless an error
+/// is returned
+fn check_invite_code(verification: &mut InviteVerification, code: &str, caller: Principal, now: u64) -> Result<InviteCodeCheck, BondedError> {
+    if let Some(locked_until) = verification.locked_until.filter(|locked_until| *locked_until > now) {
+        return Err(BondedError::RateLimited { retry_after_ns: locked_until - now });
+    }
     
-    ic_cdk::println!("🔍 DEBUG_LIST_ALL_INVITES: Found {} invites", invites.len());
-    for invite_info in &invites {
-        ic_cdk::println!("  - {}", invite_info);
+    let code_hash = match &verification.code_hash {
+        Some(code_hash) => code_hash.clone(),
+        None if verification.verified_by == Some(caller) => return Ok(InviteCodeCheck::AlreadyVerified),
+        None => return Err(BondedError::not_found("Verification code")),
+    };
+    
+    if now >= verification.expires_at {
+        return Err(BondedError::expired("Verification code"));
     }
     
-    BondedResult::ok(invites)
+    if !constant_time_eq(hash_invite_code(&verification.invite_id, code).as_bytes(), code_hash.as_bytes()) {
+        verification.attempts += 1;
+        let locked = verification.attempts >= MAX_INVITE_CODE_ATTEMPTS;
+        if locked {
+            // The code is burned; a new one can be requested after the lockout
+            verification.code_hash = None;
+            verification.attempts = 0;
+            verification.locked_until = Some(now + INVITE_CODE_LOCKOUT_NS);
+        }
+        return Ok(InviteCodeCheck::Incorrect { locked });
+    }
+    
+    verification.code_hash = None;
+    verification.attempts = 0;
+    verification.verified_by = Some(caller);
+    
+    Ok(InviteCodeCheck::Verified)
 }
 
-#[update]
-pub fn accept_partner_invite(invite_id: String) -> BondedResult<AcceptInviteResponse> {
+#[update(name = "accept_partner_invite_v2")]
+pub async fn accept_partner_invite(invite_id: String, kill_switch_public_key: Vec<u8>) -> BondedResult<AcceptInviteResponse> {
     let accepter = caller_principal();
     
-    let mut invite = match with_invite_store_read(|store| store.get(&invite_id)) {
+    if let Err(msg) = verify_update_allowed(accepter, "accept_partner_invite") {
+        return BondedResult::err(msg);
+    }
+    
+    // Fetched before any state is read, so nothing below spans the call
+    let public_key = match relationship_public_key().await {
+        Ok(public_key) => public_key,
+        Err(msg) => return BondedResult::err(msg),
+    };
+    
+    let mut invite = match find_invite(&invite_id) {
         Some(inv) => inv,
-        None => return BondedResult::err("Invite not found"),
+        None => return BondedResult::err(BondedError::not_found("Invite")),
     };
     
     // Validate invite
     if current_time() > invite.expires_at {
-        return BondedResult::err("Invite has expired");
+        return BondedResult::err(BondedError::expired("Invite"));
     }
     
     if invite.status != InviteStatus::Pending {
-        return BondedResult::err("Invite is no longer valid");
+        return BondedResult::err(BondedError::conflict("Invite is no longer valid"));
     }
     
-    // For production, prevent self-acceptance
-    // For testing/development, allow self-acceptance with debug logging
     if invite.inviter_principal == accepter {
-        ic_cdk::println!("⚠️ DEBUG: Same user trying to accept own invite - allowing for testing purposes");
-        ic_cdk::println!("   Inviter: {}", invite.inviter_principal.to_text());
-        ic_cdk::println!("   Accepter: {}", accepter.to_text());
-        
-        // In a real production environment, uncomment this line:
-        return BondedResult::err("Cannot accept your own invite");
+        return BondedResult::err(BondedError::invalid_input("invite_id", "Cannot accept your own invite"));
+    }
+    
+    // Proof of control of the invited email, see verify_invite_code
+    let verification = with_invite_verification_store_read(|store| store.get(&invite.id))
+        .filter(|verification| verification.verified_by == Some(accepter));
+    let Some(verification) = verification else {
+        return BondedResult::err(BondedError::unauthorized("Verify the invited email address before accepting"));
+    };
+    
+    // Both partners' kill switch keys are registered at setup (spec 3.1)
+    if let Err(msg) = validate_kill_switch_key(&kill_switch_public_key) {
+        return BondedResult::err(msg);
     }
+    let Some(inviter_kill_switch_key) = invite.inviter_kill_switch_key.take() else {
+        return BondedResult::err(BondedError::conflict("Invite predates kill switch setup; ask your partner for a new invite"));
+    };
     
     // Create relationship
     let relationship_id = with_canister_state(|state| {
@@ -212,58 +730,61 @@ pub fn accept_partner_invite(invite_id: String) -> BondedResult<AcceptInviteResp
         generate_id("relationship", state.next_relationship_id)
     });
     
-    // Generate real threshold keys using proper cryptography
-    let master_key = generate_master_key();
-    let (user1_share, user2_share, bonded_share) = match split_key_threshold(&master_key) {
-        Ok(shares) => shares,
-        Err(e) => return BondedResult::err(&format!("Failed to generate key shares: {}", e)),
-    };
-    let public_key = match derive_public_key(&master_key) {
-        Ok(pk) => pk,
-        Err(e) => return BondedResult::err(&format!("Failed to derive public key: {}", e)),
-    };
-    
     let relationship = Relationship {
         id: relationship_id.clone(),
         partner1: invite.inviter_principal,
         partner2: Some(accepter),
         status: RelationshipStatus::Active,
         created_at: current_time(),
-        bonded_key_share: bonded_share,
         evidence_count: 0,
         last_activity: current_time(),
+        destroyed_at: None,
+        destroyed_by: None,
+        signing_keys: Vec::new(),
+        relationship_keys: vec![vetkd_relationship_key(1, public_key.clone(), current_time())],
+        key_rotation: None,
+        expires_at: None,
+        accepted_at: Some(current_time()),
+        key_scheme: RelationshipKeyScheme::VetKd,
     };
     
     // Store the relationship
     with_relationship_store(|store| {
         store.insert(relationship_id.clone(), relationship.clone());
     });
+    update_relationship_commitment(&relationship_id);
     
-    // Store key shares securely for both users
-    if let Err(e) = store_user_key_share(invite.inviter_principal, &relationship_id, user1_share.clone()) {
-        return BondedResult::err(&format!("Failed to store inviter key share: {}", e));
+    if let Err(msg) = store_kill_switch_key(&relationship_id, invite.inviter_principal, inviter_kill_switch_key) {
+        return BondedResult::err(msg);
     }
-    if let Err(e) = store_user_key_share(accepter, &relationship_id, user2_share.clone()) {
-        return BondedResult::err(&format!("Failed to store accepter key share: {}", e));
+    if let Err(msg) = store_kill_switch_key(&relationship_id, accepter, kill_switch_public_key) {
+        return BondedResult::err(msg);
     }
     
     // Update both users' profiles
     update_user_relationship_list(invite.inviter_principal, &relationship_id);
     update_user_relationship_list(accepter, &relationship_id);
+    if let Some(email) = verification.email {
+        set_notification_email(accepter, email);
+    }
     
     // Mark invite as accepted
     invite.status = InviteStatus::Accepted;
+    invite.closed_at = Some(current_time());
+    with_invite_verification_store(|store| {
+        store.remove(&invite.id);
+    });
     with_invite_store(|store| {
-        store.insert(invite_id.clone(), invite);
+        store.insert(invite.id.clone(), invite);
     });
     
     // Log audit event
-    log_audit_event(accepter, "accept_partner_invite", Some(relationship_id.clone()));
+    log_relationship_audit_event(accepter, &relationship_id, "accept_partner_invite", None);
     
     BondedResult::ok(AcceptInviteResponse {
         relationship_id,
         relationship,
-        user_key_share: user2_share, // Return accepter's key share
+        user_key_share: Vec::new(),
         public_key,
     })
 }
@@ -272,13 +793,29 @@ pub fn accept_partner_invite(invite_id: String) -> BondedResult<AcceptInviteResp
 // RELATIONSHIP MANAGEMENT
 // =========================
 
-#[update]
-pub fn create_relationship(request: CreateRelationshipRequest) -> BondedResult<CreateRelationshipResponse> {
+/// Request a relationship with `partner_principal`. It stays Pending, with
+/// no access for the partner, until they call `accept_relationship`.
+#[update(name = "create_relationship_v2")]
+pub async fn create_relationship(request: CreateRelationshipRequest) -> BondedResult<CreateRelationshipResponse> {
     let user1 = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(user1, "create_relationship") {
+        return BondedResult::err(msg);
+    }
+    
+    // Fetched before any state is touched, so nothing below spans the call
+    let public_key = match relationship_public_key().await {
+        Ok(public_key) => public_key,
+        Err(msg) => return BondedResult::err(msg),
+    };
     let user2 = request.partner_principal;
     
     if user1 == user2 {
-        return BondedResult::err("Cannot create relationship with yourself");
+        return BondedResult::err(BondedError::invalid_input("partner_principal", "Cannot create relationship with yourself"));
+    }
+    
+    if let Err(msg) = validate_kill_switch_key(&request.kill_switch_public_key) {
+        return BondedResult::err(msg);
     }
     
     // Generate a unique relationship ID
@@ -287,119 +824,224 @@ pub fn create_relationship(request: CreateRelationshipRequest) -> BondedResult<C
         generate_id("relationship", state.next_relationship_id)
     });
     
-    // Generate real threshold keys using proper cryptography
-    let master_key = generate_master_key();
-    let (user1_share, user2_share, bonded_share) = match split_key_threshold(&master_key) {
-        Ok(shares) => shares,
-        Err(e) => return BondedResult::err(&format!("Failed to generate key shares: {}", e)),
-    };
-    let public_key = match derive_public_key(&master_key) {
-        Ok(pk) => pk,
-        Err(e) => return BondedResult::err(&format!("Failed to derive public key: {}", e)),
-    };
-    
     let relationship = Relationship {
         id: relationship_id.clone(),
         partner1: user1,
-        partner2: Some(user2), // In MVP, we auto-accept for simplicity
-        status: RelationshipStatus::Active, // In MVP, relationships are immediately active
+        partner2: Some(user2),
+        status: RelationshipStatus::Pending, // Until user2 accepts
         created_at: current_time(),
-        bonded_key_share: bonded_share,
         evidence_count: 0,
         last_activity: current_time(),
+        destroyed_at: None,
+        destroyed_by: None,
+        signing_keys: Vec::new(),
+        relationship_keys: vec![vetkd_relationship_key(1, public_key.clone(), current_time())],
+        key_rotation: None,
+        expires_at: Some(current_time() + RELATIONSHIP_REQUEST_TTL_NS),
+        accepted_at: None,
+        key_scheme: RelationshipKeyScheme::VetKd,
     };
     
     with_relationship_store(|store| {
         store.insert(relationship_id.clone(), relationship);
     });
+    update_relationship_commitment(&relationship_id);
     
-    // Store key shares securely for both users
-    if let Err(e) = store_user_key_share(user1, &relationship_id, user1_share.clone()) {
-        return BondedResult::err(&format!("Failed to store user1 key share: {}", e));
-    }
-    if let Err(e) = store_user_key_share(user2, &relationship_id, user2_share.clone()) {
-        return BondedResult::err(&format!("Failed to store user2 key share: {}", e));
+    // user2 registers theirs when accepting
+    if let Err(msg) = store_kill_switch_key(&relationship_id, user1, request.kill_switch_public_key) {
+        return BondedResult::err(msg);
     }
     
-    // Update both users' profiles
+    // user2's profile is updated on acceptance
     update_user_relationship_list(user1, &relationship_id);
-    update_user_relationship_list(user2, &relationship_id);
     
     // Log audit event
-    log_audit_event(user1, "create_relationship", Some(relationship_id.clone()));
+    log_relationship_audit_event(user1, &relationship_id, "create_relationship", None);
     
     BondedResult::ok(CreateRelationshipResponse {
         relationship_id,
-        user_key_share: user1_share, // Return the calling user's share
+        user_key_share: Vec::new(),
         public_key,
     })
 }
 
-#[update]
-pub fn accept_relationship(relationship_id: String) -> BondedResult<Vec<u8>> {
+/// Join a requested relationship. Its keys are derived with
+/// `derive_vetkd_key`; the returned key share is only non-empty when
+/// re-accepting a relationship that predates vetKD.
+#[update(name = "accept_relationship_v2")]
+pub fn accept_relationship(relationship_id: String, kill_switch_public_key: Vec<u8>) -> BondedResult<Vec<u8>> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "accept_relationship") {
+        return BondedResult::err(msg);
+    }
+    
     let mut relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     // Verify caller is user2 in the relationship
     if relationship.partner2 != Some(caller) {
-        return BondedResult::err("Not authorized to accept this relationship");
+        return BondedResult::err(BondedError::unauthorized("Not authorized to accept this relationship"));
+    }
+    
+    match relationship.status {
+        RelationshipStatus::Pending => {}
+        // Accepting again just returns the share, if there is one
+        RelationshipStatus::Active if relationship.key_scheme == RelationshipKeyScheme::VetKd => {
+            return BondedResult::ok(Vec::new());
+        }
+        RelationshipStatus::Active => {
+            return match get_user_key_share(caller, &relationship_id) {
+                Some(key_share) => BondedResult::ok(key_share),
+                None => BondedResult::err(BondedError::not_found("Key share")),
+            };
+        }
+        RelationshipStatus::Destroyed => return BondedResult::err(BondedError::not_found("Relationship data")),
+        RelationshipStatus::Expired => return BondedResult::err(BondedError::expired("Relationship request")),
+        _ => return BondedResult::err(BondedError::conflict("Relationship request is no longer open")),
+    }
+    
+    let now = current_time();
+    if relationship.expires_at.is_some_and(|expires_at| now >= expires_at) {
+        close_relationship_request(relationship, RelationshipStatus::Expired);
+        return BondedResult::err(BondedError::expired("Relationship request"));
+    }
+    
+    if let Err(msg) = validate_kill_switch_key(&kill_switch_public_key) {
+        return BondedResult::err(msg);
+    }
+    
+    if let Err(msg) = store_kill_switch_key(&relationship_id, caller, kill_switch_public_key) {
+        return BondedResult::err(msg);
     }
     
-    // Update relationship status (in MVP, this is already active)
     relationship.status = RelationshipStatus::Active;
-    relationship.last_activity = current_time();
+    relationship.expires_at = None;
+    relationship.accepted_at = Some(now);
+    relationship.last_activity = now;
     
     with_relationship_store(|store| {
         store.insert(relationship_id.clone(), relationship);
     });
+    update_user_relationship_list(caller, &relationship_id);
     
     // Log audit event
-    log_audit_event(caller, "accept_relationship", Some(relationship_id.clone()));
+    log_relationship_audit_event(caller, &relationship_id, "accept_relationship", None);
     
-    // Return the user's real key share for this relationship
-    match get_user_key_share(caller, &relationship_id) {
-        Some(key_share) => BondedResult::ok(key_share),
-        None => BondedResult::err("Key share not found for this user and relationship"),
-    }
+    BondedResult::ok(Vec::new())
 }
 
 #[update]
-pub fn terminate_relationship(relationship_id: String) -> BondedResult<String> {
+pub fn decline_relationship(relationship_id: String) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "decline_relationship") {
+        return BondedResult::err(msg);
+    }
+    
     let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
-    // Verify caller is part of the relationship
-    if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+    // Verify caller is user2 in the relationship
+    if relationship.partner2 != Some(caller) {
+        return BondedResult::err(BondedError::unauthorized("Not authorized to decline this relationship"));
     }
     
-    // Delete all evidence for this relationship
-    let evidence_to_delete: Vec<String> = with_evidence_store_read(|store| {
+    if !matches!(relationship.status, RelationshipStatus::Pending) {
+        return BondedResult::err(BondedError::conflict("Relationship request is no longer open"));
+    }
+    
+    close_relationship_request(relationship, RelationshipStatus::Declined);
+    
+    log_relationship_audit_event(caller, &relationship_id, "decline_relationship", None);
+    
+    BondedResult::ok(format!("Relationship request {} declined", relationship_id))
+}
+
+/// Close an unanswered request: its key material and any evidence partner1
+/// uploaded in the meantime are removed
+fn close_relationship_request(mut relationship: Relationship, status: RelationshipStatus) {
+    let relationship_id = relationship.id.clone();
+    
+    for evidence_id in relationship_evidence_ids(&relationship_id) {
+        remove_evidence(&evidence_id);
+    }
+    update_relationship_commitment(&relationship_id);
+    
+    for partner in [Some(relationship.partner1), relationship.partner2].into_iter().flatten() {
+        remove_user_key_shares(partner, &relationship_id);
+    }
+    remove_bonded_key_shares(&relationship_id);
+    
+    relationship.status = status;
+    relationship.evidence_count = 0;
+    relationship.expires_at = None;
+    relationship.last_activity = current_time();
+    
+    with_relationship_store(|store| {
+        store.insert(relationship_id, relationship);
+    });
+}
+
+fn sweep_expired_relationship_requests() {
+    let now = current_time();
+    let expired: Vec<Relationship> = with_relationship_store_read(|store| {
         store
             .iter()
-            .filter_map(|(id, evidence)| {
-                if evidence.relationship_id == relationship_id {
-                    Some(id.clone())
-                } else {
-                    None
-                }
+            .filter(|(_, relationship)| {
+                matches!(relationship.status, RelationshipStatus::Pending)
+                    && relationship.expires_at.is_some_and(|expires_at| expires_at <= now)
             })
+            .map(|(_, relationship)| relationship)
             .collect()
     });
     
-    with_evidence_store(|store| {
-        for evidence_id in evidence_to_delete {
-            store.remove(&evidence_id);
-        }
-    });
+    if expired.is_empty() {
+        return;
+    }
+    
+    let count = expired.len();
+    for relationship in expired {
+        let (relationship_id, partner1) = (relationship.id.clone(), relationship.partner1);
+        close_relationship_request(relationship, RelationshipStatus::Expired);
+        log_relationship_audit_event(partner1, &relationship_id, "expire_relationship_request", None);
+    }
+    ic_cdk::println!("🧹 Expired {} unanswered relationship requests", count);
+}
+
+/// Periodically expire unanswered relationship requests.
+/// Timers do not survive upgrades, so call from `init` and `post_upgrade`.
+pub fn schedule_relationship_request_expiry() {
+    ic_cdk_timers::set_timer_interval(RELATIONSHIP_REQUEST_SWEEP_INTERVAL, sweep_expired_relationship_requests);
+}
+
+#[update(name = "terminate_relationship_v2")]
+pub fn terminate_relationship(relationship_id: String) -> BondedResult<String> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "terminate_relationship") {
+        return BondedResult::err(msg);
+    }
+    
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+    
+    // Verify caller is part of the relationship
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+    
+    // Delete all evidence for this relationship
+    for evidence_id in relationship_evidence_ids(&relationship_id) {
+        remove_evidence(&evidence_id);
+    }
+    update_relationship_commitment(&relationship_id);
     
     // Mark relationship as terminated (don't delete for audit purposes)
     let mut updated_relationship = relationship;
@@ -411,29 +1053,29 @@ pub fn terminate_relationship(relationship_id: String) -> BondedResult<String> {
     });
     
     // Log audit event
-    log_audit_event(caller, "terminate_relationship", Some(relationship_id.clone()));
+    log_relationship_audit_event(caller, &relationship_id, "terminate_relationship", None);
     
     BondedResult::ok(format!("Relationship {} terminated and all evidence deleted", relationship_id))
 }
 
-#[query]
+#[query(name = "get_relationship_v2")]
 pub fn get_relationship(relationship_id: String) -> BondedResult<Relationship> {
     let caller = caller_principal();
     
     let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
-    // Verify caller is part of the relationship
-    if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+    // Verify caller is part of the relationship; destroyed ones stay readable
+    if let Err(msg) = verify_relationship_read_access(&relationship, caller) {
+        return BondedResult::err(msg);
     }
     
     BondedResult::ok(relationship)
 }
 
-#[query]
+#[query(name = "get_user_relationships_v2")]
 pub fn get_user_relationships() -> BondedResult<Vec<Relationship>> {
     let caller = caller_principal();
     
@@ -453,28 +1095,39 @@ pub fn get_user_relationships() -> BondedResult<Vec<Relationship>> {
     BondedResult::ok(relationships)
 }
 
-#[query]
+/// The caller's threshold key share; vetKD relationships have none, their
+/// keys come from `derive_vetkd_key`
+#[query(name = "get_key_share_v2")]
 pub fn get_key_share(relationship_id: String) -> BondedResult<Vec<u8>> {
     let caller = caller_principal();
     
     let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
         Some(rel) => rel,
-        None => return BondedResult::err("Relationship not found"),
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
     };
     
     // Verify caller is part of the relationship
     if let Err(msg) = verify_relationship_access(&relationship, caller) {
-        return BondedResult::err(&msg);
+        return BondedResult::err(msg);
     }
     
     // Return the user's real key share for this relationship
     match get_user_key_share(caller, &relationship_id) {
         Some(key_share) => BondedResult::ok(key_share),
-        None => BondedResult::err("Key share not found for this user and relationship"),
+        None => BondedResult::err(BondedError::not_found("Key share")),
     }
 }
 
-// Helper function to update user relationship list
+/// Record an address the user has proven control of
+fn set_notification_email(user: Principal, email: String) {
+    with_user_store(|store| {
+        if let Some(mut profile) = store.get(&user) {
+            profile.email = Some(email);
+            store.insert(user, profile);
+        }
+    });
+}
+
 fn update_user_relationship_list(user: Principal, relationship_id: &str) {
     with_user_store(|store| {
         if let Some(mut profile) = store.get(&user) {
@@ -491,9 +1144,75 @@ fn update_user_relationship_list(user: Principal, relationship_id: &str) {
                 relationships: vec![relationship_id.to_string()],
                 total_evidence_uploaded: 0,
                 kyc_verified: false,
+                quota_tier: None,
+                email: None,
                 last_seen: current_time(),
             };
             store.insert(user, profile);
         }
     });
-} 
\ No newline at end of file
+} 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000_000_000_000;
+    const CODE: &str = "123456";
+
+    fn verification() -> InviteVerification {
+        InviteVerification {
+            invite_id: "invite_1".to_string(),
+            code_hash: Some(hash_invite_code("invite_1", CODE)),
+            issued_at: NOW,
+            expires_at: NOW + INVITE_CODE_TTL_NS,
+            attempts: 0,
+            locked_until: None,
+            verified_by: None,
+            email: None,
+        }
+    }
+
+    fn caller() -> Principal {
+        Principal::from_slice(&[7; 29])
+    }
+
+    #[test]
+    fn invite_code_locks_out_after_max_failures() {
+        let mut verification = verification();
+        for attempt in 1..MAX_INVITE_CODE_ATTEMPTS {
+            assert!(matches!(check_invite_code(&mut verification, "000000", caller(), NOW), Ok(InviteCodeCheck::Incorrect { locked: false })));
+            assert_eq!(verification.attempts, attempt);
+        }
+
+        assert!(matches!(check_invite_code(&mut verification, "000000", caller(), NOW), Ok(InviteCodeCheck::Incorrect { locked: true })));
+        assert_eq!(verification.code_hash, None);
+        assert_eq!(verification.locked_until, Some(NOW + INVITE_CODE_LOCKOUT_NS));
+
+        // Even the right code is refused until the lockout ends
+        assert!(matches!(
+            check_invite_code(&mut verification, CODE, caller(), NOW + 60),
+            Err(BondedError::RateLimited { retry_after_ns }) if retry_after_ns == INVITE_CODE_LOCKOUT_NS - 60
+        ));
+        // ...and afterwards the burned code is gone
+        assert!(matches!(
+            check_invite_code(&mut verification, CODE, caller(), NOW + INVITE_CODE_LOCKOUT_NS),
+            Err(BondedError::NotFound { .. })
+        ));
+    }
+
+    #[test]
+    fn correct_invite_code_resets_failed_attempts() {
+        let mut verification = verification();
+        for _ in 1..MAX_INVITE_CODE_ATTEMPTS {
+            assert!(matches!(check_invite_code(&mut verification, "000000", caller(), NOW), Ok(InviteCodeCheck::Incorrect { locked: false })));
+        }
+
+        assert!(matches!(check_invite_code(&mut verification, CODE, caller(), NOW), Ok(InviteCodeCheck::Verified)));
+        assert_eq!(verification.attempts, 0);
+        assert_eq!(verification.code_hash, None);
+        assert_eq!(verification.locked_until, None);
+        assert_eq!(verification.verified_by, Some(caller()));
+
+        assert!(matches!(check_invite_code(&mut verification, CODE, caller(), NOW), Ok(InviteCodeCheck::AlreadyVerified)));
+    }
+}
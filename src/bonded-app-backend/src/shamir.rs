@@ -0,0 +1,262 @@
+// =============================
+// SHAMIR SECRET SHARING (GF(256))
+// =============================
+// Byte-wise Shamir secret sharing over GF(2^8) with the AES reduction
+// polynomial x^8 + x^4 + x^3 + x + 1. Each share carries its evaluation
+// point so any `threshold` distinct shares reconstruct the secret.
+
+/// A single indexed share. `index` is the non-zero x-coordinate the
+/// polynomials were evaluated at; `value` holds one y-coordinate per byte.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyShare {
+    pub index: u8,
+    pub value: Vec<u8>,
+}
+
+impl KeyShare {
+    /// Wire format: `[index || value]`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.value.len() + 1);
+        bytes.push(self.index);
+        bytes.extend_from_slice(&self.value);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        match bytes.split_first() {
+            Some((&0, _)) => Err("Share index must be non-zero".to_string()),
+            Some((&index, value)) if !value.is_empty() => Ok(KeyShare {
+                index,
+                value: value.to_vec(),
+            }),
+            _ => Err("Share is too short".to_string()),
+        }
+    }
+}
+
+/// Split `secret` into `share_count` shares, any `threshold` of which
+/// reconstruct it. Shares are issued at x = 1..=share_count.
+pub fn split_secret(secret: &[u8], threshold: u8, share_count: u8) -> Result<Vec<KeyShare>, String> {
+    if secret.is_empty() {
+        return Err("Secret cannot be empty".to_string());
+    }
+    if threshold < 2 {
+        return Err("Threshold must be at least 2".to_string());
+    }
+    if share_count < threshold {
+        return Err("Share count must be at least the threshold".to_string());
+    }
+
+    // One random polynomial per secret byte: f(0) = secret byte,
+    // remaining (threshold - 1) coefficients uniformly random.
+    let coefficient_count = (threshold - 1) as usize;
+    let mut coefficients = vec![0u8; secret.len() * coefficient_count];
+    getrandom::getrandom(&mut coefficients)
+        .map_err(|e| format!("Failed to generate share coefficients: {}", e))?;
+
+    let shares = (1..=share_count)
+        .map(|x| {
+            let value = secret
+                .iter()
+                .enumerate()
+                .map(|(i, &byte)| evaluate(byte, &coefficients[i * coefficient_count..(i + 1) * coefficient_count], x))
+                .collect();
+            KeyShare { index: x, value }
+        })
+        .collect();
+
+    // Coefficients are key material
+    coefficients.iter_mut().for_each(|c| *c = 0);
+
+    Ok(shares)
+}
+
+/// f(x) for f(0) = `secret_byte` and the higher-degree `coefficients`
+fn evaluate(secret_byte: u8, coefficients: &[u8], x: u8) -> u8 {
+    // Horner evaluation from the highest-degree coefficient down
+    let acc = coefficients.iter().rev().fold(0u8, |acc, &c| gf_mul(acc, x) ^ c);
+    gf_mul(acc, x) ^ secret_byte
+}
+
+/// Reconstruct the secret from shares using Lagrange interpolation at x = 0.
+/// The caller is responsible for supplying at least `threshold` shares;
+/// fewer shares yield an unrelated value rather than an error.
+pub fn reconstruct_secret(shares: &[KeyShare]) -> Result<Vec<u8>, String> {
+    if shares.len() < 2 {
+        return Err("At least two shares are required".to_string());
+    }
+
+    let secret_len = shares[0].value.len();
+    for (i, share) in shares.iter().enumerate() {
+        if share.index == 0 {
+            return Err("Share index must be non-zero".to_string());
+        }
+        if share.value.len() != secret_len {
+            return Err("All shares must have the same length".to_string());
+        }
+        if shares[..i].iter().any(|other| other.index == share.index) {
+            return Err("Shares must have distinct indices".to_string());
+        }
+    }
+
+    // Lagrange basis at zero: l_j(0) = prod_{m != j} x_m / (x_m - x_j)
+    // (subtraction is XOR in characteristic 2)
+    let basis: Vec<u8> = shares
+        .iter()
+        .map(|share_j| {
+            shares
+                .iter()
+                .filter(|share_m| share_m.index != share_j.index)
+                .fold(1u8, |acc, share_m| {
+                    gf_mul(acc, gf_div(share_m.index, share_m.index ^ share_j.index))
+                })
+        })
+        .collect();
+
+    let secret = (0..secret_len)
+        .map(|i| {
+            shares
+                .iter()
+                .zip(&basis)
+                .fold(0u8, |acc, (share, &l)| acc ^ gf_mul(share.value[i], l))
+        })
+        .collect();
+
+    Ok(secret)
+}
+
+// =============================
+// GF(256) ARITHMETIC
+// =============================
+// Branch-free on the operand values so timing does not depend on key bytes.
+
+fn gf_mul(mut a: u8, mut b: u8) -> u8 {
+    let mut product = 0u8;
+    for _ in 0..8 {
+        product ^= a & 0u8.wrapping_sub(b & 1);
+        let carry = 0u8.wrapping_sub(a >> 7);
+        a = (a << 1) ^ (0x1b & carry);
+        b >>= 1;
+    }
+    product
+}
+
+/// Multiplicative inverse via a^254 (a^255 = 1 for non-zero a)
+fn gf_inv(a: u8) -> u8 {
+    let a2 = gf_mul(a, a);
+    let a4 = gf_mul(a2, a2);
+    let a8 = gf_mul(a4, a4);
+    let a16 = gf_mul(a8, a8);
+    let a32 = gf_mul(a16, a16);
+    let a64 = gf_mul(a32, a32);
+    let a128 = gf_mul(a64, a64);
+    // 254 = 128 + 64 + 32 + 16 + 8 + 4 + 2
+    [a64, a32, a16, a8, a4, a2].iter().fold(a128, |acc, &p| gf_mul(acc, p))
+}
+
+fn gf_div(a: u8, b: u8) -> u8 {
+    gf_mul(a, gf_inv(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::{RngCore, SeedableRng};
+
+    /// Every subset of `shares` with at least `min_size` members
+    fn subsets(shares: &[KeyShare], min_size: usize) -> Vec<Vec<KeyShare>> {
+        (0u32..1 << shares.len())
+            .filter(|mask| mask.count_ones() as usize >= min_size)
+            .map(|mask| {
+                shares
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| mask & (1 << i) != 0)
+                    .map(|(_, share)| share.clone())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn any_threshold_subset_reconstructs_the_secret() {
+        let mut rng = ChaCha20Rng::seed_from_u64(1);
+        for (threshold, share_count) in [(2, 2), (2, 3), (3, 3), (3, 5), (4, 6), (6, 6)] {
+            for _ in 0..20 {
+                let mut secret = vec![0u8; 1 + rng.next_u32() as usize % 64];
+                rng.fill_bytes(&mut secret);
+
+                let shares = split_secret(&secret, threshold, share_count).unwrap();
+                assert_eq!(shares.len(), share_count as usize);
+                for subset in subsets(&shares, threshold as usize) {
+                    assert_eq!(reconstruct_secret(&subset).unwrap(), secret);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn one_share_of_two_is_uniform_for_every_secret() {
+        // With threshold 2 a share is f(x) = a*x + s. Over all 256 choices of
+        // the random coefficient `a`, every share value occurs exactly once,
+        // whatever the secret: the share says nothing about `s`.
+        for secret in 0..=255u8 {
+            for x in 1..=255u8 {
+                let mut seen = [false; 256];
+                for a in 0..=255u8 {
+                    seen[evaluate(secret, &[a], x) as usize] = true;
+                }
+                assert!(seen.iter().all(|&hit| hit), "secret {} leaks through x = {}", secret, x);
+            }
+        }
+    }
+
+    #[test]
+    fn two_shares_of_three_are_uniform_for_every_secret() {
+        // Threshold 3: each pair of share values occurs for exactly one of the
+        // 65536 coefficient pairs, so two shares are independent of the secret
+        for (secret, x1, x2) in [(0u8, 1u8, 2u8), (0x5a, 1, 3), (0xff, 2, 3), (0x01, 7, 200)] {
+            let mut counts = vec![0u8; 1 << 16];
+            for a1 in 0..=255u8 {
+                for a2 in 0..=255u8 {
+                    let y1 = evaluate(secret, &[a1, a2], x1);
+                    let y2 = evaluate(secret, &[a1, a2], x2);
+                    counts[(y1 as usize) << 8 | y2 as usize] += 1;
+                }
+            }
+            assert!(counts.iter().all(|&count| count == 1), "secret {:#x} leaks through x = {}, {}", secret, x1, x2);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_parameters_and_shares() {
+        assert!(split_secret(&[], 2, 3).is_err());
+        assert!(split_secret(b"secret", 1, 3).is_err());
+        assert!(split_secret(b"secret", 3, 2).is_err());
+
+        let shares = split_secret(b"secret", 2, 3).unwrap();
+        assert!(reconstruct_secret(&shares[..1]).is_err());
+        assert!(reconstruct_secret(&[shares[0].clone(), shares[0].clone()]).is_err());
+
+        let mut short = shares[1].clone();
+        short.value.pop();
+        assert!(reconstruct_secret(&[shares[0].clone(), short]).is_err());
+    }
+
+    #[test]
+    fn share_bytes_round_trip() {
+        for share in split_secret(b"0123456789abcdef0123456789abcdef", 2, 3).unwrap() {
+            assert_eq!(KeyShare::from_bytes(&share.to_bytes()).unwrap(), share);
+        }
+        assert!(KeyShare::from_bytes(&[0, 1, 2]).is_err());
+        assert!(KeyShare::from_bytes(&[1]).is_err());
+    }
+
+    #[test]
+    fn field_inverse_is_correct() {
+        for a in 1..=255u8 {
+            assert_eq!(gf_mul(a, gf_inv(a)), 1);
+        }
+    }
+}
@@ -20,6 +20,24 @@ pub type GeoCacheStorage = StableBTreeMap<String, GeolocationCache, Memory>;
 pub type SchedulerStorage = StableBTreeMap<Principal, SchedulerSettings, Memory>;
 pub type ContentStorage = StableBTreeMap<String, ProcessedContent, Memory>;
 pub type KeyShareStorage = StableBTreeMap<String, UserKeyShare, Memory>;
+pub type AuditLogStorage = StableBTreeMap<u64, AuditLogEntry, Memory>;
+pub type AuditIndexStorage = StableBTreeMap<String, u64, Memory>;
+pub type CommitmentStorage = StableBTreeMap<String, RelationshipCommitment, Memory>;
+pub type KillSwitchKeyStorage = StableBTreeMap<String, KillSwitchKey, Memory>;
+pub type ConfigStorage = StableBTreeMap<String, CanisterConfig, Memory>;
+pub type UploadSessionStorage = StableBTreeMap<String, UploadSession, Memory>;
+pub type EvidenceChunkStorage = StableBTreeMap<String, Vec<u8>, Memory>;
+pub type EvidenceIndexStorage = StableBTreeMap<String, EvidenceSummary, Memory>;
+pub type EvidenceTagIndexStorage = StableBTreeMap<String, String, Memory>;
+pub type EvidenceUploadIndexStorage = StableBTreeMap<String, String, Memory>;
+pub type MigrationStorage = StableBTreeMap<String, MigrationState, Memory>;
+pub type WrappedKeyStorage = StableBTreeMap<String, WrappedKey, Memory>;
+pub type InviteVerificationStorage = StableBTreeMap<String, InviteVerification, Memory>;
+pub type RelationshipUsageStorage = StableBTreeMap<String, StorageUsage, Memory>;
+pub type UserUsageStorage = StableBTreeMap<Principal, StorageUsage, Memory>;
+pub type UploadChunkReceiptStorage = StableBTreeMap<String, UploadChunkReceipt, Memory>;
+pub type EvidenceCountStorage = StableBTreeMap<String, u64, Memory>;
+pub type BondedKeyShareStorage = StableBTreeMap<String, Vec<u8>, Memory>;
 
 // Memory layout
 const EVIDENCE_MEMORY_ID: MemoryId = MemoryId::new(0);
@@ -36,6 +54,27 @@ const GEO_CACHE_MEMORY_ID: MemoryId = MemoryId::new(10);
 const SCHEDULER_MEMORY_ID: MemoryId = MemoryId::new(11);
 const CONTENT_MEMORY_ID: MemoryId = MemoryId::new(12);
 const KEY_SHARE_MEMORY_ID: MemoryId = MemoryId::new(13);
+const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(14);
+const AUDIT_INDEX_MEMORY_ID: MemoryId = MemoryId::new(15);
+const COMMITMENT_MEMORY_ID: MemoryId = MemoryId::new(16);
+const KILL_SWITCH_KEY_MEMORY_ID: MemoryId = MemoryId::new(17);
+const CONFIG_MEMORY_ID: MemoryId = MemoryId::new(18);
+const UPLOAD_SESSION_MEMORY_ID: MemoryId = MemoryId::new(19);
+const EVIDENCE_CHUNK_MEMORY_ID: MemoryId = MemoryId::new(20);
+const EVIDENCE_INDEX_MEMORY_ID: MemoryId = MemoryId::new(21);
+const EVIDENCE_TAG_INDEX_MEMORY_ID: MemoryId = MemoryId::new(22);
+const EVIDENCE_UPLOAD_INDEX_MEMORY_ID: MemoryId = MemoryId::new(23);
+const MIGRATION_MEMORY_ID: MemoryId = MemoryId::new(24);
+const WRAPPED_KEY_MEMORY_ID: MemoryId = MemoryId::new(25);
+const INVITE_VERIFICATION_MEMORY_ID: MemoryId = MemoryId::new(26);
+const RELATIONSHIP_USAGE_MEMORY_ID: MemoryId = MemoryId::new(27);
+const USER_USAGE_MEMORY_ID: MemoryId = MemoryId::new(28);
+const UPLOAD_CHUNK_RECEIPT_MEMORY_ID: MemoryId = MemoryId::new(29);
+const EVIDENCE_COUNT_MEMORY_ID: MemoryId = MemoryId::new(30);
+const BONDED_KEY_SHARE_MEMORY_ID: MemoryId = MemoryId::new(31);
+
+// Single entry in CONFIG_STORE holding the canister configuration
+const CANISTER_CONFIG_KEY: &str = "canister_config";
 
 // Global state management
 thread_local! {
@@ -126,6 +165,116 @@ thread_local! {
         )
     );
     
+    static AUDIT_LOG_STORE: RefCell<AuditLogStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_LOG_MEMORY_ID)),
+        )
+    );
+    
+    static AUDIT_INDEX_STORE: RefCell<AuditIndexStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(AUDIT_INDEX_MEMORY_ID)),
+        )
+    );
+    
+    static COMMITMENT_STORE: RefCell<CommitmentStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(COMMITMENT_MEMORY_ID)),
+        )
+    );
+    
+    static KILL_SWITCH_KEY_STORE: RefCell<KillSwitchKeyStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(KILL_SWITCH_KEY_MEMORY_ID)),
+        )
+    );
+    
+    static CONFIG_STORE: RefCell<ConfigStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(CONFIG_MEMORY_ID)),
+        )
+    );
+    
+    static UPLOAD_SESSION_STORE: RefCell<UploadSessionStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(UPLOAD_SESSION_MEMORY_ID)),
+        )
+    );
+    
+    static EVIDENCE_CHUNK_STORE: RefCell<EvidenceChunkStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EVIDENCE_CHUNK_MEMORY_ID)),
+        )
+    );
+    
+    static EVIDENCE_INDEX_STORE: RefCell<EvidenceIndexStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EVIDENCE_INDEX_MEMORY_ID)),
+        )
+    );
+    
+    static EVIDENCE_TAG_INDEX_STORE: RefCell<EvidenceTagIndexStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EVIDENCE_TAG_INDEX_MEMORY_ID)),
+        )
+    );
+    
+    static EVIDENCE_UPLOAD_INDEX_STORE: RefCell<EvidenceUploadIndexStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EVIDENCE_UPLOAD_INDEX_MEMORY_ID)),
+        )
+    );
+    
+    static MIGRATION_STORE: RefCell<MigrationStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(MIGRATION_MEMORY_ID)),
+        )
+    );
+    
+    static WRAPPED_KEY_STORE: RefCell<WrappedKeyStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(WRAPPED_KEY_MEMORY_ID)),
+        )
+    );
+    
+    static INVITE_VERIFICATION_STORE: RefCell<InviteVerificationStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(INVITE_VERIFICATION_MEMORY_ID)),
+        )
+    );
+    
+    static RELATIONSHIP_USAGE_STORE: RefCell<RelationshipUsageStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(RELATIONSHIP_USAGE_MEMORY_ID)),
+        )
+    );
+    
+    static USER_USAGE_STORE: RefCell<UserUsageStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(USER_USAGE_MEMORY_ID)),
+        )
+    );
+    
+    static UPLOAD_CHUNK_RECEIPT_STORE: RefCell<UploadChunkReceiptStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(UPLOAD_CHUNK_RECEIPT_MEMORY_ID)),
+        )
+    );
+    
+    static EVIDENCE_COUNT_STORE: RefCell<EvidenceCountStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(EVIDENCE_COUNT_MEMORY_ID)),
+        )
+    );
+    
+    // Bonded's shares of threshold relationship keys, by relationship and key
+    // version. Kept out of `Relationship` so no endpoint ever returns them.
+    static BONDED_KEY_SHARE_STORE: RefCell<BondedKeyShareStorage> = RefCell::new(
+        StableBTreeMap::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(BONDED_KEY_SHARE_MEMORY_ID)),
+        )
+    );
+    
     static CANISTER_STATE: RefCell<CanisterState> = RefCell::new(CanisterState::default());
 }
 
@@ -134,10 +283,8 @@ thread_local! {
 pub struct CanisterState {
     pub next_evidence_id: u64,
     pub next_relationship_id: u64,
-    pub next_invite_id: u64,
-    pub total_evidence_count: u64,
-    pub total_relationship_count: u64,
-    pub total_invite_count: u64,
+    pub next_upload_id: u64,
+    pub next_email_id: u64,
 }
 
 // Storage access functions
@@ -185,10 +332,6 @@ pub fn with_canister_state<R>(f: impl FnOnce(&mut CanisterState) -> R) -> R {
     CANISTER_STATE.with(|state| f(&mut state.borrow_mut()))
 }
 
-pub fn with_canister_state_read<R>(f: impl FnOnce(&CanisterState) -> R) -> R {
-    CANISTER_STATE.with(|state| f(&state.borrow()))
-}
-
 // Timeline storage accessors
 pub fn with_timeline_store<R>(f: impl FnOnce(&mut TimelineStorage) -> R) -> R {
     TIMELINE_STORE.with(|store| f(&mut store.borrow_mut()))
@@ -270,6 +413,163 @@ pub fn with_key_share_store_read<R>(f: impl FnOnce(&KeyShareStorage) -> R) -> R
     KEY_SHARE_STORE.with(|store| f(&store.borrow()))
 }
 
+// Audit log storage accessors
+pub fn with_audit_log_store<R>(f: impl FnOnce(&mut AuditLogStorage) -> R) -> R {
+    AUDIT_LOG_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_audit_log_store_read<R>(f: impl FnOnce(&AuditLogStorage) -> R) -> R {
+    AUDIT_LOG_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_audit_index_store<R>(f: impl FnOnce(&mut AuditIndexStorage) -> R) -> R {
+    AUDIT_INDEX_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_audit_index_store_read<R>(f: impl FnOnce(&AuditIndexStorage) -> R) -> R {
+    AUDIT_INDEX_STORE.with(|store| f(&store.borrow()))
+}
+
+// Commitment storage accessors
+pub fn with_commitment_store<R>(f: impl FnOnce(&mut CommitmentStorage) -> R) -> R {
+    COMMITMENT_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_commitment_store_read<R>(f: impl FnOnce(&CommitmentStorage) -> R) -> R {
+    COMMITMENT_STORE.with(|store| f(&store.borrow()))
+}
+
+// Kill switch key storage accessors
+pub fn with_kill_switch_key_store<R>(f: impl FnOnce(&mut KillSwitchKeyStorage) -> R) -> R {
+    KILL_SWITCH_KEY_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_kill_switch_key_store_read<R>(f: impl FnOnce(&KillSwitchKeyStorage) -> R) -> R {
+    KILL_SWITCH_KEY_STORE.with(|store| f(&store.borrow()))
+}
+
+// Chunked upload storage functions
+pub fn with_upload_session_store<R>(f: impl FnOnce(&mut UploadSessionStorage) -> R) -> R {
+    UPLOAD_SESSION_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_upload_session_store_read<R>(f: impl FnOnce(&UploadSessionStorage) -> R) -> R {
+    UPLOAD_SESSION_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_upload_chunk_receipt_store<R>(f: impl FnOnce(&mut UploadChunkReceiptStorage) -> R) -> R {
+    UPLOAD_CHUNK_RECEIPT_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_upload_chunk_receipt_store_read<R>(f: impl FnOnce(&UploadChunkReceiptStorage) -> R) -> R {
+    UPLOAD_CHUNK_RECEIPT_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_evidence_chunk_store<R>(f: impl FnOnce(&mut EvidenceChunkStorage) -> R) -> R {
+    EVIDENCE_CHUNK_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_evidence_chunk_store_read<R>(f: impl FnOnce(&EvidenceChunkStorage) -> R) -> R {
+    EVIDENCE_CHUNK_STORE.with(|store| f(&store.borrow()))
+}
+
+// Evidence index storage functions
+pub fn with_evidence_index_store<R>(f: impl FnOnce(&mut EvidenceIndexStorage) -> R) -> R {
+    EVIDENCE_INDEX_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_evidence_index_store_read<R>(f: impl FnOnce(&EvidenceIndexStorage) -> R) -> R {
+    EVIDENCE_INDEX_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_evidence_count_store<R>(f: impl FnOnce(&mut EvidenceCountStorage) -> R) -> R {
+    EVIDENCE_COUNT_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_evidence_count_store_read<R>(f: impl FnOnce(&EvidenceCountStorage) -> R) -> R {
+    EVIDENCE_COUNT_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_evidence_tag_index_store<R>(f: impl FnOnce(&mut EvidenceTagIndexStorage) -> R) -> R {
+    EVIDENCE_TAG_INDEX_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_evidence_tag_index_store_read<R>(f: impl FnOnce(&EvidenceTagIndexStorage) -> R) -> R {
+    EVIDENCE_TAG_INDEX_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_evidence_upload_index_store<R>(f: impl FnOnce(&mut EvidenceUploadIndexStorage) -> R) -> R {
+    EVIDENCE_UPLOAD_INDEX_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_evidence_upload_index_store_read<R>(f: impl FnOnce(&EvidenceUploadIndexStorage) -> R) -> R {
+    EVIDENCE_UPLOAD_INDEX_STORE.with(|store| f(&store.borrow()))
+}
+
+// Schema migration storage functions
+pub fn with_migration_store<R>(f: impl FnOnce(&mut MigrationStorage) -> R) -> R {
+    MIGRATION_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_migration_store_read<R>(f: impl FnOnce(&MigrationStorage) -> R) -> R {
+    MIGRATION_STORE.with(|store| f(&store.borrow()))
+}
+
+// Wrapped evidence key storage functions
+pub fn with_wrapped_key_store<R>(f: impl FnOnce(&mut WrappedKeyStorage) -> R) -> R {
+    WRAPPED_KEY_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_wrapped_key_store_read<R>(f: impl FnOnce(&WrappedKeyStorage) -> R) -> R {
+    WRAPPED_KEY_STORE.with(|store| f(&store.borrow()))
+}
+
+// Invite email verification storage functions
+pub fn with_invite_verification_store<R>(f: impl FnOnce(&mut InviteVerificationStorage) -> R) -> R {
+    INVITE_VERIFICATION_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_invite_verification_store_read<R>(f: impl FnOnce(&InviteVerificationStorage) -> R) -> R {
+    INVITE_VERIFICATION_STORE.with(|store| f(&store.borrow()))
+}
+
+// Storage quota usage functions
+pub fn with_relationship_usage_store<R>(f: impl FnOnce(&mut RelationshipUsageStorage) -> R) -> R {
+    RELATIONSHIP_USAGE_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_relationship_usage_store_read<R>(f: impl FnOnce(&RelationshipUsageStorage) -> R) -> R {
+    RELATIONSHIP_USAGE_STORE.with(|store| f(&store.borrow()))
+}
+
+pub fn with_user_usage_store<R>(f: impl FnOnce(&mut UserUsageStorage) -> R) -> R {
+    USER_USAGE_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+pub fn with_user_usage_store_read<R>(f: impl FnOnce(&UserUsageStorage) -> R) -> R {
+    USER_USAGE_STORE.with(|store| f(&store.borrow()))
+}
+
+// Bonded key share storage functions
+pub fn with_bonded_key_share_store<R>(f: impl FnOnce(&mut BondedKeyShareStorage) -> R) -> R {
+    BONDED_KEY_SHARE_STORE.with(|store| f(&mut store.borrow_mut()))
+}
+
+// Canister configuration
+pub fn get_canister_config() -> CanisterConfig {
+    CONFIG_STORE.with(|store| store.borrow().get(&CANISTER_CONFIG_KEY.to_string()).unwrap_or_default())
+}
+
+pub fn update_canister_config<R>(f: impl FnOnce(&mut CanisterConfig) -> R) -> R {
+    CONFIG_STORE.with(|store| {
+        let mut store = store.borrow_mut();
+        let mut config = store.get(&CANISTER_CONFIG_KEY.to_string()).unwrap_or_default();
+        let result = f(&mut config);
+        store.insert(CANISTER_CONFIG_KEY.to_string(), config);
+        result
+    })
+}
+
 // Statistics functions
 pub fn get_storage_stats() -> (u64, u64, u64, u64) {
     let evidence_count = with_evidence_store_read(|store| store.len());
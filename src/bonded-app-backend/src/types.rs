@@ -25,7 +25,10 @@ pub struct Evidence {
     pub upload_timestamp: u64,
     pub hash: String,
     pub uploader: Principal,
-    pub signature: Option<Vec<u8>>,
+    pub signature: Option<Vec<u8>>, // Uploader's Ed25519 signature over the attestation message
+    pub signed_at: Option<u64>,     // When the signature was verified; selects the key set to check it against
+    pub content: Option<EvidenceContent>, // Set for chunked uploads; encrypted_data is then empty
+    pub countersignature: Option<EvidenceCountersignature>, // Other partner's confirmation
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -33,6 +36,16 @@ pub enum RelationshipStatus {
     Pending,
     Active,
     Terminated,
+    Destroyed, // Kill switch executed; all evidence overwritten and removed
+    Declined,  // Partner declined the relationship request
+    Expired,   // Relationship request was not answered in time
+}
+
+/// Where a relationship's encryption keys come from
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RelationshipKeyScheme {
+    ThresholdShares, // 2-of-3 Shamir shares of a canister-generated key (relationships created before vetKD)
+    VetKd,           // Derived per key version through vetKD; the canister holds no key material
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -42,9 +55,42 @@ pub struct Relationship {
     pub partner2: Option<Principal>,
     pub status: RelationshipStatus,
     pub created_at: u64,
-    pub bonded_key_share: Vec<u8>, // Bonded's share of the 2-of-3 threshold key
     pub evidence_count: u64,
     pub last_activity: u64,
+    pub destroyed_at: Option<u64>,
+    pub destroyed_by: Option<Principal>,
+    pub signing_keys: Vec<PartnerSigningKey>, // Partners' device keys for evidence attestation, with history
+    pub relationship_keys: Vec<RelationshipKey>, // Relationship public keys, with history
+    pub key_rotation: Option<KeyRotation>, // Set while a previous key version awaits retirement
+    pub expires_at: Option<u64>, // Deadline for partner2 to answer a Pending request
+    pub accepted_at: Option<u64>, // When partner2 joined; None while Pending
+    pub key_scheme: RelationshipKeyScheme,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PartnerSigningKey {
+    pub partner: Principal,
+    pub public_key: Vec<u8>, // 32-byte Ed25519 public key
+    pub registered_at: u64,  // Activation time
+    pub revoked_at: Option<u64>,
+    pub device_name: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipKey {
+    pub version: u32,
+    pub public_key: Vec<u8>, // Ed25519 public key of a threshold key; the relationship context's vetKD public key for vetKD keys
+    pub activated_at: u64,
+    pub revoked_at: Option<u64>,
+}
+
+/// Keys that were active for a relationship at a point in time
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipKeySet {
+    pub relationship_id: String,
+    pub at: u64,
+    pub relationship_key: Option<RelationshipKey>,
+    pub signing_keys: Vec<PartnerSigningKey>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -55,6 +101,8 @@ pub struct UserProfile {
     pub total_evidence_uploaded: u64,
     pub kyc_verified: bool,
     pub last_seen: u64,
+    pub quota_tier: Option<QuotaTier>, // Set by a controller; None = Standard
+    pub email: Option<String>, // Notification address, given at registration or verified with an invite code
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
@@ -111,6 +159,26 @@ pub struct EmailLog {
     pub user: Principal,
     pub log_data: String, // JSON encoded email log
     pub created_at: u64,
+    pub delivery: Option<EmailDelivery>, // Set for email the canister sent itself
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailDelivery {
+    pub category: String,         // e.g. "invite_code"
+    pub provider: String,
+    pub recipient_hint: String,   // Masked address; recipients are not stored
+    pub subject: String,
+    pub status: EmailDeliveryStatus,
+    pub http_status: Option<u32>,
+    pub error: Option<String>,
+    pub completed_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EmailDeliveryStatus {
+    Pending, // Outcall in flight, or the call trapped before recording a result
+    Sent,    // Accepted by the provider
+    Failed,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -147,15 +215,447 @@ pub struct UserKeyShare {
     pub relationship_id: String,
     pub key_share: Vec<u8>, // Encrypted key share for this user
     pub created_at: u64,
+    pub key_version: u32, // Relationship key version the share belongs to
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub struct AuditLogEntry {
     pub id: String,
+    pub sequence: u64,
     pub user: Principal,
+    pub relationship_id: Option<String>, // Set for relationship-scoped actions
     pub action: String,
     pub timestamp: u64,
     pub metadata: Option<String>,
+    pub previous_hash: String, // entry_hash of sequence - 1 (zero hash for genesis)
+    pub entry_hash: String,    // SHA-256 over this entry's fields and previous_hash
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditLogPage {
+    pub entries: Vec<AuditLogEntry>, // Newest first
+    pub next_before: Option<u64>,    // Pass as `before` to fetch the next page
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditLogHead {
+    pub length: u64,
+    pub head_hash: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditChainVerification {
+    pub checked_from: u64,
+    pub checked_count: u64,
+    pub valid: bool,
+    pub first_invalid_sequence: Option<u64>,
+}
+
+// =======================
+// CHUNKED EVIDENCE UPLOAD
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceContent {
+    pub content_id: String, // Key prefix of the content's chunks in EVIDENCE_CHUNK_STORE
+    pub total_size: u64,
+    pub chunk_size: u32,
+    pub chunk_count: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BeginEvidenceUploadRequest {
+    pub relationship_id: String,
+    pub metadata: EvidenceMetadata,
+    pub hash: String, // Hex SHA-256 of the complete encrypted file
+    pub total_size: u64,
+    pub chunk_size: u32,            // Every chunk but the last must be exactly this size
+    pub chunk_hashes: Vec<String>, // Hex SHA-256 of each chunk, in order
+    pub wrapped_key: Option<Vec<u8>>, // Evidence DEK wrapped under the current relationship key
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UploadSession {
+    pub upload_id: String,
+    pub relationship_id: String,
+    pub uploader: Principal,
+    pub metadata: EvidenceMetadata,
+    pub hash: String,
+    pub total_size: u64,
+    pub chunk_size: u32,
+    pub chunk_hashes: Vec<String>,
+    pub created_at: u64,
+    pub wrapped_key: Option<Vec<u8>>,
+}
+
+/// SHA-256 midstate that can be stored between messages
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Sha256Progress {
+    pub state: Vec<u32>,   // The eight working words
+    pub buffered: Vec<u8>, // Input not yet filling a 64-byte block
+    pub length: u64,       // Bytes consumed so far
+}
+
+/// Per-chunk upload state, keyed like the chunk itself
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UploadChunkReceipt {
+    pub received_at: u64,
+    pub prefix_hash: Option<Sha256Progress>, // Over chunks 0..=index, once every earlier chunk is hashed
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UploadSessionStatus {
+    pub upload_id: String,
+    pub chunk_count: u32,
+    pub missing_chunks: Vec<u32>, // Resume by sending exactly these
+    pub verified_chunks: u32,     // Leading chunks already folded into the file hash
+    pub expires_at: u64,
+}
+
+// =======================
+// EVIDENCE DOWNLOAD
+// =======================
+
+/// Evidence without its ciphertext, for timelines and listings
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceSummary {
+    pub id: String,
+    pub relationship_id: String,
+    pub metadata: EvidenceMetadata,
+    pub upload_timestamp: u64,
+    pub hash: String,
+    pub uploader: Principal,
+    pub signature: Option<Vec<u8>>,
+    pub signed_at: Option<u64>,
+    pub size: u64,        // Ciphertext size in bytes
+    pub chunk_count: u32, // Fetch with get_evidence_chunk(id, 0..chunk_count)
+    pub countersignature: Option<EvidenceCountersignature>,
+    pub attestation: AttestationState,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceCountersignature {
+    pub partner: Principal,
+    pub signature: Vec<u8>, // Ed25519 signature over the countersignature message
+    pub signed_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AttestationState {
+    Unsigned,      // No uploader signature yet
+    Signed,        // Signed by the uploader, awaiting the other partner
+    Countersigned, // Signed by the uploader and confirmed by the other partner
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceSigningMessages {
+    pub evidence_id: String,
+    pub signature_message: Vec<u8>,        // Signed by the uploader (sign_evidence)
+    pub countersignature_message: Vec<u8>, // Signed by the other partner (countersign_evidence)
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceChunk {
+    pub evidence_id: String,
+    pub index: u32,
+    pub chunk_count: u32,
+    pub total_size: u64,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EvidenceDownloadToken {
+    pub evidence_id: String,
+    pub token: String,
+    pub url_path: String, // Path to GET from the canister's HTTP gateway, token included
+    pub expires_at: u64,
+}
+
+// =======================
+// HTTP GATEWAY
+// =======================
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    pub streaming_strategy: Option<StreamingStrategy>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StreamingCallbackToken {
+    pub evidence_id: String,
+    pub index: u32,
+    pub download_token: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct StreamingCallbackHttpResponse {
+    pub body: Vec<u8>,
+    pub token: Option<StreamingCallbackToken>,
+}
+
+candid::define_function!(pub StreamingCallback : (StreamingCallbackToken) -> (StreamingCallbackHttpResponse) query);
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum StreamingStrategy {
+    Callback {
+        callback: StreamingCallback,
+        token: StreamingCallbackToken,
+    },
+}
+
+// =======================
+// KEY ROTATION
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRotation {
+    pub from_version: u32,
+    pub to_version: u32,
+    pub started_at: u64,
+    pub started_by: Principal,
+    pub acknowledged_by: Vec<Principal>,
+}
+
+/// An evidence item's data-encryption key, wrapped under one relationship key version
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WrappedKey {
+    pub evidence_id: String,
+    pub relationship_id: String,
+    pub key_version: u32,
+    pub wrapped_key: Vec<u8>,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct WrappedKeyInput {
+    pub evidence_id: String,
+    pub wrapped_key: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRotationResponse {
+    pub relationship_id: String,
+    pub key_version: u32,
+    pub user_key_share: Vec<u8>, // Caller's share of the new key; empty under vetKD, derive it instead
+    pub public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KeyRotationStatus {
+    pub relationship_id: String,
+    pub key_version: u32,                // Current key version
+    pub rotation: Option<KeyRotation>,   // None once the previous version is retired
+    pub pending_rewrap: u64,             // Evidence whose DEK is not yet wrapped under the current version
+}
+
+// =======================
+// KILL SWITCH
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KillSwitchKey {
+    pub relationship_id: String,
+    pub partner: Principal,
+    pub context: String,     // kill_switch:<relationshipID>:<partnerPrincipal>
+    pub public_key: Vec<u8>, // Ed25519 verifying key for delete_all_data messages bound to `context`
+    pub registered_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KillSwitchRequest {
+    pub relationship_id: String,
+    pub issued_at: u64,      // Signed into the message; must be recent
+    pub signature: Vec<u8>,  // Ed25519 signature over the delete_all_data message
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct KillSwitchReceipt {
+    pub relationship_id: String,
+    pub destroyed_at: u64,
+    pub destroyed_by: Principal,
+    pub evidence_destroyed: u64,
+}
+
+// =======================
+// CERTIFIED DATA COMMITMENTS
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipCommitment {
+    pub relationship_id: String,
+    pub root_hash: Vec<u8>, // Merkle root over the relationship's Evidence.hash values (H_input)
+    pub evidence_count: u64,
+    pub updated_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipCommitmentResponse {
+    pub relationship_id: String,
+    pub root_hash: String, // Hex-encoded H_input
+    pub evidence_count: u64,
+    pub updated_at: u64,
+    pub witness: Vec<u8>,             // CBOR hash tree: ["relationship_commitments", relationship_id] -> root_hash
+    pub certificate: Option<Vec<u8>>, // IC certificate over the canister's certified data
+}
+
+// =======================
+// PERMANENT PASSPORT
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TimelineRange {
+    pub start: Option<u64>, // Inclusive bound on metadata.timestamp
+    pub end: Option<u64>,   // Inclusive bound on metadata.timestamp
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipTimelineDocument {
+    pub version: String, // Timeline logic version, e.g. "timeline_logic_v1"
+    pub format: String,  // MIME type of `document`
+    pub relationship_id: String,
+    pub document: Vec<u8>,        // Canonical timeline document T
+    pub timeline_hash: String,    // H_timeline = SHA-256(T), hex
+    pub input_commitment: String, // H_input the document was rendered from, hex
+}
+
+// =======================
+// CANISTER CONFIGURATION
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CanisterConfig {
+    pub vetkd: Option<VetKdConfig>, // None = management canister with default key
+    pub email: Option<EmailConfig>, // None = outbound email disabled
+    pub rate_limits: Option<RateLimitConfig>, // None = built-in limits
+}
+
+/// Token bucket: holds up to `capacity` calls and regains one every
+/// `refill_interval_ns`
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub refill_interval_ns: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MethodRateLimit {
+    pub method: String, // Endpoint name without the `_v2` suffix
+    pub limit: RateLimit,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    pub default_limit: RateLimit,             // Any update call without its own limit
+    pub method_limits: Vec<MethodRateLimit>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailConfig {
+    pub provider: EmailProviderKind,
+    pub api_url: String,            // e.g. "https://api.sendgrid.com", "https://api.postmarkapp.com"
+    pub api_key: String,            // Write-only; blanked by get_email_config
+    pub from_address: String,
+    pub from_name: Option<String>,
+    pub outcall_cycles: u64,        // Cycles attached to each outcall; 0 = default
+    pub app_url: Option<String>,    // Base URL for links in templated email; default https://bonded.app
+}
+
+// =======================
+// EMAIL TEMPLATES
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EmailTemplateId {
+    Invite,
+    InviteReminder,
+    InviteCode,
+    RelationshipAccepted,
+    KillSwitchExecuted,
+    EvidenceMilestone,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum EmailLocale {
+    #[default]
+    En,
+    Es,
+    De,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum EmailPlaceholder {
+    InviterName,
+    InviteLink,
+    InviteExpiresOn,
+    VerificationCode,
+    CodeValidMinutes,
+    AppLink,
+    AcceptedOn,
+    DestroyedOn,
+    EvidenceCount,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EmailTemplateInfo {
+    pub id: EmailTemplateId,
+    pub locale: EmailLocale,
+    pub subject: String,
+    pub placeholders: Vec<EmailPlaceholder>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum EmailProviderKind {
+    SendGrid,
+    Postmark,
+    Mock, // Minimal JSON API for local mock servers and PocketIC outcall mocks
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VetKdConfig {
+    pub canister_id: Principal, // Management canister, or a local stand-in exposing the same interface
+    pub key_name: String,       // e.g. "key_1", "test_key_1", "dfx_test_key"
+    pub derive_key_cycles: u64, // Cycles attached to each vetkd_derive_key call
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MigrationState {
+    pub id: String,
+    pub cursor: Option<Vec<u8>>, // Encoded key of the last record rewritten
+    pub records_migrated: u64,
+    pub started_at: Option<u64>,
+    pub completed_at: Option<u64>,
+}
+
+// =======================
+// VETKD KEY DERIVATION
+// =======================
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum VetKdKeyScope {
+    Relationship, // Shared by both partners: evidence encryption
+    Partner,      // Private to one partner within a relationship: signing
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct VetKdEncryptedKey {
+    pub scope: VetKdKeyScope,
+    pub relationship_id: String,
+    pub key_version: Option<u32>, // Relationship key version; None for partner keys
+    pub context: Vec<u8>,       // Derivation context (domain separator for the scope)
+    pub input: Vec<u8>,         // Derivation input the key is bound to
+    pub encrypted_key: Vec<u8>, // Encrypted to the caller's transport public key
 }
 
 // =======================
@@ -174,12 +674,75 @@ pub enum InviteStatus {
 pub struct PartnerInvite {
     pub id: String,
     pub inviter_principal: Principal,
-    pub partner_email: String,
+    pub partner_email_hash: String,    // Hex SHA-256 over the salt and normalized email
+    pub partner_email_salt: Vec<u8>,
+    pub partner_email_hint: String,    // Masked address for display, e.g. "j***@example.com"
     pub inviter_name: String,
     pub status: InviteStatus,
     pub created_at: u64,
     pub expires_at: u64,
     pub metadata: Option<String>,
+    pub closed_at: Option<u64>, // When the invite was accepted, cancelled or expired
+    pub inviter_kill_switch_key: Option<Vec<u8>>, // Registered for the inviter on acceptance
+}
+
+/// One-time code proving control of the invited email. Kept apart from
+/// `PartnerInvite`, which anyone holding the link can read.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct InviteVerification {
+    pub invite_id: String,
+    pub code_hash: Option<String>, // None once verified or locked out
+    pub issued_at: u64,
+    pub expires_at: u64,
+    pub attempts: u32,
+    pub locked_until: Option<u64>,
+    pub verified_by: Option<Principal>, // Principal that entered the correct code
+    pub email: Option<String>, // Address the code was mailed to; becomes the accepter's notification address
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct InviteCodeResponse {
+    pub email_hint: String,
+    pub expires_at: u64,
+}
+
+// =======================
+// STORAGE QUOTAS
+// =======================
+
+/// Running totals kept in step with every insert and remove
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub struct StorageUsage {
+    pub bytes: u64,
+    pub items: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum QuotaTier {
+    Standard,
+    Extended, // Granted by a controller; relationships need it for every partner
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct QuotaLimits {
+    pub max_bytes: u64,
+    pub max_items: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct RelationshipUsage {
+    pub relationship_id: String,
+    pub tier: QuotaTier,
+    pub usage: StorageUsage, // Evidence ciphertext bytes and evidence items
+    pub limits: QuotaLimits,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UsageReport {
+    pub tier: QuotaTier,
+    pub client_storage: StorageUsage, // Stored record bytes and records
+    pub client_storage_limits: QuotaLimits,
+    pub relationships: Vec<RelationshipUsage>,
 }
 
 // =======================
@@ -193,6 +756,7 @@ pub struct CreatePartnerInviteRequest {
     pub expires_at: u64,
     pub metadata: Option<String>,
     pub frontend_url: Option<String>,
+    pub kill_switch_public_key: Vec<u8>, // Inviter's kill switch key, registered on acceptance
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
@@ -202,11 +766,26 @@ pub struct CreatePartnerInviteResponse {
     pub expires_at: u64,
 }
 
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ResendPartnerInviteRequest {
+    pub invite_id: String,          // `PartnerInvite.id`, as returned by list_my_invites
+    pub expires_at: Option<u64>,    // Defaults to the same window as the original invite
+    pub frontend_url: Option<String>,
+}
+
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct SendInviteEmailRequest {
-    pub recipient_email: String,
-    pub email_content: String,
-    pub subject: String,
+    pub invite_id: String,          // Token from CreatePartnerInviteResponse
+    pub recipient_email: String,    // Must be the invited address
+    pub template: EmailTemplateId,  // Invite or InviteReminder
+    pub locale: Option<EmailLocale>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SendNotificationEmailRequest {
+    pub relationship_id: String,
+    pub template: EmailTemplateId,  // RelationshipAccepted, KillSwitchExecuted or EvidenceMilestone
+    pub locale: Option<EmailLocale>,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
@@ -220,7 +799,7 @@ pub struct SendEmailResponse {
 pub struct AcceptInviteResponse {
     pub relationship_id: String,
     pub relationship: Relationship,
-    pub user_key_share: Vec<u8>,
+    pub user_key_share: Vec<u8>, // Empty for vetKD relationships: derive keys with `derive_vetkd_key`
     pub public_key: Vec<u8>,
 }
 
@@ -229,19 +808,20 @@ pub struct UserDashboardData {
     pub profile: UserProfile,
     pub settings: UserSettings,
     pub relationships: Vec<Relationship>,
-    pub recent_evidence: Vec<Evidence>,
+    pub recent_evidence: Vec<EvidenceSummary>,
     pub last_updated: u64,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct CreateRelationshipRequest {
     pub partner_principal: Principal,
+    pub kill_switch_public_key: Vec<u8>, // Requester's kill switch key (Ed25519)
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct CreateRelationshipResponse {
     pub relationship_id: String,
-    pub user_key_share: Vec<u8>,
+    pub user_key_share: Vec<u8>, // Empty for vetKD relationships: derive keys with `derive_vetkd_key`
     pub public_key: Vec<u8>,
 }
 
@@ -250,24 +830,44 @@ pub struct UploadEvidenceRequest {
     pub relationship_id: String,
     pub encrypted_data: Vec<u8>,
     pub metadata: EvidenceMetadata,
-    pub hash: String,
-    pub signature: Option<Vec<u8>>,
+    pub hash: String,                // Hex SHA-256 of encrypted_data followed by the canonical metadata encoding
+    pub signature: Vec<u8>,          // Ed25519 signature over the evidence signature message, by the relationship key
+    pub wrapped_key: Option<Vec<u8>>, // Evidence DEK wrapped under the current relationship key
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct TimelineQuery {
     pub relationship_id: String,
-    pub page: Option<u32>,
+    pub page: Option<u32>, // Deprecated offset paging; ignored when `cursor` is set
     pub category_filter: Option<String>,
     pub start_date: Option<u64>,
     pub end_date: Option<u64>,
+    pub cursor: Option<String>,             // From a previous response's next_cursor/prev_cursor
+    pub direction: Option<PageDirection>,   // Default: Next
+    pub page_size: Option<u32>,             // Default 20, capped server-side
+    pub sort_by: Option<TimelineSortField>, // Default: MetadataTimestamp
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TimelineSortField {
+    MetadataTimestamp, // When the evidence was captured
+    UploadTimestamp,   // When the evidence reached the canister
+}
+
+/// Timelines are ordered newest first; Next walks towards older items
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PageDirection {
+    Next,
+    Previous,
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct TimelineResponse {
-    pub evidence: Vec<Evidence>,
-    pub total_count: u64,
-    pub has_more: bool,
+    pub evidence: Vec<EvidenceSummary>,
+    pub total_count: Option<u64>,      // Omitted on cursor pages of date-bounded queries; keep the first page's
+    pub has_more: bool,                // More items in the requested direction
+    pub next_cursor: Option<String>,   // Older items exist past the last entry
+    pub prev_cursor: Option<String>,   // Newer items exist before the first entry
 }
 
 #[derive(CandidType, Serialize, Deserialize)]
@@ -285,10 +885,79 @@ pub struct UpdateSettingsRequest {
 // RESULT TYPE
 // =======================
 
+/// Machine-readable error returned by every `BondedResult` endpoint. The
+/// `Display` text matches the free-form messages of the legacy interface.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub enum BondedError {
+    NotFound { resource: String },
+    Unauthorized { reason: String },
+    Expired { resource: String },
+    InvalidInput { field: String, reason: String },
+    QuotaExceeded { resource: String, limit: u64 },
+    RateLimited { retry_after_ns: u64 },
+    Conflict { reason: String },
+    AlreadyDeleted { resource: String },
+    Unavailable { reason: String },
+    Internal { reason: String },
+}
+
+impl BondedError {
+    pub fn not_found(resource: impl Into<String>) -> Self {
+        BondedError::NotFound { resource: resource.into() }
+    }
+
+    pub fn unauthorized(reason: impl Into<String>) -> Self {
+        BondedError::Unauthorized { reason: reason.into() }
+    }
+
+    pub fn expired(resource: impl Into<String>) -> Self {
+        BondedError::Expired { resource: resource.into() }
+    }
+
+    pub fn invalid_input(field: impl Into<String>, reason: impl Into<String>) -> Self {
+        BondedError::InvalidInput { field: field.into(), reason: reason.into() }
+    }
+
+    pub fn quota_exceeded(resource: impl Into<String>, limit: u64) -> Self {
+        BondedError::QuotaExceeded { resource: resource.into(), limit }
+    }
+
+    pub fn conflict(reason: impl Into<String>) -> Self {
+        BondedError::Conflict { reason: reason.into() }
+    }
+
+    pub fn unavailable(reason: impl Into<String>) -> Self {
+        BondedError::Unavailable { reason: reason.into() }
+    }
+
+    pub fn internal(reason: impl Into<String>) -> Self {
+        BondedError::Internal { reason: reason.into() }
+    }
+}
+
+impl std::fmt::Display for BondedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BondedError::NotFound { resource } => write!(f, "{} not found", resource),
+            BondedError::Unauthorized { reason } => write!(f, "{}", reason),
+            BondedError::Expired { resource } => write!(f, "{} has expired", resource),
+            BondedError::InvalidInput { reason, .. } => write!(f, "{}", reason),
+            BondedError::QuotaExceeded { resource, limit } => write!(f, "{} quota exceeded (limit {})", resource, limit),
+            BondedError::RateLimited { retry_after_ns } => {
+                write!(f, "Rate limit exceeded, retry in {}s", retry_after_ns.div_ceil(1_000_000_000))
+            }
+            BondedError::Conflict { reason } => write!(f, "{}", reason),
+            BondedError::AlreadyDeleted { resource } => write!(f, "AlreadyDeleted: {} has already been destroyed", resource),
+            BondedError::Unavailable { reason } => write!(f, "{}", reason),
+            BondedError::Internal { reason } => write!(f, "{}", reason),
+        }
+    }
+}
+
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum BondedResult<T> {
     Ok(T),
-    Err(String),
+    Err(BondedError),
 }
 
 impl<T> BondedResult<T> {
@@ -296,8 +965,312 @@ impl<T> BondedResult<T> {
         BondedResult::Ok(value)
     }
     
-    pub fn err(msg: &str) -> Self {
-        BondedResult::Err(msg.to_string())
+    pub fn err(error: BondedError) -> Self {
+        BondedResult::Err(error)
+    }
+}
+
+// Result type of the pre-`BondedError` interface, kept for one release so
+// existing clients can still decode errors (see `compat.rs`). Plain comment:
+// a doc comment would be repeated on every instantiation in the .did
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum LegacyBondedResult<T> {
+    Ok(T),
+    Err(String),
+}
+
+impl<T> From<BondedResult<T>> for LegacyBondedResult<T> {
+    fn from(result: BondedResult<T>) -> Self {
+        match result {
+            BondedResult::Ok(value) => LegacyBondedResult::Ok(value),
+            BondedResult::Err(error) => LegacyBondedResult::Err(error.to_string()),
+        }
+    }
+}
+
+/// Timeline page of the pre-`BondedError` interface: full evidence records
+/// instead of `EvidenceSummary`, and no cursors
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LegacyTimelineResponse {
+    pub evidence: Vec<Evidence>,
+    pub total_count: u64,
+    pub has_more: bool,
+}
+
+/// `Relationship` as the pre-`BondedError` interface returned it. Bonded's
+/// key share is no longer released, so `bonded_key_share` is always empty.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LegacyRelationship {
+    pub id: String,
+    pub partner1: Principal,
+    pub partner2: Option<Principal>,
+    pub status: RelationshipStatus,
+    pub created_at: u64,
+    pub bonded_key_share: Vec<u8>,
+    pub evidence_count: u64,
+    pub last_activity: u64,
+}
+
+impl From<Relationship> for LegacyRelationship {
+    fn from(relationship: Relationship) -> Self {
+        LegacyRelationship {
+            id: relationship.id,
+            partner1: relationship.partner1,
+            partner2: relationship.partner2,
+            status: relationship.status,
+            created_at: relationship.created_at,
+            bonded_key_share: Vec::new(),
+            evidence_count: relationship.evidence_count,
+            last_activity: relationship.last_activity,
+        }
+    }
+}
+
+/// `UserDashboardData` of the pre-`BondedError` interface: full evidence
+/// records instead of `EvidenceSummary`
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct LegacyUserDashboardData {
+    pub profile: UserProfile,
+    pub settings: UserSettings,
+    pub relationships: Vec<LegacyRelationship>,
+    pub recent_evidence: Vec<Evidence>,
+    pub last_updated: u64,
+}
+
+// Requests of the pre-`BondedError` interface. Their replacements require
+// fields old clients cannot supply (kill switch keys, server-side email
+// templates), so the legacy endpoints only decode them and point callers at
+// the `_v2` methods.
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct LegacyCreatePartnerInviteRequest {
+    pub partner_email: String,
+    pub inviter_name: String,
+    pub expires_at: u64,
+    pub metadata: Option<String>,
+    pub frontend_url: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct LegacyCreateRelationshipRequest {
+    pub partner_principal: Principal,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct LegacySendInviteEmailRequest {
+    pub recipient_email: String,
+    pub email_content: String,
+    pub subject: String,
+}
+
+// =======================
+// VERSIONED RECORD ENCODING
+// =======================
+// Stored records are framed as `RECORD_MAGIC | schema version (u16, BE) |
+// candid`. Records written before the envelope existed are bare candid
+// (always starting with `DIDL`) and are read as schema version 1.
+//
+// To change a record's layout incompatibly, bump its `SCHEMA_VERSION`, keep
+// the previous layout as a private struct and convert from it in
+// `upgrade_from`. Old records then decode on read, and a migration in
+// `migrations.rs` can rewrite them in the new layout. Only layouts that have
+// been deployed need keeping: changes made between two releases share one
+// version bump.
+
+const RECORD_MAGIC: &[u8; 4] = b"BNDR";
+const UNVERSIONED_SCHEMA_VERSION: u16 = 1;
+
+pub trait VersionedRecord: CandidType + for<'de> Deserialize<'de> {
+    const SCHEMA_VERSION: u16 = 1;
+
+    /// Decode a record written under another schema version
+    fn upgrade_from(version: u16, _payload: &[u8]) -> Result<Self, String> {
+        Err(format!("no upgrade path from schema version {}", version))
+    }
+}
+
+/// Schema version and candid payload of a stored record
+pub fn split_record(bytes: &[u8]) -> (u16, &[u8]) {
+    match bytes.strip_prefix(RECORD_MAGIC.as_slice()) {
+        Some(rest) if rest.len() >= 2 => (u16::from_be_bytes([rest[0], rest[1]]), &rest[2..]),
+        _ => (UNVERSIONED_SCHEMA_VERSION, bytes),
+    }
+}
+
+pub fn encode_record<T: VersionedRecord>(record: &T) -> Vec<u8> {
+    let payload = Encode!(record).expect("stored records are always encodable");
+    let mut bytes = Vec::with_capacity(RECORD_MAGIC.len() + 2 + payload.len());
+    bytes.extend_from_slice(RECORD_MAGIC);
+    bytes.extend_from_slice(&T::SCHEMA_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&payload);
+    bytes
+}
+
+pub fn decode_record<T: VersionedRecord>(bytes: &[u8]) -> T {
+    let (version, payload) = split_record(bytes);
+    let decoded = if version == T::SCHEMA_VERSION {
+        Decode!(payload, T).map_err(|e| e.to_string())
+    } else {
+        T::upgrade_from(version, payload)
+    };
+
+    decoded.unwrap_or_else(|e| {
+        ic_cdk::trap(&format!(
+            "Failed to decode {} record (schema v{}): {}",
+            std::any::type_name::<T>(),
+            version,
+            e
+        ))
+    })
+}
+
+// Record types still at their first schema version
+impl VersionedRecord for Evidence {}
+impl VersionedRecord for UserProfile {}
+impl VersionedRecord for UserSettings {}
+impl VersionedRecord for InviteVerification {}
+impl VersionedRecord for AuditLogEntry {}
+impl VersionedRecord for TimelineData {}
+impl VersionedRecord for UserFaceEmbedding {}
+impl VersionedRecord for AutoScannerSettings {}
+impl VersionedRecord for CaptureSettings {}
+impl VersionedRecord for EmailLog {}
+impl VersionedRecord for GeolocationCache {}
+impl VersionedRecord for SchedulerSettings {}
+impl VersionedRecord for ProcessedContent {}
+impl VersionedRecord for KillSwitchKey {}
+impl VersionedRecord for UploadSession {}
+impl VersionedRecord for UploadChunkReceipt {}
+impl VersionedRecord for CanisterConfig {}
+impl VersionedRecord for RelationshipCommitment {}
+impl VersionedRecord for MigrationState {}
+impl VersionedRecord for WrappedKey {}
+impl VersionedRecord for StorageUsage {}
+impl VersionedRecord for EvidenceSummary {}
+
+// Schema history
+
+/// `PartnerInvite` before the partner email was hashed
+#[derive(CandidType, Deserialize)]
+struct PartnerInviteV1 {
+    id: String,
+    inviter_principal: Principal,
+    partner_email: String,
+    inviter_name: String,
+    status: InviteStatus,
+    created_at: u64,
+    expires_at: u64,
+    metadata: Option<String>,
+    closed_at: Option<u64>,
+}
+
+impl VersionedRecord for PartnerInvite {
+    const SCHEMA_VERSION: u16 = 2;
+
+    fn upgrade_from(version: u16, payload: &[u8]) -> Result<Self, String> {
+        match version {
+            1 => {
+                let v1 = Decode!(payload, PartnerInviteV1).map_err(|e| e.to_string())?;
+                // No randomness during upgrades; the ID is unique per invite
+                let salt = crate::utils::legacy_invite_email_salt(&v1.id);
+                Ok(PartnerInvite {
+                    partner_email_hash: crate::utils::hash_invite_email(&salt, &v1.partner_email),
+                    partner_email_hint: crate::utils::mask_email(&v1.partner_email),
+                    partner_email_salt: salt,
+                    id: v1.id,
+                    inviter_principal: v1.inviter_principal,
+                    inviter_name: v1.inviter_name,
+                    status: v1.status,
+                    created_at: v1.created_at,
+                    expires_at: v1.expires_at,
+                    metadata: v1.metadata,
+                    closed_at: v1.closed_at,
+                    inviter_kill_switch_key: None,
+                })
+            }
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
+    }
+}
+
+/// `Relationship` as first deployed: no signing keys, key registry or
+/// request deadlines
+#[derive(CandidType, Deserialize)]
+struct RelationshipV1 {
+    id: String,
+    partner1: Principal,
+    partner2: Option<Principal>,
+    status: RelationshipStatus,
+    created_at: u64,
+    bonded_key_share: Vec<u8>,
+    evidence_count: u64,
+    last_activity: u64,
+}
+
+impl VersionedRecord for Relationship {
+    const SCHEMA_VERSION: u16 = 2;
+
+    fn upgrade_from(version: u16, payload: &[u8]) -> Result<Self, String> {
+        match version {
+            1 => {
+                let v1 = Decode!(payload, RelationshipV1).map_err(|e| e.to_string())?;
+                // Relationships were created already active back then, so
+                // creation is acceptance. Their public keys were never stored.
+                // Bonded's key share moves to its own store on first read, so
+                // the rewritten record no longer carries it.
+                crate::utils::store_bonded_key_share(&v1.id, 1, v1.bonded_key_share);
+                let accepted_at = matches!(v1.status, RelationshipStatus::Active).then_some(v1.created_at);
+                Ok(Relationship {
+                    id: v1.id,
+                    partner1: v1.partner1,
+                    partner2: v1.partner2,
+                    status: v1.status,
+                    created_at: v1.created_at,
+                    evidence_count: v1.evidence_count,
+                    last_activity: v1.last_activity,
+                    destroyed_at: None,
+                    destroyed_by: None,
+                    signing_keys: Vec::new(),
+                    relationship_keys: Vec::new(),
+                    key_rotation: None,
+                    expires_at: None,
+                    accepted_at,
+                    key_scheme: RelationshipKeyScheme::ThresholdShares,
+                })
+            }
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
+    }
+}
+
+/// `UserKeyShare` before key versions
+#[derive(CandidType, Deserialize)]
+struct UserKeyShareV1 {
+    key_id: String,
+    user: Principal,
+    relationship_id: String,
+    key_share: Vec<u8>,
+    created_at: u64,
+}
+
+impl VersionedRecord for UserKeyShare {
+    const SCHEMA_VERSION: u16 = 2;
+
+    fn upgrade_from(version: u16, payload: &[u8]) -> Result<Self, String> {
+        match version {
+            1 => {
+                let v1 = Decode!(payload, UserKeyShareV1).map_err(|e| e.to_string())?;
+                Ok(UserKeyShare {
+                    key_id: v1.key_id,
+                    user: v1.user,
+                    relationship_id: v1.relationship_id,
+                    key_share: v1.key_share,
+                    created_at: v1.created_at,
+                    key_version: 1,
+                })
+            }
+            _ => Err(format!("no upgrade path from schema version {}", version)),
+        }
     }
 }
 
@@ -308,180 +1281,300 @@ impl<T> BondedResult<T> {
 impl Storable for Evidence {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for Relationship {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for UserProfile {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for UserSettings {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for PartnerInvite {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for StorageUsage {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for InviteVerification {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for AuditLogEntry {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for TimelineData {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for UserFaceEmbedding {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for AutoScannerSettings {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for CaptureSettings {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for EmailLog {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for GeolocationCache {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for SchedulerSettings {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for ProcessedContent {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
 impl Storable for UserKeyShare {
     const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
 
-    fn to_bytes(&self) -> Cow<[u8]> {
-        Cow::Owned(Encode!(self).unwrap())
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for KillSwitchKey {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for EvidenceSummary {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for UploadChunkReceipt {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for UploadSession {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for CanisterConfig {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for MigrationState {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for WrappedKey {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        decode_record(bytes.as_ref())
+    }
+}
+
+impl Storable for RelationshipCommitment {
+    const BOUND: ic_stable_structures::storable::Bound = ic_stable_structures::storable::Bound::Unbounded;
+
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
     }
 
     fn from_bytes(bytes: Cow<[u8]>) -> Self {
-        Decode!(bytes.as_ref(), Self).unwrap()
+        decode_record(bytes.as_ref())
     }
 }
 
@@ -0,0 +1,580 @@
+use crate::evidence::record_evidence;
+use crate::quotas::check_relationship_quota;
+use crate::key_rotation::{key_version_at, store_initial_wrapped_key, validate_wrapped_key};
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use ic_cdk_macros::{query, update};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+// ==================
+// CHUNKED EVIDENCE UPLOAD
+// ==================
+// Large media is uploaded as a session: `begin_evidence_upload` declares the
+// file's size, full-file hash and per-chunk hashes, `put_evidence_chunk` stores
+// chunks in any order (re-sending a chunk is harmless, so interrupted uploads
+// resume from `missing_chunks`), and `commit_evidence_upload` verifies the
+// whole file before it becomes evidence. Chunks stay in EVIDENCE_CHUNK_STORE
+// under the upload ID, which becomes the evidence's `content_id`.
+//
+// The session itself is written once. Each chunk gets a receipt under its
+// own key, and the file hash is carried forward chunk by chunk as a stored
+// SHA-256 midstate on those receipts, so no single message hashes more than
+// MAX_HASHED_BYTES_PER_MESSAGE. Chunks that arrive ahead of a gap are hashed
+// once the gap is filled; whatever is left is finished by commit, over as
+// many calls as it takes.
+
+/// Keeps each `put_evidence_chunk` message under the 2 MiB ingress limit
+pub(crate) const MAX_CHUNK_SIZE: u32 = 1_900_000;
+const MAX_UPLOAD_SIZE: u64 = 256 * 1024 * 1024;
+/// Hashing budget of one `put_evidence_chunk` or `commit_evidence_upload` call
+const MAX_HASHED_BYTES_PER_MESSAGE: u64 = 32 * 1024 * 1024;
+const MAX_OPEN_UPLOADS_PER_USER: usize = 5;
+/// Sessions that receive no chunk for this long are discarded
+const UPLOAD_SESSION_TTL_NS: u64 = 24 * 60 * 60 * 1_000_000_000;
+const UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub(crate) fn chunk_key(content_id: &str, index: u32) -> String {
+    // Zero-padded so lexicographic order matches chunk order
+    format!("{}:{:010}", content_id, index)
+}
+
+fn chunk_range(content_id: &str) -> std::ops::Range<String> {
+    format!("{}:", content_id)..format!("{};", content_id) // ';' sorts directly after ':'
+}
+
+const SHA256_INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+impl Sha256Progress {
+    pub(crate) fn new() -> Self {
+        Sha256Progress {
+            state: SHA256_INITIAL_STATE.to_vec(),
+            buffered: Vec::new(),
+            length: 0,
+        }
+    }
+
+    fn compress(state: &mut [u32; 8], block: &[u8]) {
+        sha2::compress256(state, &[sha2::digest::generic_array::GenericArray::clone_from_slice(block)]);
+    }
+
+    fn working_state(&self) -> [u32; 8] {
+        self.state.as_slice().try_into().expect("SHA-256 state has eight words")
+    }
+
+    pub(crate) fn update(&mut self, mut data: &[u8]) {
+        let mut state = self.working_state();
+        self.length += data.len() as u64;
+
+        if !self.buffered.is_empty() {
+            let take = (64 - self.buffered.len()).min(data.len());
+            self.buffered.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffered.len() == 64 {
+                Self::compress(&mut state, &self.buffered);
+                self.buffered.clear();
+            }
+        }
+
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            Self::compress(&mut state, block);
+        }
+        self.buffered.extend_from_slice(blocks.remainder());
+        self.state = state.to_vec();
+    }
+
+    pub(crate) fn finalize(&self) -> [u8; 32] {
+        let mut state = self.working_state();
+        let mut tail = self.buffered.clone();
+        tail.push(0x80);
+        while tail.len() % 64 != 56 {
+            tail.push(0);
+        }
+        tail.extend_from_slice(&(self.length * 8).to_be_bytes());
+        for block in tail.chunks_exact(64) {
+            Self::compress(&mut state, block);
+        }
+
+        let mut digest = [0u8; 32];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn chunk_count_for(total_size: u64, chunk_size: u32) -> u64 {
+    total_size.div_ceil(chunk_size as u64)
+}
+
+fn expected_chunk_len(session: &UploadSession, index: u32) -> u64 {
+    let chunk_count = session.chunk_hashes.len() as u32;
+    if index + 1 < chunk_count {
+        session.chunk_size as u64
+    } else {
+        session.total_size - session.chunk_size as u64 * (chunk_count as u64 - 1)
+    }
+}
+
+fn chunk_receipts(upload_id: &str) -> Vec<(String, UploadChunkReceipt)> {
+    with_upload_chunk_receipt_store_read(|store| store.range(chunk_range(upload_id)).collect())
+}
+
+/// Sessions expire a fixed time after their latest accepted chunk
+fn session_expires_at(session: &UploadSession, receipts: &[(String, UploadChunkReceipt)]) -> u64 {
+    let last_activity = receipts
+        .iter()
+        .map(|(_, receipt)| receipt.received_at)
+        .fold(session.created_at, u64::max);
+    last_activity + UPLOAD_SESSION_TTL_NS
+}
+
+fn session_status(session: &UploadSession) -> UploadSessionStatus {
+    let receipts = chunk_receipts(&session.upload_id);
+    let chunk_count = session.chunk_hashes.len() as u32;
+    let received: std::collections::BTreeSet<&str> = receipts.iter().map(|(key, _)| key.as_str()).collect();
+
+    UploadSessionStatus {
+        upload_id: session.upload_id.clone(),
+        chunk_count,
+        missing_chunks: (0..chunk_count)
+            .filter(|index| !received.contains(chunk_key(&session.upload_id, *index).as_str()))
+            .collect(),
+        verified_chunks: receipts.iter().take_while(|(_, receipt)| receipt.prefix_hash.is_some()).count() as u32,
+        expires_at: session_expires_at(session, &receipts),
+    }
+}
+
+/// Carry the file hash forward over received chunks, starting after the
+/// last hashed one and stopping at the first gap or the message budget.
+/// Returns how many leading chunks are hashed.
+fn advance_upload_hash(session: &UploadSession) -> u32 {
+    let upload_id = &session.upload_id;
+    let chunk_count = session.chunk_hashes.len() as u32;
+
+    let mut index = 0;
+    let mut progress = Sha256Progress::new();
+    with_upload_chunk_receipt_store_read(|store| {
+        for (_, receipt) in store.range(chunk_range(upload_id)) {
+            match receipt.prefix_hash {
+                Some(prefix_hash) => {
+                    progress = prefix_hash;
+                    index += 1;
+                }
+                None => break,
+            }
+        }
+    });
+
+    let mut hashed_bytes = 0u64;
+    while index < chunk_count && hashed_bytes < MAX_HASHED_BYTES_PER_MESSAGE {
+        let key = chunk_key(upload_id, index);
+        let Some(mut receipt) = with_upload_chunk_receipt_store_read(|store| store.get(&key)) else {
+            break;
+        };
+        let Some(bytes) = with_evidence_chunk_store_read(|store| store.get(&key)) else {
+            break;
+        };
+
+        progress.update(&bytes);
+        hashed_bytes += bytes.len() as u64;
+        receipt.prefix_hash = Some(progress.clone());
+        with_upload_chunk_receipt_store(|store| {
+            store.insert(key, receipt);
+        });
+        index += 1;
+    }
+
+    index
+}
+
+/// Look up a live session owned by `caller`
+fn owned_session(upload_id: &str, caller: candid::Principal) -> Result<UploadSession, BondedError> {
+    let session = match with_upload_session_store_read(|store| store.get(&upload_id.to_string())) {
+        Some(session) => session,
+        None => return Err(BondedError::not_found("Upload session")),
+    };
+
+    if session.uploader != caller {
+        return Err(BondedError::unauthorized("Not authorized to access this upload session"));
+    }
+
+    if session_expires_at(&session, &chunk_receipts(upload_id)) <= current_time() {
+        discard_upload_session(&session.upload_id);
+        return Err(BondedError::expired("Upload session"));
+    }
+
+    Ok(session)
+}
+
+fn discard_upload_session(upload_id: &str) {
+    with_upload_session_store(|store| {
+        store.remove(&upload_id.to_string());
+    });
+    remove_chunk_receipts(upload_id);
+    remove_content_chunks(upload_id);
+}
+
+fn remove_chunk_receipts(upload_id: &str) {
+    with_upload_chunk_receipt_store(|store| {
+        let keys: Vec<String> = store.range(chunk_range(upload_id)).map(|(key, _)| key).collect();
+        for key in keys {
+            store.remove(&key);
+        }
+    });
+}
+
+fn remove_content_chunks(content_id: &str) {
+    with_evidence_chunk_store(|store| {
+        let keys: Vec<String> = store.range(chunk_range(content_id)).map(|(key, _)| key).collect();
+        for key in keys {
+            store.remove(&key);
+        }
+    });
+}
+
+/// Remove the chunked content owned by an evidence record, if any
+pub fn remove_evidence_content(evidence: &Evidence) {
+    if let Some(content) = &evidence.content {
+        remove_content_chunks(&content.content_id);
+    }
+}
+
+/// Zero-overwrite every chunk of an evidence record's content, then remove it
+pub fn destroy_evidence_content(evidence: &Evidence) {
+    let Some(content) = &evidence.content else {
+        return;
+    };
+
+    with_evidence_chunk_store(|store| {
+        let chunks: Vec<(String, usize)> = store
+            .range(chunk_range(&content.content_id))
+            .map(|(key, bytes)| (key, bytes.len()))
+            .collect();
+        for (key, len) in chunks {
+            store.insert(key.clone(), vec![0u8; len]);
+            store.remove(&key);
+        }
+    });
+}
+
+/// Drop every in-flight upload for a relationship (used by the kill switch)
+pub fn discard_relationship_uploads(relationship_id: &str) {
+    let upload_ids: Vec<String> = with_upload_session_store_read(|store| {
+        store
+            .iter()
+            .filter(|(_, session)| session.relationship_id == relationship_id)
+            .map(|(upload_id, _)| upload_id)
+            .collect()
+    });
+
+    for upload_id in upload_ids {
+        discard_upload_session(&upload_id);
+    }
+}
+
+fn sweep_expired_uploads() {
+    let now = current_time();
+    let expired: Vec<String> = with_upload_session_store_read(|store| {
+        store
+            .iter()
+            .filter(|(upload_id, session)| session_expires_at(session, &chunk_receipts(upload_id)) <= now)
+            .map(|(upload_id, _)| upload_id)
+            .collect()
+    });
+
+    if expired.is_empty() {
+        return;
+    }
+
+    for upload_id in &expired {
+        discard_upload_session(upload_id);
+    }
+    ic_cdk::println!("🧹 Discarded {} expired upload sessions", expired.len());
+}
+
+/// Periodically discard abandoned sessions and their chunks.
+/// Timers do not survive upgrades, so call from `init` and `post_upgrade`.
+pub fn schedule_upload_expiry() {
+    ic_cdk_timers::set_timer_interval(UPLOAD_SWEEP_INTERVAL, sweep_expired_uploads);
+}
+
+// ==================
+// UPLOAD ENDPOINTS
+// ==================
+
+#[update]
+pub fn begin_evidence_upload(request: BeginEvidenceUploadRequest) -> BondedResult<UploadSessionStatus> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "begin_evidence_upload") {
+        return BondedResult::err(msg);
+    }
+
+    if let Err(msg) = validate_evidence_metadata(&request.metadata) {
+        return BondedResult::err(msg);
+    }
+
+    let relationship = match with_relationship_store_read(|store| store.get(&request.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    // Same lower bound as single-message uploads (IV + some data + tag)
+    if request.total_size < 32 {
+        return BondedResult::err(BondedError::invalid_input("total_size", "Encrypted data appears to be too small"));
+    }
+    if request.total_size > MAX_UPLOAD_SIZE {
+        return BondedResult::err(BondedError::invalid_input("total_size", format!("Uploads are limited to {} bytes", MAX_UPLOAD_SIZE)));
+    }
+    if request.chunk_size == 0 || request.chunk_size > MAX_CHUNK_SIZE {
+        return BondedResult::err(BondedError::invalid_input("chunk_size", format!("Chunk size must be between 1 and {} bytes", MAX_CHUNK_SIZE)));
+    }
+    if request.chunk_hashes.len() as u64 != chunk_count_for(request.total_size, request.chunk_size) {
+        return BondedResult::err(BondedError::invalid_input("chunk_hashes", "Number of chunk hashes does not match total size and chunk size"));
+    }
+    if !is_sha256_hex(&request.hash) || !request.chunk_hashes.iter().all(|hash| is_sha256_hex(hash)) {
+        return BondedResult::err(BondedError::invalid_input("chunk_hashes", "Hashes must be hex-encoded SHA-256 digests"));
+    }
+    if let Some(wrapped_key) = &request.wrapped_key {
+        if let Err(msg) = validate_wrapped_key(wrapped_key) {
+            return BondedResult::err(msg);
+        }
+    }
+
+    // Checked again on commit, when the evidence is actually charged
+    if let Err(msg) = check_relationship_quota(&relationship, request.total_size) {
+        return BondedResult::err(msg);
+    }
+
+    let open_uploads = with_upload_session_store_read(|store| {
+        store.iter().filter(|(_, session)| session.uploader == caller).count()
+    });
+    if open_uploads >= MAX_OPEN_UPLOADS_PER_USER {
+        return BondedResult::err(BondedError::quota_exceeded("Open uploads", MAX_OPEN_UPLOADS_PER_USER as u64));
+    }
+
+    let upload_id = with_canister_state(|state| {
+        state.next_upload_id += 1;
+        generate_id("upload", state.next_upload_id)
+    });
+
+    let session = UploadSession {
+        upload_id: upload_id.clone(),
+        relationship_id: request.relationship_id,
+        uploader: caller,
+        metadata: request.metadata,
+        hash: request.hash.to_ascii_lowercase(),
+        total_size: request.total_size,
+        chunk_size: request.chunk_size,
+        chunk_hashes: request.chunk_hashes.iter().map(|hash| hash.to_ascii_lowercase()).collect(),
+        created_at: current_time(),
+        wrapped_key: request.wrapped_key,
+    };
+
+    let status = session_status(&session);
+    with_upload_session_store(|store| {
+        store.insert(upload_id, session);
+    });
+
+    BondedResult::ok(status)
+}
+
+#[update]
+pub fn put_evidence_chunk(upload_id: String, index: u32, bytes: Vec<u8>) -> BondedResult<UploadSessionStatus> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "put_evidence_chunk") {
+        return BondedResult::err(msg);
+    }
+
+    let session = match owned_session(&upload_id, caller) {
+        Ok(session) => session,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    if index as usize >= session.chunk_hashes.len() {
+        return BondedResult::err(BondedError::invalid_input("index", "Chunk index out of range"));
+    }
+
+    if bytes.len() as u64 != expected_chunk_len(&session, index) {
+        return BondedResult::err(BondedError::invalid_input("bytes", "Chunk has the wrong size"));
+    }
+
+    if hex::encode(Sha256::digest(&bytes)) != session.chunk_hashes[index as usize] {
+        return BondedResult::err(BondedError::invalid_input("bytes", "Chunk hash mismatch"));
+    }
+
+    // A re-sent chunk has identical bytes, so any hash progress over it stands
+    let key = chunk_key(&upload_id, index);
+    let receipt = UploadChunkReceipt {
+        received_at: current_time(),
+        prefix_hash: with_upload_chunk_receipt_store_read(|store| store.get(&key)).and_then(|receipt| receipt.prefix_hash),
+    };
+    with_evidence_chunk_store(|store| {
+        store.insert(key.clone(), bytes);
+    });
+    with_upload_chunk_receipt_store(|store| {
+        store.insert(key, receipt);
+    });
+
+    advance_upload_hash(&session);
+
+    BondedResult::ok(session_status(&session))
+}
+
+#[update]
+pub fn commit_evidence_upload(upload_id: String) -> BondedResult<String> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "commit_evidence_upload") {
+        return BondedResult::err(msg);
+    }
+
+    let session = match owned_session(&upload_id, caller) {
+        Ok(session) => session,
+        Err(msg) => return BondedResult::err(msg),
+    };
+
+    let status = session_status(&session);
+    if !status.missing_chunks.is_empty() {
+        return BondedResult::err(BondedError::conflict(format!("{} chunks are still missing", status.missing_chunks.len())));
+    }
+
+    // Access may have been lost while the upload was in flight
+    let relationship = match with_relationship_store_read(|store| store.get(&session.relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    if let Err(msg) = check_relationship_quota(&relationship, session.total_size) {
+        return BondedResult::err(msg);
+    }
+
+    // Large backlogs of out-of-order chunks take several calls to hash
+    if advance_upload_hash(&session) < status.chunk_count {
+        return BondedResult::err(BondedError::unavailable("Upload is still being verified; call commit_evidence_upload again"));
+    }
+
+    // Every chunk's size was checked on arrival; the midstate over all of
+    // them yields both the client-facing file hash and the evidence hash
+    // used for the relationship commitment
+    let last_chunk = chunk_key(&upload_id, status.chunk_count - 1);
+    let Some(progress) = with_upload_chunk_receipt_store_read(|store| store.get(&last_chunk)).and_then(|receipt| receipt.prefix_hash) else {
+        return BondedResult::err(BondedError::internal("Upload hash progress is missing"));
+    };
+    let file_hash = hex::encode(progress.finalize());
+    let mut evidence_progress = progress;
+    evidence_progress.update(&encode_evidence_metadata(&session.metadata));
+    let evidence_hash = hex::encode(evidence_progress.finalize());
+
+    if file_hash != session.hash {
+        discard_upload_session(&upload_id);
+        return BondedResult::err(BondedError::invalid_input("hash", "Uploaded data does not match the declared hash; upload discarded"));
+    }
+
+    with_upload_session_store(|store| {
+        store.remove(&upload_id);
+    });
+    remove_chunk_receipts(&upload_id);
+
+    let content = EvidenceContent {
+        content_id: upload_id,
+        total_size: session.total_size,
+        chunk_size: session.chunk_size,
+        chunk_count: status.chunk_count,
+    };
+
+    // The DEK was wrapped under the key version current when the upload began
+    let key_version = key_version_at(&relationship, session.created_at);
+    let evidence_id = record_evidence(relationship, caller, Vec::new(), session.metadata, evidence_hash, None, Some(content));
+    if let Some(wrapped_key) = session.wrapped_key {
+        store_initial_wrapped_key(&session.relationship_id, &evidence_id, key_version, wrapped_key);
+    }
+
+    BondedResult::ok(evidence_id)
+}
+
+#[update]
+pub fn abort_evidence_upload(upload_id: String) -> BondedResult<String> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "abort_evidence_upload") {
+        return BondedResult::err(msg);
+    }
+
+    if let Err(msg) = owned_session(&upload_id, caller) {
+        return BondedResult::err(msg);
+    }
+
+    discard_upload_session(&upload_id);
+
+    BondedResult::ok(format!("Upload {} aborted", upload_id))
+}
+
+#[query]
+pub fn get_evidence_upload_status(upload_id: String) -> BondedResult<UploadSessionStatus> {
+    let caller = caller_principal();
+
+    let session = match with_upload_session_store_read(|store| store.get(&upload_id)) {
+        Some(session) => session,
+        None => return BondedResult::err(BondedError::not_found("Upload session")),
+    };
+
+    if session.uploader != caller {
+        return BondedResult::err(BondedError::unauthorized("Not authorized to access this upload session"));
+    }
+
+    let status = session_status(&session);
+    if status.expires_at <= current_time() {
+        return BondedResult::err(BondedError::expired("Upload session"));
+    }
+
+    BondedResult::ok(status)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::{Decode, Encode};
+    use rand_chacha::ChaCha20Rng;
+    use rand_core::{RngCore, SeedableRng};
+
+    #[test]
+    fn resumed_hash_matches_one_pass_hash() {
+        let mut rng = ChaCha20Rng::seed_from_u64(8);
+        for len in [0usize, 1, 55, 56, 63, 64, 65, 127, 128, 1000, 4099] {
+            let mut data = vec![0u8; len];
+            rng.fill_bytes(&mut data);
+            let expected: [u8; 32] = Sha256::digest(&data).into();
+
+            // Split at uneven points, storing the midstate between pieces
+            for piece in [1usize, 7, 64, 100, 333] {
+                let mut progress = Sha256Progress::new();
+                for part in data.chunks(piece) {
+                    let stored = Encode!(&progress).unwrap();
+                    progress = Decode!(&stored, Sha256Progress).unwrap();
+                    progress.update(part);
+                }
+                assert_eq!(progress.finalize(), expected, "len {} piece {}", len, piece);
+            }
+        }
+    }
+}
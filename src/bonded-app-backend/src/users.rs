@@ -1,6 +1,10 @@
 use crate::storage::*;
 use crate::types::*;
 use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use crate::commitment::update_relationship_commitment;
+use crate::evidence::remove_evidence;
+use crate::evidence_index::relationship_evidence_ids;
 use candid::Principal;
 use ic_cdk_macros::{query, update};
 
@@ -13,13 +17,21 @@ pub fn whoami() -> Principal {
     caller_principal()
 }
 
-#[update]
+#[update(name = "register_user_v2")]
 pub fn register_user(email: Option<String>) -> BondedResult<String> {
     let user = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(user, "register_user") {
+        return BondedResult::err(msg);
+    }
+    
+    if email.as_deref().is_some_and(|email| !is_valid_email(email)) {
+        return BondedResult::err(BondedError::invalid_input("email", "Invalid email address format"));
+    }
+    
     // Check if user already exists
     if with_user_store_read(|store| store.get(&user)).is_some() {
-        return BondedResult::err("User already registered");
+        return BondedResult::err(BondedError::conflict("User already registered"));
     }
     
     let profile = UserProfile {
@@ -28,6 +40,8 @@ pub fn register_user(email: Option<String>) -> BondedResult<String> {
         relationships: vec![],
         total_evidence_uploaded: 0,
         kyc_verified: false,
+        quota_tier: None,
+        email: email.clone(),
         last_seen: current_time(),
     };
     
@@ -61,10 +75,14 @@ pub fn register_user(email: Option<String>) -> BondedResult<String> {
 // SETTINGS MANAGEMENT
 // ====================
 
-#[update]
+#[update(name = "update_user_settings_v2")]
 pub fn update_user_settings(request: UpdateSettingsRequest) -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "update_user_settings") {
+        return BondedResult::err(msg);
+    }
+    
     let mut current_settings = with_settings_store_read(|store| store.get(&caller))
         .unwrap_or_else(|| UserSettings {
             ai_filters_enabled: true,
@@ -112,13 +130,13 @@ pub fn update_user_settings(request: UpdateSettingsRequest) -> BondedResult<Stri
     BondedResult::ok("Settings updated successfully".to_string())
 }
 
-#[query]
+#[query(name = "get_user_settings_v2")]
 pub fn get_user_settings() -> BondedResult<UserSettings> {
     let caller = caller_principal();
     
     match with_settings_store_read(|store| store.get(&caller)) {
         Some(settings) => BondedResult::ok(settings),
-        None => BondedResult::err("Settings not found"),
+        None => BondedResult::err(BondedError::not_found("Settings")),
     }
 }
 
@@ -126,10 +144,14 @@ pub fn get_user_settings() -> BondedResult<UserSettings> {
 // PROFILE MANAGEMENT
 // =================
 
-#[update]
+#[update(name = "update_face_embedding_v2")]
 pub fn update_face_embedding(embedding: Vec<f32>) -> BondedResult<String> {
     let user = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(user, "update_face_embedding") {
+        return BondedResult::err(msg);
+    }
+    
     // For now, we'll just log this for MVP
     // In production, this would store the face embedding securely
     log_audit_event(user, "update_face_embedding", Some(format!("embedding_size:{}", embedding.len())));
@@ -145,36 +167,49 @@ pub fn update_face_embedding(embedding: Vec<f32>) -> BondedResult<String> {
     BondedResult::ok("Face embedding updated successfully".to_string())
 }
 
-#[update]
-pub fn verify_kyc() -> BondedResult<String> {
-    let user = caller_principal();
+/// Record a completed KYC check; only the verifier (a controller) can
+#[update(name = "verify_kyc_v2")]
+pub fn verify_kyc(user: Principal) -> BondedResult<String> {
+    let caller = caller_principal();
     
-    with_user_store(|store| {
+    if let Err(msg) = verify_controller(caller) {
+        return BondedResult::err(msg);
+    }
+    
+    let result = with_user_store(|store| {
         if let Some(mut profile) = store.get(&user) {
             profile.kyc_verified = true;
             profile.last_seen = current_time();
             store.insert(user, profile);
         } else {
-            return BondedResult::err("User profile not found");
+            return BondedResult::err(BondedError::not_found("User profile"));
         }
         BondedResult::ok("KYC verification completed".to_string())
-    })
+    });
+    
+    log_audit_event(caller, "verify_kyc", Some(format!("user:{}", user)));
+    
+    result
 }
 
-#[query]
+#[query(name = "get_user_profile_v2")]
 pub fn get_user_profile() -> BondedResult<UserProfile> {
     let caller = caller_principal();
     
     match with_user_store_read(|store| store.get(&caller)) {
         Some(profile) => BondedResult::ok(profile),
-        None => BondedResult::err("Profile not found"),
+        None => BondedResult::err(BondedError::not_found("Profile")),
     }
 }
 
-#[update]
+#[update(name = "delete_user_account_v2")]
 pub fn delete_user_account() -> BondedResult<String> {
     let caller = caller_principal();
     
+    if let Err(msg) = verify_update_allowed(caller, "delete_user_account") {
+        return BondedResult::err(msg);
+    }
+    
     // Get user's relationships first
     let user_relationships: Vec<String> = with_user_store_read(|store| {
         store.get(&caller)
@@ -185,6 +220,11 @@ pub fn delete_user_account() -> BondedResult<String> {
     // Terminate all relationships
     for relationship_id in user_relationships {
         if let Some(relationship) = with_relationship_store_read(|store| store.get(&relationship_id)) {
+            // Already wiped by the kill switch; keep the Destroyed tombstone
+            if matches!(relationship.status, RelationshipStatus::Destroyed) {
+                continue;
+            }
+            
             // Mark relationship as terminated
             let mut updated_relationship = relationship;
             updated_relationship.status = RelationshipStatus::Terminated;
@@ -195,24 +235,10 @@ pub fn delete_user_account() -> BondedResult<String> {
             });
             
             // Delete all evidence for this relationship
-            let evidence_to_delete: Vec<String> = with_evidence_store_read(|store| {
-                store
-                    .iter()
-                    .filter_map(|(id, evidence)| {
-                        if evidence.relationship_id == relationship_id {
-                            Some(id.clone())
-                        } else {
-                            None
-                        }
-                    })
-                    .collect()
-            });
-            
-            with_evidence_store(|store| {
-                for evidence_id in evidence_to_delete {
-                    store.remove(&evidence_id);
-                }
-            });
+            for evidence_id in relationship_evidence_ids(&relationship_id) {
+                remove_evidence(&evidence_id);
+            }
+            update_relationship_commitment(&relationship_id);
         }
     }
     
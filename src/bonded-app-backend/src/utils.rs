@@ -1,24 +1,26 @@
 use crate::types::*;
 use crate::storage::*;
+use crate::shamir;
+use crate::entropy;
+use crate::audit;
 use candid::Principal;
 use ic_cdk::api::time;
 use sha2::{Digest, Sha256};
 
 // Custom getrandom implementation for IC environment
 use getrandom::register_custom_getrandom;
+use std::num::NonZeroU32;
+
+/// Returned to `getrandom` callers while the entropy pool is still unseeded
+const ENTROPY_UNAVAILABLE: u32 = getrandom::Error::CUSTOM_START + 1;
 
 fn custom_getrandom(buf: &mut [u8]) -> Result<(), getrandom::Error> {
-    // Simple deterministic randomness for MVP
-    // In production, you'd use proper IC randomness APIs
-    let timestamp = time();
-    let mut seed = timestamp as u64;
-    
-    for byte in buf.iter_mut() {
-        seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
-        *byte = (seed >> 16) as u8;
-    }
-    
-    Ok(())
+    // Backed by the raw_rand-seeded ChaCha20 pool; fails closed until seeded
+    entropy::fill_random(buf).map_err(|_| {
+        NonZeroU32::new(ENTROPY_UNAVAILABLE)
+            .expect("custom error code is non-zero")
+            .into()
+    })
 }
 
 register_custom_getrandom!(custom_getrandom);
@@ -27,6 +29,67 @@ register_custom_getrandom!(custom_getrandom);
 // UTILITY FUNCTIONS
 // ==================
 
+/// 256-bit random invite token, hex-encoded. It is the only secret in an
+/// invite link and is never stored; see `hash_invite_token`.
+pub fn generate_invite_token() -> Result<String, String> {
+    let mut token = [0u8; 32];
+    entropy::fill_random(&mut token)?;
+    Ok(hex::encode(token))
+}
+
+/// INVITE_STORE key and `PartnerInvite.id` for an invite token
+pub fn hash_invite_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+fn normalize_email(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+/// Salted hash stored in place of an invited partner's email
+pub fn hash_invite_email(salt: &[u8], email: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"bonded:invite-email:v1\n");
+    hasher.update(salt);
+    hasher.update(normalize_email(email).as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+pub fn generate_invite_email_salt() -> Result<Vec<u8>, String> {
+    let mut salt = [0u8; 16];
+    entropy::fill_random(&mut salt)?;
+    Ok(salt.to_vec())
+}
+
+/// Salt for invites hashed during a schema upgrade, where no randomness is
+/// available; invite IDs are unique, so salts still differ per invite
+pub fn legacy_invite_email_salt(invite_id: &str) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(b"bonded:invite-email-salt:v1\n");
+    hasher.update(invite_id.as_bytes());
+    hasher.finalize()[..16].to_vec()
+}
+
+/// "jane@example.com" -> "j***@example.com"
+pub fn mask_email(email: &str) -> String {
+    let email = normalize_email(email);
+    match email.split_once('@') {
+        Some((local, domain)) => {
+            let first: String = local.chars().take(1).collect();
+            format!("{}***@{}", first, domain)
+        }
+        None => "***".to_string(),
+    }
+}
+
+/// Compare secrets without an early exit on the first differing byte
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 pub fn generate_id(prefix: &str, counter: u64) -> String {
     let timestamp = time();
     format!("{}_{:016x}_{:016x}", prefix, timestamp, counter)
@@ -35,17 +98,44 @@ pub fn generate_id(prefix: &str, counter: u64) -> String {
 pub fn generate_evidence_hash(data: &[u8], metadata: &EvidenceMetadata) -> String {
     let mut hasher = Sha256::new();
     hasher.update(data);
-    hasher.update(metadata.timestamp.to_be_bytes());
-    hasher.update(&metadata.content_type);
+    finalize_evidence_hash(hasher, metadata)
+}
+
+/// Finish an evidence hash whose data has already been fed to `hasher`
+/// (used when the data arrives in chunks). Covers every metadata field.
+pub fn finalize_evidence_hash(mut hasher: Sha256, metadata: &EvidenceMetadata) -> String {
+    hasher.update(encode_evidence_metadata(metadata));
     hex::encode(hasher.finalize())
 }
 
-pub fn generate_relationship_key_share() -> Vec<u8> {
-    // In a real implementation, this would use proper threshold cryptography
-    // For MVP, we generate a random 32-byte key share
-    let mut key_share = vec![0u8; 32];
-    getrandom::getrandom(&mut key_share).expect("Failed to generate random key share");
-    key_share
+/// Canonical encoding of evidence metadata for hashing and signing:
+/// big-endian timestamp, then length-prefixed strings, optional strings
+/// behind a presence byte, and the tag count before the tags
+pub fn encode_evidence_metadata(metadata: &EvidenceMetadata) -> Vec<u8> {
+    fn push_field(bytes: &mut Vec<u8>, field: &str) {
+        bytes.extend_from_slice(&(field.len() as u64).to_be_bytes());
+        bytes.extend_from_slice(field.as_bytes());
+    }
+
+    fn push_optional_field(bytes: &mut Vec<u8>, field: Option<&str>) {
+        match field {
+            Some(value) => {
+                bytes.push(1);
+                push_field(bytes, value);
+            }
+            None => bytes.push(0),
+        }
+    }
+
+    let mut bytes = metadata.timestamp.to_be_bytes().to_vec();
+    push_field(&mut bytes, &metadata.content_type);
+    push_optional_field(&mut bytes, metadata.location.as_deref());
+    push_optional_field(&mut bytes, metadata.description.as_deref());
+    bytes.extend_from_slice(&(metadata.tags.len() as u64).to_be_bytes());
+    for tag in &metadata.tags {
+        push_field(&mut bytes, tag);
+    }
+    bytes
 }
 
 pub fn caller_principal() -> Principal {
@@ -57,77 +147,91 @@ pub fn current_time() -> u64 {
 }
 
 pub fn log_audit_event(user: Principal, action: &str, metadata: Option<String>) {
-    let _entry = AuditLogEntry {
-        id: generate_id("audit", current_time()),
-        user,
-        action: action.to_string(),
-        timestamp: current_time(),
-        metadata,
-    };
-    // In a full implementation, this would store to stable memory
-    // For MVP, we just log for debugging
-    ic_cdk::println!("AUDIT: {} performed {} at {}", user, action, current_time());
+    let entry = audit::append_audit_entry(user, None, action, metadata);
+    ic_cdk::println!("AUDIT #{}: {} performed {} at {}", entry.sequence, user, action, entry.timestamp);
+}
+
+/// Audit event that both partners can see in the relationship-scoped view
+pub fn log_relationship_audit_event(user: Principal, relationship_id: &str, action: &str, metadata: Option<String>) {
+    let entry = audit::append_audit_entry(user, Some(relationship_id), action, metadata);
+    ic_cdk::println!("AUDIT #{}: {} performed {} on {} at {}", entry.sequence, user, action, relationship_id, entry.timestamp);
 }
 
 // =============================
 // REAL THRESHOLD CRYPTOGRAPHY
 // =============================
-// Production-ready threshold cryptography using Shamir's Secret Sharing
+// 2-of-3 threshold cryptography using Shamir's Secret Sharing
 
 use ed25519_dalek::{SigningKey, VerifyingKey, Signature, Signer, Verifier};
 use hkdf::Hkdf;
 
-/// Generate a cryptographically secure master key for the relationship
-pub fn generate_master_key() -> Vec<u8> {
+/// Generate a cryptographically secure master key for the relationship.
+/// Fails if the entropy pool has not been seeded from `raw_rand` yet.
+pub fn generate_master_key() -> Result<Vec<u8>, String> {
     // Generate a proper Ed25519 private key (32 bytes)
     let mut secret_bytes = [0u8; 32];
-    getrandom::getrandom(&mut secret_bytes).expect("Failed to generate secure random key");
-    secret_bytes.to_vec()
+    entropy::fill_random(&mut secret_bytes)?;
+    Ok(secret_bytes.to_vec())
 }
 
-/// Split master key using XOR-based threshold approach (simplified for MVP)
-/// Returns (user1_share, user2_share, bonded_recovery_share)
-/// This is a simplified 2-of-3 threshold: user1_share XOR user2_share = master_key
-/// bonded_share = user1_share XOR master_key (so bonded + user1 = master_key)
-pub fn split_key_threshold(master_key: &[u8]) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), String> {
+/// Shamir x-coordinates for the three relationship shares
+pub const PARTNER1_SHARE_INDEX: u8 = 1;
+pub const PARTNER2_SHARE_INDEX: u8 = 2;
+pub const BONDED_SHARE_INDEX: u8 = 3;
+
+/// The three encoded shares of a relationship master key.
+/// Each share is `[index || 32 bytes]`, see `shamir::KeyShare`.
+pub struct ThresholdShares {
+    pub partner1: Vec<u8>,
+    pub partner2: Vec<u8>,
+    pub bonded: Vec<u8>,
+}
+
+/// Split master key into a real 2-of-3 Shamir sharing over GF(256).
+/// Any two of partner1, partner2 and Bonded reconstruct the key; a single
+/// share is uniformly random and reveals nothing about it.
+pub fn split_key_threshold(master_key: &[u8]) -> Result<ThresholdShares, String> {
     if master_key.len() != 32 {
         return Err("Master key must be exactly 32 bytes".to_string());
     }
 
-    // Generate a random user1_share
-    let mut user1_share = vec![0u8; 32];
-    getrandom::getrandom(&mut user1_share).map_err(|e| format!("Failed to generate random share: {}", e))?;
-    
-    // user2_share = master_key XOR user1_share
-    // This means: user1_share XOR user2_share = master_key
-    let mut user2_share = vec![0u8; 32];
-    for i in 0..32 {
-        user2_share[i] = master_key[i] ^ user1_share[i];
-    }
-    
-    // bonded_share = user1_share XOR master_key  
-    // This means: user1_share XOR bonded_share = master_key
-    let mut bonded_share = vec![0u8; 32];
-    for i in 0..32 {
-        bonded_share[i] = user1_share[i] ^ master_key[i];
+    let mut shares = shamir::split_secret(master_key, 2, 3)?.into_iter();
+    let mut next_share = |expected_index: u8| -> Result<Vec<u8>, String> {
+        match shares.next() {
+            Some(share) if share.index == expected_index => Ok(share.to_bytes()),
+            _ => Err("Unexpected share layout".to_string()),
+        }
+    };
+
+    let threshold_shares = ThresholdShares {
+        partner1: next_share(PARTNER1_SHARE_INDEX)?,
+        partner2: next_share(PARTNER2_SHARE_INDEX)?,
+        bonded: next_share(BONDED_SHARE_INDEX)?,
+    };
+
+    // Refuse to hand out shares that do not round-trip
+    if reconstruct_key_from_shares(&threshold_shares.partner2, &threshold_shares.bonded)? != master_key {
+        return Err("Key share verification failed".to_string());
     }
 
-    Ok((user1_share, user2_share, bonded_share))
+    Ok(threshold_shares)
 }
 
-/// Reconstruct master key from any 2 of the 3 shares
+/// Reconstruct master key from any 2 of the 3 indexed shares
 pub fn reconstruct_key_from_shares(share1: &[u8], share2: &[u8]) -> Result<Vec<u8>, String> {
-    if share1.len() != 32 || share2.len() != 32 {
-        return Err("Both shares must be exactly 32 bytes".to_string());
-    }
-    
-    // XOR the two shares to get the master key
-    let mut master_key = vec![0u8; 32];
-    for i in 0..32 {
-        master_key[i] = share1[i] ^ share2[i];
+    let share1 = shamir::KeyShare::from_bytes(share1)?;
+    let share2 = shamir::KeyShare::from_bytes(share2)?;
+
+    for share in [&share1, &share2] {
+        if share.value.len() != 32 {
+            return Err("Both shares must carry exactly 32 bytes".to_string());
+        }
+        if share.index > BONDED_SHARE_INDEX {
+            return Err("Unknown share index".to_string());
+        }
     }
 
-    Ok(master_key)
+    shamir::reconstruct_secret(&[share1, share2])
 }
 
 /// Derive Ed25519 public key from master private key
@@ -146,6 +250,7 @@ pub fn derive_public_key(master_key: &[u8]) -> Result<Vec<u8>, String> {
 }
 
 /// Derive AES encryption key from master key using HKDF
+#[allow(dead_code)]
 pub fn derive_encryption_key(master_key: &[u8], relationship_id: &str) -> Result<Vec<u8>, String> {
     if master_key.len() != 32 {
         return Err("Master key must be exactly 32 bytes".to_string());
@@ -161,6 +266,7 @@ pub fn derive_encryption_key(master_key: &[u8], relationship_id: &str) -> Result
 }
 
 /// Sign data with master key (for evidence authenticity)
+#[allow(dead_code)]
 pub fn sign_data(master_key: &[u8], data: &[u8]) -> Result<Vec<u8>, String> {
     if master_key.len() != 32 {
         return Err("Master key must be exactly 32 bytes".to_string());
@@ -192,7 +298,10 @@ pub fn verify_signature(public_key: &[u8], data: &[u8], signature: &[u8]) -> Res
 }
 
 /// Store a user's key share securely
-pub fn store_user_key_share(user: Principal, relationship_id: &str, key_share: Vec<u8>) -> Result<(), String> {
+/// The current share lives under `<relationship>_<principal>`; shares of a
+/// key version that is being rotated out are archived under
+/// `<relationship>_<principal>_v<version>` until the rotation completes.
+pub fn store_user_key_share(user: Principal, relationship_id: &str, key_share: Vec<u8>, key_version: u32) -> Result<(), String> {
     let key_id = format!("{}_{}", relationship_id, user.to_text());
     
     let user_key_share = UserKeyShare {
@@ -201,15 +310,95 @@ pub fn store_user_key_share(user: Principal, relationship_id: &str, key_share: V
         relationship_id: relationship_id.to_string(),
         key_share,
         created_at: current_time(),
+        key_version,
     };
     
     with_key_share_store(|store| {
+        // Keep the share being replaced readable under its version
+        if let Some(mut previous) = store.get(&key_id) {
+            if previous.key_version != key_version {
+                previous.key_id = format!("{}_v{}", key_id, previous.key_version);
+                store.insert(previous.key_id.clone(), previous);
+            }
+        }
         store.insert(key_id, user_key_share);
     });
     
     Ok(())
 }
 
+/// Retrieve a user's share of a specific relationship key version, current or archived
+pub fn get_user_key_share_version(user: Principal, relationship_id: &str, key_version: u32) -> Option<UserKeyShare> {
+    let key_id = format!("{}_{}", relationship_id, user.to_text());
+    
+    with_key_share_store_read(|store| {
+        match store.get(&key_id) {
+            Some(share) if share.key_version == key_version => Some(share),
+            _ => store.get(&format!("{}_v{}", key_id, key_version)),
+        }
+    })
+}
+
+/// Remove a user's archived shares of the given key version
+pub fn remove_archived_key_share(user: Principal, relationship_id: &str, key_version: u32) {
+    let key_id = format!("{}_{}_v{}", relationship_id, user.to_text(), key_version);
+    with_key_share_store(|store| {
+        store.remove(&key_id);
+    });
+}
+
+/// Remove every share, current and archived, a user holds for a relationship
+pub fn remove_user_key_shares(user: Principal, relationship_id: &str) {
+    let key_id = format!("{}_{}", relationship_id, user.to_text());
+    let archived_prefix = format!("{}_v", key_id);
+    
+    with_key_share_store(|store| {
+        let archived: Vec<String> = store
+            .range(archived_prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&archived_prefix))
+            .collect();
+        for key in archived {
+            store.remove(&key);
+        }
+        store.remove(&key_id);
+    });
+}
+
+pub(crate) fn bonded_key_share_id(relationship_id: &str, key_version: u32) -> String {
+    format!("{}:{:010}", relationship_id, key_version)
+}
+
+/// Store Bonded's share of a threshold relationship key version
+pub fn store_bonded_key_share(relationship_id: &str, key_version: u32, key_share: Vec<u8>) {
+    with_bonded_key_share_store(|store| {
+        store.insert(bonded_key_share_id(relationship_id, key_version), key_share);
+    });
+}
+
+/// Remove Bonded's share of a relationship key version
+pub fn remove_bonded_key_share(relationship_id: &str, key_version: u32) {
+    with_bonded_key_share_store(|store| {
+        store.remove(&bonded_key_share_id(relationship_id, key_version));
+    });
+}
+
+/// Remove Bonded's shares of every key version of a relationship
+pub fn remove_bonded_key_shares(relationship_id: &str) {
+    let prefix = format!("{}:", relationship_id);
+
+    with_bonded_key_share_store(|store| {
+        let versions: Vec<String> = store
+            .range(prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&prefix))
+            .collect();
+        for key in versions {
+            store.remove(&key);
+        }
+    });
+}
+
 /// Retrieve a user's key share for a relationship
 pub fn get_user_key_share(user: Principal, relationship_id: &str) -> Option<Vec<u8>> {
     let key_id = format!("{}_{}", relationship_id, user.to_text());
@@ -219,39 +408,72 @@ pub fn get_user_key_share(user: Principal, relationship_id: &str) -> Option<Vec<
     })
 }
 
-pub fn verify_relationship_access(relationship: &Relationship, caller: Principal) -> Result<(), String> {
+pub fn verify_relationship_access(relationship: &Relationship, caller: Principal) -> Result<(), BondedError> {
     if relationship.partner1 != caller && relationship.partner2 != Some(caller) {
-        return Err("Not authorized to access this relationship".to_string());
+        return Err(BondedError::unauthorized("Not authorized to access this relationship"));
+    }
+    // Destroyed relationships behave as if their data never existed
+    if matches!(relationship.status, RelationshipStatus::Destroyed) {
+        return Err(BondedError::not_found("Relationship data"));
+    }
+    match relationship.status {
+        // partner2 only gets access by accepting the request
+        RelationshipStatus::Pending if relationship.partner1 != caller => {
+            Err(BondedError::unauthorized("Relationship request has not been accepted"))
+        }
+        RelationshipStatus::Declined => Err(BondedError::unauthorized("Relationship request was declined")),
+        RelationshipStatus::Expired => Err(BondedError::expired("Relationship request")),
+        _ => Ok(()),
+    }
+}
+
+/// Like `verify_relationship_access`, but partners keep read-only access to
+/// a destroyed relationship's record and audit trail, which outlive its data
+pub fn verify_relationship_read_access(relationship: &Relationship, caller: Principal) -> Result<(), BondedError> {
+    if matches!(relationship.status, RelationshipStatus::Destroyed) && is_relationship_partner(relationship, caller) {
+        return Ok(());
+    }
+    verify_relationship_access(relationship, caller)
+}
+
+/// Operator-only endpoints (configuration) are restricted to canister controllers
+pub fn verify_controller(caller: Principal) -> Result<(), BondedError> {
+    if !ic_cdk::api::is_controller(&caller) {
+        return Err(BondedError::unauthorized("Only canister controllers can perform this operation"));
     }
     Ok(())
 }
 
-pub fn validate_evidence_metadata(metadata: &EvidenceMetadata) -> Result<(), String> {
+pub fn is_relationship_partner(relationship: &Relationship, user: Principal) -> bool {
+    relationship.partner1 == user || relationship.partner2 == Some(user)
+}
+
+pub fn validate_evidence_metadata(metadata: &EvidenceMetadata) -> Result<(), BondedError> {
     if metadata.content_type.is_empty() {
-        return Err("Content type is required".to_string());
+        return Err(BondedError::invalid_input("metadata.content_type", "Content type is required"));
     }
     
     if metadata.timestamp == 0 {
-        return Err("Timestamp is required".to_string());
+        return Err(BondedError::invalid_input("metadata.timestamp", "Timestamp is required"));
     }
     
     // Ensure timestamp is not in the future (with some tolerance)
     let now = current_time();
     if metadata.timestamp > now + 60_000_000_000 { // 1 minute tolerance in nanoseconds
-        return Err("Timestamp cannot be in the future".to_string());
+        return Err(BondedError::invalid_input("metadata.timestamp", "Timestamp cannot be in the future"));
     }
     
     Ok(())
 }
 
-pub fn validate_encrypted_data(data: &[u8]) -> Result<(), String> {
+pub fn validate_encrypted_data(data: &[u8]) -> Result<(), BondedError> {
     if data.is_empty() {
-        return Err("Encrypted data cannot be empty".to_string());
+        return Err(BondedError::invalid_input("encrypted_data", "Encrypted data cannot be empty"));
     }
     
     // Check for minimum size (IV + some data + tag)
     if data.len() < 32 {
-        return Err("Encrypted data appears to be too small".to_string());
+        return Err(BondedError::invalid_input("encrypted_data", "Encrypted data appears to be too small"));
     }
     
     Ok(())
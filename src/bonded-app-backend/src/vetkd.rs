@@ -0,0 +1,381 @@
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use crate::rate_limit::verify_update_allowed;
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call_with_payment128;
+use ic_cdk_macros::{query, update};
+use serde::Deserialize;
+
+// ==================
+// VETKD KEY DERIVATION
+// ==================
+// Relationship and partner keys are derived by the subnet through the vetKD
+// system API instead of being generated and held by this canister. A derived
+// key only ever leaves the subnet encrypted to the transport public key the
+// client supplied, so the canister never sees plaintext key material.
+//
+// Every relationship created since is a `RelationshipKeyScheme::VetKd` one:
+// its relationship key of version `v` is the vetKD key for the relationship
+// ID and `v`, so rotating just moves to the next version. Older relationships
+// keep their threshold shares and only derive partner keys here.
+//
+// The target canister is configurable: by default the management canister,
+// but any canister exposing the same `vetkd_public_key`/`vetkd_derive_key`
+// interface (e.g. `vetkd-stand-in` on a local replica) can be configured instead.
+
+const DEFAULT_VETKD_KEY_NAME: &str = "key_1";
+const DEFAULT_DERIVE_KEY_CYCLES: u64 = 26_153_846_153;
+
+/// Compressed G1 point, as produced by the vetKD client libraries
+const TRANSPORT_PUBLIC_KEY_LENGTH: usize = 48;
+
+const RELATIONSHIP_KEY_CONTEXT: &[u8] = b"bonded:vetkd:relationship:v1";
+const PARTNER_KEY_CONTEXT: &[u8] = b"bonded:vetkd:partner:v1";
+
+// Management canister interface (subset)
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum VetKdCurve {
+    #[serde(rename = "bls12_381_g2")]
+    Bls12381G2,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdKeyId {
+    curve: VetKdCurve,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdPublicKeyArgs {
+    canister_id: Option<Principal>,
+    context: Vec<u8>,
+    key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdPublicKeyResult {
+    public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdDeriveKeyArgs {
+    input: Vec<u8>,
+    context: Vec<u8>,
+    transport_public_key: Vec<u8>,
+    key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKdDeriveKeyResult {
+    encrypted_key: Vec<u8>,
+}
+
+pub fn vetkd_config() -> VetKdConfig {
+    get_canister_config().vetkd.unwrap_or_else(|| VetKdConfig {
+        canister_id: Principal::management_canister(),
+        key_name: DEFAULT_VETKD_KEY_NAME.to_string(),
+        derive_key_cycles: DEFAULT_DERIVE_KEY_CYCLES,
+    })
+}
+
+fn key_id(config: &VetKdConfig) -> VetKdKeyId {
+    VetKdKeyId {
+        curve: VetKdCurve::Bls12381G2,
+        name: config.key_name.clone(),
+    }
+}
+
+fn scope_context(scope: &VetKdKeyScope) -> Vec<u8> {
+    match scope {
+        VetKdKeyScope::Relationship => RELATIONSHIP_KEY_CONTEXT.to_vec(),
+        VetKdKeyScope::Partner => PARTNER_KEY_CONTEXT.to_vec(),
+    }
+}
+
+/// Derivation input for a scope. Inputs are length-prefixed so that no
+/// (relationship, version) or (relationship, principal) pair can collide
+/// with another.
+pub fn scope_input(scope: &VetKdKeyScope, relationship_id: &str, partner: Principal, key_version: u32) -> Vec<u8> {
+    match scope {
+        VetKdKeyScope::Relationship => {
+            let mut input = Vec::new();
+            input.extend_from_slice(&(relationship_id.len() as u64).to_be_bytes());
+            input.extend_from_slice(relationship_id.as_bytes());
+            input.extend_from_slice(&key_version.to_be_bytes());
+            input
+        }
+        VetKdKeyScope::Partner => {
+            let mut input = Vec::new();
+            input.extend_from_slice(&(relationship_id.len() as u64).to_be_bytes());
+            input.extend_from_slice(relationship_id.as_bytes());
+            input.extend_from_slice(&(partner.as_slice().len() as u64).to_be_bytes());
+            input.extend_from_slice(partner.as_slice());
+            input
+        }
+    }
+}
+
+/// Relationship key versions a partner may derive: the current one, and the
+/// one being rotated out until it is retired
+fn derivable_key_version(relationship: &Relationship, requested: Option<u32>) -> Result<u32, BondedError> {
+    if relationship.key_scheme != RelationshipKeyScheme::VetKd {
+        return Err(BondedError::conflict("Relationship keys of this relationship are threshold shares; use get_key_share"));
+    }
+
+    let current = crate::key_rotation::current_key_version(relationship);
+    let key_version = requested.unwrap_or(current);
+    let rotating_out = relationship.key_rotation.as_ref().map(|rotation| rotation.from_version);
+    if key_version != current && Some(key_version) != rotating_out {
+        return Err(BondedError::invalid_input("key_version", "Key version is not current and not being rotated out"));
+    }
+    Ok(key_version)
+}
+
+async fn fetch_vetkd_public_key(context: Vec<u8>) -> Result<Vec<u8>, String> {
+    let config = vetkd_config();
+    let args = VetKdPublicKeyArgs {
+        canister_id: None,
+        context,
+        key_id: key_id(&config),
+    };
+
+    let (result,): (VetKdPublicKeyResult,) = ic_cdk::call(config.canister_id, "vetkd_public_key", (args,))
+        .await
+        .map_err(|(code, msg)| format!("vetkd_public_key failed ({:?}): {}", code, msg))?;
+
+    Ok(result.public_key)
+}
+
+/// The relationship context's vetKD public key, recorded with every vetKD
+/// relationship key version
+pub(crate) async fn relationship_public_key() -> Result<Vec<u8>, BondedError> {
+    fetch_vetkd_public_key(scope_context(&VetKdKeyScope::Relationship))
+        .await
+        .map_err(BondedError::unavailable)
+}
+
+async fn fetch_vetkd_encrypted_key(
+    context: Vec<u8>,
+    input: Vec<u8>,
+    transport_public_key: Vec<u8>,
+) -> Result<Vec<u8>, String> {
+    let config = vetkd_config();
+    let args = VetKdDeriveKeyArgs {
+        input,
+        context,
+        transport_public_key,
+        key_id: key_id(&config),
+    };
+
+    let (result,): (VetKdDeriveKeyResult,) = call_with_payment128(
+        config.canister_id,
+        "vetkd_derive_key",
+        (args,),
+        config.derive_key_cycles as u128,
+    )
+    .await
+    .map_err(|(code, msg)| format!("vetkd_derive_key failed ({:?}): {}", code, msg))?;
+
+    Ok(result.encrypted_key)
+}
+
+// ==================
+// VETKD ENDPOINTS
+// ==================
+
+/// Public key for a derivation scope; clients combine it with the scope's
+/// input to verify and decrypt keys returned by `derive_vetkd_key`.
+#[update]
+pub async fn get_vetkd_public_key(scope: VetKdKeyScope) -> BondedResult<Vec<u8>> {
+    if let Err(msg) = verify_update_allowed(caller_principal(), "get_vetkd_public_key") {
+        return BondedResult::err(msg);
+    }
+    
+    match fetch_vetkd_public_key(scope_context(&scope)).await {
+        Ok(public_key) => BondedResult::ok(public_key),
+        Err(e) => BondedResult::err(BondedError::unavailable(e)),
+    }
+}
+
+/// Derive the caller's key for `scope`, encrypted to `transport_public_key`.
+/// `key_version` selects a relationship key version (default: current).
+#[update]
+pub async fn derive_vetkd_key(
+    relationship_id: String,
+    scope: VetKdKeyScope,
+    key_version: Option<u32>,
+    transport_public_key: Vec<u8>,
+) -> BondedResult<VetKdEncryptedKey> {
+    let caller = caller_principal();
+    
+    if let Err(msg) = verify_update_allowed(caller, "derive_vetkd_key") {
+        return BondedResult::err(msg);
+    }
+
+    let relationship = match with_relationship_store_read(|store| store.get(&relationship_id)) {
+        Some(rel) => rel,
+        None => return BondedResult::err(BondedError::not_found("Relationship")),
+    };
+
+    if let Err(msg) = verify_relationship_access(&relationship, caller) {
+        return BondedResult::err(msg);
+    }
+
+    if transport_public_key.len() != TRANSPORT_PUBLIC_KEY_LENGTH {
+        return BondedResult::err(BondedError::invalid_input("transport_public_key", "Transport public key must be a 48-byte compressed G1 point"));
+    }
+
+    let key_version = match scope {
+        VetKdKeyScope::Relationship => match derivable_key_version(&relationship, key_version) {
+            Ok(key_version) => Some(key_version),
+            Err(msg) => return BondedResult::err(msg),
+        },
+        VetKdKeyScope::Partner => None,
+    };
+
+    let context = scope_context(&scope);
+    let input = scope_input(&scope, &relationship_id, caller, key_version.unwrap_or_default());
+
+    let encrypted_key = match fetch_vetkd_encrypted_key(context.clone(), input.clone(), transport_public_key).await {
+        Ok(encrypted_key) => encrypted_key,
+        Err(e) => return BondedResult::err(BondedError::unavailable(e)),
+    };
+
+    log_relationship_audit_event(
+        caller,
+        &relationship_id,
+        "derive_vetkd_key",
+        Some(format!("scope:{:?}", scope)),
+    );
+
+    BondedResult::ok(VetKdEncryptedKey {
+        scope,
+        relationship_id,
+        key_version,
+        context,
+        input,
+        encrypted_key,
+    })
+}
+
+// ==================
+// VETKD CONFIGURATION
+// ==================
+
+#[query]
+pub fn get_vetkd_config() -> VetKdConfig {
+    vetkd_config()
+}
+
+/// Point key derivation at a different key or at a local stand-in canister
+#[update]
+pub fn set_vetkd_config(config: VetKdConfig) -> BondedResult<VetKdConfig> {
+    let caller = caller_principal();
+
+    if let Err(msg) = verify_controller(caller) {
+        return BondedResult::err(msg);
+    }
+
+    if config.key_name.trim().is_empty() {
+        return BondedResult::err(BondedError::invalid_input("key_name", "vetKD key name is required"));
+    }
+
+    update_canister_config(|canister_config| {
+        canister_config.vetkd = Some(config.clone());
+    });
+
+    log_audit_event(caller, "set_vetkd_config", Some(format!("canister:{} key:{}", config.canister_id, config.key_name)));
+
+    BondedResult::ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::{Decode, Encode};
+
+    const BACKEND: &[u8] = &[0, 0, 0, 0, 0, 0, 0, 1, 1, 1];
+
+    fn stand_in_key_id() -> VetKdKeyId {
+        VetKdKeyId {
+            curve: VetKdCurve::Bls12381G2,
+            name: DEFAULT_VETKD_KEY_NAME.to_string(),
+        }
+    }
+
+    /// `vetkd_public_key` as the stand-in answers the backend, through candid
+    fn stand_in_public_key(context: Vec<u8>) -> Vec<u8> {
+        let args = VetKdPublicKeyArgs {
+            canister_id: None,
+            context,
+            key_id: stand_in_key_id(),
+        };
+        let args = Decode!(&Encode!(&args).unwrap(), vetkd_stand_in::VetKdPublicKeyArgs).unwrap();
+        let result = vetkd_stand_in::public_key(Principal::from_slice(BACKEND), args);
+        Decode!(&Encode!(&result).unwrap(), VetKdPublicKeyResult).unwrap().public_key
+    }
+
+    /// `vetkd_derive_key` as the stand-in answers the backend, through candid
+    fn stand_in_derive_key(context: Vec<u8>, input: Vec<u8>, transport_public_key: Vec<u8>) -> Vec<u8> {
+        let args = VetKdDeriveKeyArgs {
+            input,
+            context,
+            transport_public_key,
+            key_id: stand_in_key_id(),
+        };
+        let args = Decode!(&Encode!(&args).unwrap(), vetkd_stand_in::VetKdDeriveKeyArgs).unwrap();
+        let result = vetkd_stand_in::derive_key(Principal::from_slice(BACKEND), args).unwrap();
+        Decode!(&Encode!(&result).unwrap(), VetKdDeriveKeyResult).unwrap().encrypted_key
+    }
+
+    #[test]
+    fn partners_derive_the_same_relationship_key_from_the_stand_in() {
+        let partner1 = Principal::from_slice(&[1; 29]);
+        let partner2 = Principal::from_slice(&[2; 29]);
+        let (secret1, transport1) = vetkd_stand_in::transport_key_pair(b"partner1 device");
+        let (secret2, transport2) = vetkd_stand_in::transport_key_pair(b"partner2 device");
+        assert_eq!(transport1.len(), TRANSPORT_PUBLIC_KEY_LENGTH);
+
+        let scope = VetKdKeyScope::Relationship;
+        let context = scope_context(&scope);
+        let public_key = stand_in_public_key(context.clone());
+        let derive = |partner: Principal, version: u32, transport: &[u8], secret| {
+            let input = scope_input(&scope, "relationship_1", partner, version);
+            let encrypted_key = stand_in_derive_key(context.clone(), input.clone(), transport.to_vec());
+            vetkd_stand_in::decrypt_key(&encrypted_key, secret, &public_key, &input)
+        };
+
+        let key1 = derive(partner1, 1, &transport1, &secret1).unwrap();
+        let key2 = derive(partner2, 1, &transport2, &secret2).unwrap();
+        assert_eq!(key1, key2);
+
+        // Rotation moves to an unrelated key
+        assert_ne!(derive(partner1, 2, &transport1, &secret1).unwrap(), key1);
+
+        // Only the transport secret the key was encrypted to opens it
+        assert!(derive(partner1, 1, &transport1, &secret2).is_err());
+    }
+
+    #[test]
+    fn partner_keys_are_private_to_each_partner() {
+        let partner1 = Principal::from_slice(&[1; 29]);
+        let partner2 = Principal::from_slice(&[2; 29]);
+        let (secret, transport) = vetkd_stand_in::transport_key_pair(b"device");
+
+        let scope = VetKdKeyScope::Partner;
+        let context = scope_context(&scope);
+        let public_key = stand_in_public_key(context.clone());
+        let derive = |partner: Principal| {
+            let input = scope_input(&scope, "relationship_1", partner, 0);
+            let encrypted_key = stand_in_derive_key(context.clone(), input.clone(), transport.clone());
+            vetkd_stand_in::decrypt_key(&encrypted_key, &secret, &public_key, &input).unwrap()
+        };
+
+        assert_ne!(derive(partner1), derive(partner2));
+
+        // The relationship context has its own public key
+        assert_ne!(stand_in_public_key(scope_context(&VetKdKeyScope::Relationship)), public_key);
+    }
+}
@@ -0,0 +1,183 @@
+use bls12_381::hash_to_curve::{ExpandMsgXmd, HashToCurve};
+use bls12_381::{pairing, G1Affine, G1Projective, G2Affine, G2Projective, Scalar};
+use candid::{CandidType, Principal};
+use ic_cdk_macros::update;
+use serde::Deserialize;
+use sha2::{Digest, Sha512};
+
+// ==================
+// LOCAL VETKD STAND-IN
+// ==================
+// Answers `vetkd_public_key` and `vetkd_derive_key` the way the management
+// canister does, for local replicas and tests without a vetKD key. It follows
+// the vetKD construction on BLS12-381: a context key offsets the master key,
+// the derived key is a BLS signature over the input, and it is returned
+// ElGamal-encrypted to the caller's transport public key.
+//
+// The master key is a hash of the key name, so anyone can recompute every key
+// this canister hands out. Point the backend at it with `set_vetkd_config`
+// on local deployments only.
+
+/// Domain separator for hashing derivation inputs to G1
+const INPUT_DST: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_AUG_";
+
+const G1_LENGTH: usize = 48;
+const G2_LENGTH: usize = 96;
+
+// Management canister interface (subset)
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub enum VetKdCurve {
+    #[serde(rename = "bls12_381_g2")]
+    Bls12381G2,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VetKdKeyId {
+    pub curve: VetKdCurve,
+    pub name: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VetKdPublicKeyArgs {
+    pub canister_id: Option<Principal>,
+    pub context: Vec<u8>,
+    pub key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VetKdPublicKeyResult {
+    pub public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VetKdDeriveKeyArgs {
+    pub input: Vec<u8>,
+    pub context: Vec<u8>,
+    pub transport_public_key: Vec<u8>,
+    pub key_id: VetKdKeyId,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct VetKdDeriveKeyResult {
+    pub encrypted_key: Vec<u8>,
+}
+
+/// Length-prefixed hash of `parts` onto a scalar, separated by `domain`
+fn hash_to_scalar(domain: &[u8], parts: &[&[u8]]) -> Scalar {
+    let mut hasher = Sha512::new();
+    for part in std::iter::once(domain).chain(parts.iter().copied()) {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    Scalar::from_bytes_wide(&hasher.finalize().into())
+}
+
+/// Secret key of `canister_id`'s derivation context
+fn context_secret(key_id: &VetKdKeyId, canister_id: Principal, context: &[u8]) -> Scalar {
+    let master = hash_to_scalar(b"vetkd-stand-in:master", &[key_id.name.as_bytes()]);
+    master + hash_to_scalar(b"vetkd-stand-in:context", &[canister_id.as_slice(), context])
+}
+
+fn context_public_key(secret: &Scalar) -> [u8; G2_LENGTH] {
+    G2Affine::from(G2Projective::generator() * secret).to_compressed()
+}
+
+fn input_point(public_key: &[u8], input: &[u8]) -> G1Affine {
+    let message = [public_key, input].concat();
+    G1Affine::from(<G1Projective as HashToCurve<ExpandMsgXmd<sha2_09::Sha256>>>::hash_to_curve(message, INPUT_DST))
+}
+
+fn g1_from_bytes(bytes: &[u8]) -> Option<G1Affine> {
+    let bytes: [u8; G1_LENGTH] = bytes.try_into().ok()?;
+    Option::from(G1Affine::from_compressed(&bytes))
+}
+
+fn g2_from_bytes(bytes: &[u8]) -> Option<G2Affine> {
+    let bytes: [u8; G2_LENGTH] = bytes.try_into().ok()?;
+    Option::from(G2Affine::from_compressed(&bytes))
+}
+
+/// `vetkd_public_key` for a call from `caller`
+pub fn public_key(caller: Principal, args: VetKdPublicKeyArgs) -> VetKdPublicKeyResult {
+    let canister_id = args.canister_id.unwrap_or(caller);
+    let secret = context_secret(&args.key_id, canister_id, &args.context);
+    VetKdPublicKeyResult {
+        public_key: context_public_key(&secret).to_vec(),
+    }
+}
+
+/// `vetkd_derive_key` for a call from `caller`: `c1 || c2 || c3`, with
+/// `c1 = g1^r`, `c2 = g2^r` and `c3 = tpk^r + key`
+pub fn derive_key(caller: Principal, args: VetKdDeriveKeyArgs) -> Result<VetKdDeriveKeyResult, String> {
+    let transport_public_key = g1_from_bytes(&args.transport_public_key)
+        .ok_or_else(|| "transport_public_key is not a compressed G1 point".to_string())?;
+
+    let secret = context_secret(&args.key_id, caller, &args.context);
+    let key = input_point(&context_public_key(&secret), &args.input) * secret;
+
+    // Deterministic ephemeral key: acceptable only because nothing here is secret
+    let ephemeral = hash_to_scalar(
+        b"vetkd-stand-in:ephemeral",
+        &[caller.as_slice(), &args.context, &args.input, &args.transport_public_key],
+    );
+    let c1 = G1Affine::from(G1Projective::generator() * ephemeral);
+    let c2 = G2Affine::from(G2Projective::generator() * ephemeral);
+    let c3 = G1Affine::from(transport_public_key * ephemeral + key);
+
+    Ok(VetKdDeriveKeyResult {
+        encrypted_key: [&c1.to_compressed()[..], &c2.to_compressed()[..], &c3.to_compressed()[..]].concat(),
+    })
+}
+
+/// Client side of a derivation: decrypt `encrypted_key` with the transport
+/// secret and check it is the key for `input` under the context's
+/// `public_key`. Returns the compressed derived key.
+pub fn decrypt_key(encrypted_key: &[u8], transport_secret: &Scalar, public_key: &[u8], input: &[u8]) -> Result<Vec<u8>, String> {
+    if encrypted_key.len() != 2 * G1_LENGTH + G2_LENGTH {
+        return Err("Encrypted key has the wrong length".to_string());
+    }
+    let (c1, rest) = encrypted_key.split_at(G1_LENGTH);
+    let (c2, c3) = rest.split_at(G2_LENGTH);
+    let invalid = || "Encrypted key is not well-formed".to_string();
+    let c1 = g1_from_bytes(c1).ok_or_else(invalid)?;
+    let c2 = g2_from_bytes(c2).ok_or_else(invalid)?;
+    let c3 = g1_from_bytes(c3).ok_or_else(invalid)?;
+    let public_key_point = g2_from_bytes(public_key).ok_or_else(|| "Public key is not a compressed G2 point".to_string())?;
+
+    if pairing(&c1, &G2Affine::generator()) != pairing(&G1Affine::generator(), &c2) {
+        return Err(invalid());
+    }
+
+    let key = G1Affine::from(G1Projective::from(c3) - c1 * transport_secret);
+    if pairing(&key, &G2Affine::generator()) != pairing(&input_point(public_key, input), &public_key_point) {
+        return Err("Derived key does not verify under the public key".to_string());
+    }
+
+    Ok(key.to_compressed().to_vec())
+}
+
+/// Transport key pair from `seed`, for tests: the secret and the
+/// compressed public key to send with `vetkd_derive_key`
+pub fn transport_key_pair(seed: &[u8]) -> (Scalar, Vec<u8>) {
+    let secret = hash_to_scalar(b"vetkd-stand-in:transport", &[seed]);
+    (secret, G1Affine::from(G1Projective::generator() * secret).to_compressed().to_vec())
+}
+
+// ==================
+// ENDPOINTS
+// ==================
+
+#[update]
+fn vetkd_public_key(args: VetKdPublicKeyArgs) -> VetKdPublicKeyResult {
+    public_key(ic_cdk::caller(), args)
+}
+
+#[update]
+fn vetkd_derive_key(args: VetKdDeriveKeyArgs) -> VetKdDeriveKeyResult {
+    derive_key(ic_cdk::caller(), args).unwrap_or_else(|e| ic_cdk::trap(&e))
+}
+
+// Native builds link into the backend's tests, which export their own
+#[cfg(target_arch = "wasm32")]
+ic_cdk::export_candid!();